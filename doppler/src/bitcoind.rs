@@ -1,17 +1,25 @@
 use crate::{
-    copy_file, get_absolute_path, run_command, ImageInfo, L1Node, NodeCommand, Options, NETWORK,
+    add_net_admin, copy_file, get_absolute_path, rpc_error_code, run_command, set_conf_overrides,
+    BitcoindRpc, BuildDetails, ExternalBitcoind, ImageInfo, L1Node, MinerTime, Network,
+    NodeCommand, Options, NETWORK, RPC_WALLET_ALREADY_LOADED, RPC_WALLET_ERROR,
 };
 use anyhow::{anyhow, Error, Result};
 use conf_parser::processer::{FileConf, Section};
 use docker_compose_types::{
     EnvFile, Healthcheck, HealthcheckTest, Networks, Ports, Service, Volumes,
 };
-use log::{error, info};
+use log::{debug, error, info, warn};
+use serde_json::Value;
 use std::{
     fs::{File, OpenOptions},
     str::from_utf8,
+    thread::{self, spawn},
+    time::Duration,
 };
 
+// SEND_COINS without an AMT
+pub const DEFAULT_SEND_COINS_AMT: i64 = 100000;
+
 #[derive(Default, Debug, Clone)]
 pub struct Bitcoind {
     pub conf: FileConf,
@@ -29,6 +37,102 @@ pub struct Bitcoind {
     // Only added when initially creating the docker compose
     pub public_p2p: Option<i64>,
     pub public_rpc: Option<i64>,
+    // set when running with --rest, replaces the bitcoin-cli calls
+    pub rpc: Option<BitcoindRpc>,
+    // declared in the external nodes file, there is no container behind it
+    pub external: bool,
+    // BITCOIND_MINER ... EVERY, mines in the background once the cluster is up
+    pub miner_time: Option<MinerTime>,
+}
+
+impl Bitcoind {
+    pub fn add_rpc_client(&mut self) -> Result<(), Error> {
+        let public_rpc = self
+            .public_rpc
+            .ok_or(anyhow!("no published rpc port for {}", self.container_name))?;
+        let url = format!("http://localhost:{}", public_rpc);
+        self.rpc = Some(BitcoindRpc::new(
+            &url,
+            &self.user,
+            &self.password,
+            Some(self.container_name.clone()),
+        )?);
+        Ok(())
+    }
+
+    /// Node level rpc call, goes over json-rpc when there is a client and bitcoin-cli in the container otherwise
+    pub fn call(&self, options: &Options, method: &str, params: &[Value]) -> Result<Value, Error> {
+        self.send_call(options, method, params, false)
+    }
+
+    /// Same as call, against the node's wallet
+    pub fn call_wallet(
+        &self,
+        options: &Options,
+        method: &str,
+        params: &[Value],
+    ) -> Result<Value, Error> {
+        self.send_call(options, method, params, true)
+    }
+
+    fn send_call(
+        &self,
+        options: &Options,
+        method: &str,
+        params: &[Value],
+        wallet: bool,
+    ) -> Result<Value, Error> {
+        if let Some(rpc) = self.get_rpc_client() {
+            let params = Value::Array(params.to_vec());
+            if wallet {
+                return rpc.call_wallet(method, params);
+            }
+            return rpc.call(method, params);
+        }
+        if self.external {
+            return Err(anyhow!("no rpc client for external bitcoind {}", self.name));
+        }
+        let compose_path = options.compose_path.clone().unwrap();
+        let datadir_flag = format!("--datadir={}", self.data_dir);
+        // the rpc credentials come from the bitcoin.conf in the datadir, on the command line they show up in ps
+        let rpc_port = format!("-rpcport={}", self.rpcport);
+        let rpc_wallet = format!("-rpcwallet={}", self.container_name);
+        // bitcoin-cli takes strings as they are and parses everything else as json
+        let args: Vec<String> = params
+            .iter()
+            .map(|param| match param {
+                Value::String(param) => param.to_owned(),
+                other => other.to_string(),
+            })
+            .collect();
+        let mut commands = vec![
+            "-f",
+            &compose_path,
+            "exec",
+            "--user",
+            "1000:1000",
+            &self.container_name,
+            "bitcoin-cli",
+            &datadir_flag,
+            &rpc_port,
+        ];
+        if wallet {
+            commands.push(&rpc_wallet);
+        }
+        commands.push(method);
+        commands.extend(args.iter().map(String::as_str));
+        let output = run_command(options, method.to_owned(), commands)?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "{} failed on {}: {}",
+                method,
+                self.name,
+                from_utf8(&output.stderr)?.trim()
+            ));
+        }
+        let stdout = from_utf8(&output.stdout)?.trim();
+        Ok(serde_json::from_str(stdout).unwrap_or_else(|_| Value::String(stdout.to_owned())))
+    }
 }
 
 pub enum L1Enum {
@@ -36,6 +140,30 @@ pub enum L1Enum {
 }
 
 impl L1Node for Bitcoind {
+    fn stop(&self, options: &Options) -> Result<(), Error> {
+        if self.external {
+            return Err(anyhow!(
+                "{} is an external bitcoind, doppler doesn't stop or start it",
+                self.name
+            ));
+        }
+        let container_name = self.get_container_name();
+        let compose_path = options.compose_path.as_ref().unwrap();
+        let commands = vec!["-f", &compose_path, "stop", &container_name];
+        run_command(options, String::from("stop"), commands).map(|_| ())
+    }
+    fn start(&self, options: &Options) -> Result<(), Error> {
+        if self.external {
+            return Err(anyhow!(
+                "{} is an external bitcoind, doppler doesn't stop or start it",
+                self.name
+            ));
+        }
+        let container_name = self.get_container_name();
+        let compose_path = options.compose_path.as_ref().unwrap();
+        let commands = vec!["-f", &compose_path, "start", &container_name];
+        run_command(options, String::from("start"), commands).map(|_| ())
+    }
     fn get_name(&self) -> String {
         self.name.clone()
     }
@@ -66,6 +194,9 @@ impl L1Node for Bitcoind {
     fn get_rpc_port(&self) -> String {
         self.rpcport.clone()
     }
+    fn get_rpc_client(&self) -> Option<&BitcoindRpc> {
+        self.rpc.as_ref()
+    }
     fn mine_bitcoin(&self, options: &Options, num_blocks: i64) -> Result<String, Error> {
         mine_bitcoin(self.clone(), options, num_blocks)
     }
@@ -90,8 +221,14 @@ impl L1Node for Bitcoind {
         let to = options.get_l2_by_name(&node_command.to)?;
         let address = to.create_on_chain_address(options)?;
 
-        //default to sending 100,000 sats
-        self.send_to_address(options, 1, node_command.amt.unwrap_or(100000), address)?;
+        // external bitcoinds are expected to have their own miner, blocks can still be mined with MINE_BLOCKS
+        let num_blocks = if self.external { 0 } else { 1 };
+        self.send_to_address(
+            options,
+            num_blocks,
+            node_command.amt.unwrap_or(DEFAULT_SEND_COINS_AMT),
+            address,
+        )?;
         Ok(())
     }
     fn send_to_address(
@@ -105,10 +242,15 @@ impl L1Node for Bitcoind {
     }
 }
 
-pub fn get_config(options: &mut Options, name: &str, is_miner: bool) -> Result<Bitcoind, Error> {
-    get_bitcoind_config(options, name, is_miner)
+pub fn get_config(
+    options: &mut Options,
+    name: &str,
+    is_miner: bool,
+    conf_overrides: &[String],
+) -> Result<Bitcoind, Error> {
+    get_bitcoind_config(options, name, is_miner, conf_overrides)
 }
-pub fn add_config(name: &str, network: &str, container_name: &str) -> Result<Bitcoind, Error> {
+pub fn add_config(name: &str, network: Network, container_name: &str) -> Result<Bitcoind, Error> {
     load_config(name, container_name, network)
 }
 
@@ -117,13 +259,21 @@ pub fn build_bitcoind(
     name: &str,
     image: &ImageInfo,
     is_miner: bool,
+    details: &BuildDetails,
 ) -> Result<()> {
-    let mut bitcoind_conf = get_config(options, name, is_miner).unwrap();
-    let public_p2p = options.new_port();
-    let public_rpc = options.new_port();
+    if details.miner_time.is_some() && !is_miner {
+        return Err(anyhow!(
+            "{} needs to be a BITCOIND_MINER to mine on a schedule",
+            name
+        ));
+    }
+    let mut bitcoind_conf = get_config(options, name, is_miner, &details.conf_overrides).unwrap();
+    let public_p2p = options.new_port(&bitcoind_conf.container_name, &bitcoind_conf.p2pport)?;
+    let public_rpc = options.new_port(&bitcoind_conf.container_name, &bitcoind_conf.rpcport)?;
 
-    let bitcoind = Service {
+    let mut bitcoind = Service {
         image: Some(image.get_image()),
+        build_: image.get_build_step(),
         container_name: Some(bitcoind_conf.container_name.clone()),
         ports: Ports::Short(vec![
             format!("{}:{}", public_p2p, bitcoind_conf.p2pport),
@@ -154,12 +304,19 @@ pub fn build_bitcoind(
         networks: Networks::Simple(vec![NETWORK.to_owned()]),
         ..Default::default()
     };
+    details.service_settings.apply(&mut bitcoind);
+    add_net_admin(options, &mut bitcoind);
     options
         .services
         .insert(bitcoind_conf.container_name.clone(), Some(bitcoind));
 
     bitcoind_conf.public_p2p = Some(public_p2p);
     bitcoind_conf.public_rpc = Some(public_rpc);
+    bitcoind_conf.miner_time = details.miner_time.clone();
+    // custom signet blocks are built and signed over rpc
+    if options.rest || options.custom_signet.is_some() {
+        bitcoind_conf.add_rpc_client()?;
+    }
     options.bitcoinds.push(bitcoind_conf);
     Ok(())
 }
@@ -167,20 +324,99 @@ pub fn build_bitcoind(
 pub fn add_bitcoinds(options: &mut Options) -> Result<()> {
     let bitcoinds: Vec<_> = options
         .services
-        .iter_mut()
+        .iter()
         .filter(|service| service.0.contains("bitcoind"))
         .map(|service| {
             let container_name = service.0;
             let bitcoind_name = container_name.split('-').last().unwrap();
-            load_config(bitcoind_name, container_name.as_str(), &options.network)
+            load_config(bitcoind_name, container_name.as_str(), options.network)
         })
         .filter_map(|res| res.ok())
+        .map(|mut bitcoind: Bitcoind| {
+            bitcoind.public_p2p =
+                options.get_assigned_port(&bitcoind.container_name, &bitcoind.p2pport);
+            bitcoind.public_rpc =
+                options.get_assigned_port(&bitcoind.container_name, &bitcoind.rpcport);
+            if options.rest || options.custom_signet.is_some() {
+                if let Err(e) = bitcoind.add_rpc_client() {
+                    error!("failed to add rpc client: {}", e);
+                }
+            }
+            bitcoind
+        })
         .collect();
     options.bitcoinds = bitcoinds;
     Ok(())
 }
 
-fn load_config(name: &str, container_name: &str, network: &str) -> Result<Bitcoind, Error> {
+pub fn add_external_bitcoinds(options: &mut Options) -> Result<(), Error> {
+    for external in options.external_bitcoinds.clone() {
+        if options
+            .bitcoinds
+            .iter()
+            .any(|bitcoind| bitcoind.name == external.name)
+        {
+            return Err(anyhow!(
+                "external bitcoind {} has the same name as a node in the local cluster",
+                external.name
+            ));
+        }
+        match load_external_config(&external) {
+            Ok(bitcoind) => options.bitcoinds.push(bitcoind),
+            Err(e) => error!("failed to load external bitcoind {}: {}", external.name, e),
+        }
+    }
+    Ok(())
+}
+
+fn load_external_config(external: &ExternalBitcoind) -> Result<Bitcoind, Error> {
+    let rpc = BitcoindRpc::new(
+        &external.rpc_url,
+        &external.user,
+        &external.password,
+        external.wallet.clone(),
+    )?;
+    let info = rpc.get_blockchain_info()?;
+    if !external.network.is_empty() && !chain_matches_network(&info.chain, &external.network) {
+        return Err(anyhow!(
+            "{} is on {}, but the external nodes file says {}",
+            external.name,
+            info.chain,
+            external.network
+        ));
+    }
+    if let Some(wallet) = external.wallet.as_ref() {
+        match rpc.load_wallet(wallet) {
+            Ok(_) => (),
+            Err(e) if rpc_error_code(&e) == Some(RPC_WALLET_ALREADY_LOADED) => (),
+            Err(e) => return Err(anyhow!("failed to load wallet {}: {}", wallet, e)),
+        }
+    }
+    info!(
+        "external bitcoind {} found on {} @ block {}",
+        external.name, info.chain, info.blocks
+    );
+    Ok(Bitcoind {
+        name: external.name.clone(),
+        container_name: external.name.clone(),
+        user: external.user.clone(),
+        password: external.password.clone(),
+        rpc: Some(rpc),
+        external: true,
+        ..Default::default()
+    })
+}
+
+// getblockchaininfo reports mainnet as "main" and testnet3 as "test"
+fn chain_matches_network(chain: &str, network: &str) -> bool {
+    match network {
+        "mainnet" | "bitcoin" => chain == "main",
+        "testnet" => chain == "test",
+        _ => chain == network,
+    }
+}
+
+fn load_config(name: &str, container_name: &str, network: Network) -> Result<Bitcoind, Error> {
     let bitcoind_config: &String = &format!("data/{}/.bitcoin/bitcoin.conf", name);
     let full_path = get_absolute_path(bitcoind_config)?
         .to_str()
@@ -210,6 +446,9 @@ fn load_config(name: &str, container_name: &str, network: &str) -> Result<Bitcoi
         path_vol: full_path,
         public_p2p: None,
         public_rpc: None,
+        rpc: None,
+        external: false,
+        miner_time: None,
         user: network_section.get_property("rpcuser"),
         password: network_section.get_property("rpcpassword"),
         p2pport: network_section.get_property("port"),
@@ -239,13 +478,16 @@ fn get_bitcoind_config(
     options: &mut Options,
     name: &str,
     is_miner: bool,
+    conf_overrides: &[String],
 ) -> Result<Bitcoind, Error> {
     let original = get_absolute_path(&format!("config/{}/bitcoin.conf", options.network))?;
     let source: File = File::open(original)?;
 
     let destination_dir: &String = &format!("data/{}/.bitcoin", name);
     let conf = conf_parser::processer::read_to_file_conf_mut(&source)?;
-    let network_section = set_network_section(conf, options)?;
+    set_network_section(conf, options)?;
+    set_conf_overrides(conf, conf_overrides, options.network.bitcoind_section())?;
+    let network_section = get_network_section(conf, options.network)?;
     let _ = copy_file(conf, destination_dir, "bitcoin.conf")?;
 
     let full_path = get_absolute_path(destination_dir)?
@@ -264,6 +506,9 @@ fn get_bitcoind_config(
         path_vol: full_path,
         public_p2p: None,
         public_rpc: None,
+        rpc: None,
+        external: false,
+        miner_time: None,
         user: network_section.get_property("rpcuser"),
         password: network_section.get_property("rpcpassword"),
         p2pport: network_section.get_property("port"),
@@ -290,35 +535,42 @@ fn get_bitcoind_config(
 }
 
 fn set_network_section(conf: &mut FileConf, options: &mut Options) -> Result<Section, Error> {
-    if !conf.sections.contains_key(&options.network) {
+    let section_name = options.network.bitcoind_section();
+    if !conf.sections.contains_key(section_name) {
         conf.sections
-            .insert(options.network.clone(), Section::new());
+            .insert(section_name.to_owned(), Section::new());
     }
-    let bitcoin = conf.sections.get_mut(&options.network).unwrap();
-    let port = match options.network.as_ref() {
-        "signet" => "38333",
-        _ => "18444",
-    };
-    let rpc_port = match options.network.as_ref() {
-        "signet" => "38332",
-        _ => "18443",
-    };
+    let bitcoin = conf.sections.get_mut(section_name).unwrap();
 
     bitcoin.set_property("bind", "0.0.0.0");
-    bitcoin.set_property("port", port);
-    bitcoin.set_property("rpcport", rpc_port);
+    bitcoin.set_property("port", options.network.bitcoind_p2p_port());
+    bitcoin.set_property("rpcport", options.network.bitcoind_rpc_port());
     bitcoin.set_property("rpcuser", "bitcoin");
     bitcoin.set_property("rpcpassword", "bitcoin");
     bitcoin.set_property("zmqpubrawblock", "tcp://0.0.0.0:28332");
     bitcoin.set_property("zmqpubrawtx", "tcp://0.0.0.0:28333");
     bitcoin.set_property("zmqpubhashtx", "tcp://0.0.0.0:28332");
     bitcoin.set_property("zmqpubhashblock", "tcp://0.0.0.0:28332");
-    let network_section = get_network_section(conf, &options.network)?;
+    if let Some(signet) = options.custom_signet.as_ref() {
+        // drop the template's mutinynet peer and block time, this chain only exists in the cluster
+        let mut custom = Section::new();
+        for (key, value) in bitcoin.clone().get_properties() {
+            if key != "addnode" && key != "signetblocktime" {
+                custom.set_property(&key, &value);
+            }
+        }
+        custom.set_property("signetchallenge", &signet.get_challenge());
+        *bitcoin = custom;
+    }
+    let network_section = get_network_section(conf, options.network)?;
     Ok(network_section)
 }
 
-fn get_network_section(conf: &mut FileConf, network: &str) -> Result<Section, Error> {
-    let network_section = conf.sections.get(network).expect("network section missing");
+fn get_network_section(conf: &mut FileConf, network: Network) -> Result<Section, Error> {
+    let network_section = conf
+        .sections
+        .get(network.bitcoind_section())
+        .expect("network section missing");
     Ok(network_section.to_owned())
 }
 
@@ -368,6 +620,19 @@ fn mine_bitcoin(node: impl L1Node, options: &Options, num_blocks: i64) -> Result
 }
 
 fn create_wallet(node: &dyn L1Node, options: &Options) -> Result<(), Error> {
+    if let Some(rpc) = node.get_rpc_client() {
+        return match rpc.create_wallet(&node.get_container_name()) {
+            Ok(wallet) => {
+                debug!("({}): created wallet {}", node.get_name(), wallet.name);
+                Ok(())
+            }
+            Err(e) if rpc_error_code(&e) == Some(RPC_WALLET_ERROR) => {
+                info!("wallet already created, will trying to load existing. If you want to start fresh for a new cluster/doppler script run `./scripts/reset.sh` @ the root of the repo");
+                node.load_wallet(options)
+            }
+            Err(e) => Err(e),
+        };
+    }
     let datadir_flag = &format!("--datadir={}", node.get_data_dir());
     let container_name = node.get_container_name();
     let compose_path = options.compose_path.as_ref().unwrap();
@@ -393,6 +658,20 @@ fn create_wallet(node: &dyn L1Node, options: &Options) -> Result<(), Error> {
 }
 
 fn load_wallet(node: &Bitcoind, options: &Options) -> Result<(), Error> {
+    if let Some(rpc) = node.get_rpc_client() {
+        match rpc.load_wallet(&node.container_name) {
+            Ok(_) => (),
+            Err(e) if rpc_error_code(&e) == Some(RPC_WALLET_ALREADY_LOADED) => {
+                info!(
+                    "wallet already loaded for {}, continuing: {}",
+                    node.get_name(),
+                    e
+                )
+            }
+            Err(e) => error!("failed to load wallet for {}: {}", node.get_name(), e),
+        }
+        return Ok(());
+    }
     let datadir_flag = &format!("--datadir={}", node.get_data_dir());
     let container_name = node.get_container_name();
     let compose_path = options.compose_path.as_ref().unwrap();
@@ -434,6 +713,11 @@ fn load_wallet(node: &Bitcoind, options: &Options) -> Result<(), Error> {
 }
 
 fn create_address(node: &Bitcoind, options: &Options) -> Result<String, Error> {
+    if let Some(rpc) = node.get_rpc_client() {
+        return rpc
+            .get_new_address()
+            .map_err(|e| anyhow!("failed to create new address: {}", e));
+    }
     let rpcwallet_flag = &format!("-rpcwallet={}", node.container_name);
     let datadir_flag = &format!("--datadir={}", node.data_dir);
     let compose_path = options.compose_path.clone().unwrap();
@@ -470,6 +754,16 @@ fn send_to_address(
     if amt == 0 {
         return Ok(());
     }
+    if let Some(rpc) = node.get_rpc_client() {
+        let txid = rpc
+            .send_to_address(&address, amt)
+            .map_err(|e| anyhow!("failed to send {} sats to {}: {}", amt, address, e))?;
+        info!("sent {} sats to {} in {}", amt, address, txid);
+        if num_blocks > 0 {
+            mine_bitcoin(node, options, num_blocks)?;
+        }
+        return Ok(());
+    }
     let datadir_flag = &format!("--datadir={}", node.get_data_dir());
     let container_name = node.get_container_name();
     let compose_path = options.compose_path.clone().unwrap();
@@ -487,13 +781,14 @@ fn send_to_address(
         &address,
         &amt_btc,
     ];
-    let output = run_command(options, "getnewaddress".to_owned(), commands)?;
+    let output = run_command(options, "sendtoaddress".to_owned(), commands)?;
     if !output.status.success() {
-        error!(
-            "failed to mine to address: {} {}",
-            from_utf8(&output.stdout).unwrap().to_owned(),
-            from_utf8(&output.stderr).unwrap().to_owned()
-        );
+        return Err(anyhow!(
+            "failed to send {} sats to {}: {}",
+            amt,
+            address,
+            from_utf8(&output.stderr)?.trim()
+        ));
     }
     mine_bitcoin(node, options, num_blocks)?;
     Ok(())
@@ -505,6 +800,23 @@ fn mine_to_address(
     num_blocks: i64,
     address: String,
 ) -> Result<(), Error> {
+    if let Some(signet) = options.custom_signet.as_ref() {
+        let rpc = node.get_rpc_client().ok_or(anyhow!(
+            "no rpc client for {}, needed to sign custom signet blocks",
+            node.get_name()
+        ))?;
+        for _ in 0..num_blocks {
+            signet
+                .mine_block(rpc, &address)
+                .map_err(|e| anyhow!("failed to mine to {}: {}", address, e))?;
+        }
+        return Ok(());
+    }
+    if let Some(rpc) = node.get_rpc_client() {
+        rpc.generate_to_address(num_blocks, &address)
+            .map_err(|e| anyhow!("failed to mine to {}: {}", address, e))?;
+        return Ok(());
+    }
     let datadir_flag = &format!("--datadir={}", node.get_data_dir());
     let block_arg = &num_blocks.to_string();
     let container_name = node.get_container_name();
@@ -523,13 +835,13 @@ fn mine_to_address(
         block_arg,
         &address,
     ];
-    let output = run_command(options, "getnewaddress".to_owned(), commands)?;
+    let output = run_command(options, "generatetoaddress".to_owned(), commands)?;
     if !output.status.success() {
-        error!(
-            "failed to mine to address: {} {}",
-            from_utf8(&output.stdout).unwrap().to_owned(),
-            from_utf8(&output.stderr).unwrap().to_owned()
-        );
+        return Err(anyhow!(
+            "failed to mine to {}: {}",
+            address,
+            from_utf8(&output.stderr)?.trim()
+        ));
     }
     Ok(())
 }
@@ -539,15 +851,19 @@ fn pair_node(
     current_node: &dyn L1Node,
     nodes: Vec<String>,
 ) -> Result<(), Error> {
+    if let Some(rpc) = current_node.get_rpc_client() {
+        for node in nodes.iter() {
+            rpc.add_node(node)?;
+        }
+        return Ok(());
+    }
     let compose_path = options.compose_path.clone().unwrap();
     let datadir_flag = &format!("--datadir={}", current_node.get_data_dir());
 
     for node in nodes.iter() {
         let container_name = current_node.get_container_name();
-        // -rpcport="3133" -rpcuser="alice" -rpcpassword="hello"
+        // the rpc credentials come from the bitcoin.conf in the datadir
         let rpc_port = format!("-rpcport={}", current_node.get_rpc_port());
-        let rpc_user = format!("-rpcuser={}", current_node.get_rpc_username());
-        let rpc_password = format!("-rpcpassword={}", current_node.get_rpc_password());
 
         let commands = vec![
             "-f",
@@ -559,8 +875,6 @@ fn pair_node(
             "bitcoin-cli",
             datadir_flag,
             &rpc_port,
-            &rpc_user,
-            &rpc_password,
             "addnode",
             node,
             r#"add"#,
@@ -570,3 +884,106 @@ fn pair_node(
 
     Ok(())
 }
+
+/// Starts a thread per BITCOIND_MINER defined with EVERY, each mining a block whenever its wait is over
+pub fn start_miner_schedules(options: &Options) {
+    if !options.can_mine() {
+        if options
+            .bitcoinds
+            .iter()
+            .any(|bitcoind| bitcoind.miner_time.is_some())
+        {
+            warn!(
+                "blocks can't be mined on {}, ignoring the miner schedules",
+                options.network
+            );
+        }
+        return;
+    }
+    for miner in options
+        .bitcoinds
+        .iter()
+        .filter(|bitcoind| !bitcoind.external)
+    {
+        let Some(miner_time) = miner.miner_time.clone() else {
+            continue;
+        };
+        info!(
+            "{} mining a block every {}{}{}",
+            miner.get_name(),
+            if miner_time.poisson { "~" } else { "" },
+            miner_time.miner_interval_amt,
+            miner_time.miner_interval_type
+        );
+        let miner = miner.clone();
+        let thread_options = options.clone();
+        spawn(move || {
+            thread_options.add_thread(thread::current());
+            let mut wait = miner_time.next_wait();
+            while thread_options.main_thread_active.val() {
+                // tick every second so a pause holds the schedule and shutdown isn't held up by long waits
+                let tick = wait.min(Duration::from_secs(1));
+                thread::sleep(tick);
+                if thread_options.main_thread_paused.val() {
+                    continue;
+                }
+                wait = wait.saturating_sub(tick);
+                if !wait.is_zero() {
+                    continue;
+                }
+                match miner.mine_bitcoin(&thread_options, 1) {
+                    Ok(_) => debug!("{} mined a scheduled block", miner.get_name()),
+                    Err(e) => error!("{} failed to mine a block: {}", miner.get_name(), e),
+                }
+                wait = miner_time.next_wait();
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ExternalBitcoind, Network};
+
+    #[test]
+    fn matches_the_chain_to_the_declared_network() {
+        assert!(chain_matches_network("main", "mainnet"));
+        assert!(chain_matches_network("main", "bitcoin"));
+        assert!(chain_matches_network("test", "testnet"));
+        assert!(chain_matches_network("signet", "signet"));
+        assert!(chain_matches_network("testnet4", "testnet4"));
+        assert!(!chain_matches_network("main", "signet"));
+        assert!(!chain_matches_network("testnet4", "testnet"));
+    }
+
+    #[test]
+    fn external_bitcoinds_are_only_reached_over_rpc() {
+        let mut options = Options::for_tests(Network::Regtest);
+        let external = Bitcoind {
+            name: "bd9".to_owned(),
+            external: true,
+            ..Default::default()
+        };
+        assert!(external.stop(&options).is_err());
+        assert!(external.start(&options).is_err());
+        let error = external.call(&options, "getblockcount", &[]).unwrap_err();
+        assert_eq!(error.to_string(), "no rpc client for external bitcoind bd9");
+
+        // the local cluster's names win, the external one is refused before it is called
+        options.bitcoinds = vec![Bitcoind {
+            name: "bd1".to_owned(),
+            ..Default::default()
+        }];
+        options.external_bitcoinds = vec![ExternalBitcoind {
+            name: "bd1".to_owned(),
+            rpc_url: "http://127.0.0.1:1".to_owned(),
+            user: String::new(),
+            password: String::new(),
+            wallet: None,
+            network: "regtest".to_owned(),
+        }];
+        assert!(add_external_bitcoinds(&mut options).is_err());
+        assert_eq!(options.bitcoinds.len(), 1);
+    }
+}
@@ -0,0 +1,282 @@
+use anyhow::{anyhow, Error, Result};
+use log::{debug, info};
+use reqwest::blocking::Client;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+// bitcoind rpc error codes we react to
+pub const RPC_WALLET_ERROR: i64 = -4;
+pub const RPC_WALLET_ALREADY_LOADED: i64 = -35;
+
+/// JSON-RPC client for a bitcoind node, used instead of exec'ing bitcoin-cli in the container
+#[derive(Debug, Clone)]
+pub struct BitcoindRpc {
+    url: String,
+    user: String,
+    password: String,
+    // wallet used for the wallet rpcs, ie. getnewaddress
+    wallet: Option<String>,
+    client: Client,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcResponse<T> {
+    result: Option<T>,
+    error: Option<RpcError>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl std::fmt::Display for RpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "rpc error {}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BlockchainInfo {
+    pub chain: String,
+    pub blocks: u64,
+    pub bestblockhash: String,
+    pub initialblockdownload: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BlockTemplate {
+    pub version: i32,
+    pub previousblockhash: String,
+    pub transactions: Vec<TemplateTransaction>,
+    pub coinbasevalue: u64,
+    pub default_witness_commitment: String,
+    pub curtime: u64,
+    pub mintime: u64,
+    pub bits: String,
+    pub height: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TemplateTransaction {
+    pub data: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AddressInfo {
+    #[serde(rename = "scriptPubKey")]
+    pub script_pub_key: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WalletTransaction {
+    pub confirmations: i64,
+}
+
+// createwallet and loadwallet answer with the wallet name, and a warning on older versions
+#[derive(Debug, Clone, Deserialize)]
+pub struct WalletResult {
+    pub name: String,
+}
+
+impl BitcoindRpc {
+    pub fn new(url: &str, user: &str, password: &str, wallet: Option<String>) -> Result<Self> {
+        let client = Client::builder().timeout(Duration::from_secs(60)).build()?;
+        Ok(Self {
+            url: url.trim_end_matches('/').to_owned(),
+            user: user.to_owned(),
+            password: password.to_owned(),
+            wallet,
+            client,
+        })
+    }
+
+    pub fn get_url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn call<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<T, Error> {
+        self.send(&self.url, method, params)
+    }
+
+    pub fn call_wallet<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> Result<T, Error> {
+        let url = match &self.wallet {
+            Some(wallet) => format!("{}/wallet/{}", self.url, wallet),
+            None => self.url.clone(),
+        };
+        self.send(&url, method, params)
+    }
+
+    fn send<T: DeserializeOwned>(
+        &self,
+        url: &str,
+        method: &str,
+        params: Value,
+    ) -> Result<T, Error> {
+        let body = json!({
+            "jsonrpc": "1.0",
+            "id": "doppler",
+            "method": method,
+            "params": params,
+        });
+        info!("({}): {} {}", method, url, params);
+        let response = self
+            .client
+            .post(url)
+            .basic_auth(&self.user, Some(&self.password))
+            .json(&body)
+            .send()?;
+        let status = response.status();
+        let text = response.text()?;
+        debug!("rpc response ({}): {}", status, text);
+        parse_response(method, status.as_u16(), &text)
+    }
+
+    pub fn get_blockchain_info(&self) -> Result<BlockchainInfo, Error> {
+        self.call("getblockchaininfo", json!([]))
+    }
+
+    pub fn get_block_count(&self) -> Result<u64, Error> {
+        self.call("getblockcount", json!([]))
+    }
+
+    pub fn create_wallet(&self, name: &str) -> Result<WalletResult, Error> {
+        self.call("createwallet", json!([name]))
+    }
+
+    pub fn load_wallet(&self, name: &str) -> Result<WalletResult, Error> {
+        self.call("loadwallet", json!([name]))
+    }
+
+    pub fn get_new_address(&self) -> Result<String, Error> {
+        self.call_wallet("getnewaddress", json!([]))
+    }
+
+    pub fn generate_to_address(
+        &self,
+        num_blocks: i64,
+        address: &str,
+    ) -> Result<Vec<String>, Error> {
+        self.call("generatetoaddress", json!([num_blocks, address]))
+    }
+
+    // amount in sats, bitcoind expects btc
+    pub fn send_to_address(&self, address: &str, amt: i64) -> Result<String, Error> {
+        let amt_btc = (amt as f64) / 100_000_000_f64;
+        self.call_wallet("sendtoaddress", json!([address, amt_btc]))
+    }
+
+    pub fn get_transaction_confirmations(&self, txid: &str) -> Result<i64, Error> {
+        let tx: WalletTransaction = self.call_wallet("gettransaction", json!([txid]))?;
+        Ok(tx.confirmations)
+    }
+
+    pub fn get_block_template(&self, request: Value) -> Result<BlockTemplate, Error> {
+        self.call("getblocktemplate", json!([request]))
+    }
+
+    pub fn get_address_info(&self, address: &str) -> Result<AddressInfo, Error> {
+        self.call_wallet("getaddressinfo", json!([address]))
+    }
+
+    pub fn add_node(&self, node: &str) -> Result<(), Error> {
+        let _: Option<Value> = self.call("addnode", json!([node, "add"]))?;
+        Ok(())
+    }
+}
+
+// bitcoind answers rpc errors with a 500 and the error in the body
+fn parse_response<T: DeserializeOwned>(method: &str, status: u16, text: &str) -> Result<T, Error> {
+    let parsed: RpcResponse<T> = serde_json::from_str(text).map_err(|e| {
+        anyhow!(
+            "invalid response for {} ({}): {} {}",
+            method,
+            status,
+            e,
+            text
+        )
+    })?;
+    if let Some(error) = parsed.error {
+        return Err(error.into());
+    }
+    match parsed.result {
+        Some(result) => Ok(result),
+        // some rpcs (ie. addnode) only ever return null
+        None => {
+            serde_json::from_value(Value::Null).map_err(|_| anyhow!("empty result for {}", method))
+        }
+    }
+}
+
+/// Returns the rpc error code when the error came back from bitcoind itself
+pub fn rpc_error_code(error: &Error) -> Option<i64> {
+    error.downcast_ref::<RpcError>().map(|error| error.code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_typed_results() {
+        let info: AddressInfo = parse_response(
+            "getaddressinfo",
+            200,
+            r#"{"result":{"address":"bcrt1q","scriptPubKey":"0014ab","ismine":true},"error":null,"id":"doppler"}"#,
+        )
+        .unwrap();
+        assert_eq!(info.script_pub_key, "0014ab");
+
+        let tx: WalletTransaction = parse_response(
+            "gettransaction",
+            200,
+            r#"{"result":{"txid":"ab","confirmations":3},"error":null,"id":"doppler"}"#,
+        )
+        .unwrap();
+        assert_eq!(tx.confirmations, 3);
+
+        let added: Option<Value> = parse_response(
+            "addnode",
+            200,
+            r#"{"result":null,"error":null,"id":"doppler"}"#,
+        )
+        .unwrap();
+        assert!(added.is_none());
+    }
+
+    #[test]
+    fn returns_rpc_errors() {
+        let error = parse_response::<WalletResult>(
+            "loadwallet",
+            500,
+            r#"{"result":null,"error":{"code":-35,"message":"Wallet already loaded"},"id":"doppler"}"#,
+        )
+        .unwrap_err();
+        assert_eq!(rpc_error_code(&error), Some(RPC_WALLET_ALREADY_LOADED));
+
+        // a result of the wrong shape fails to parse, it is not an rpc error
+        let error = parse_response::<u64>(
+            "getblockcount",
+            200,
+            r#"{"result":"ab","error":null,"id":"doppler"}"#,
+        )
+        .unwrap_err();
+        assert_eq!(rpc_error_code(&error), None);
+
+        // a null result can't stand in for a value the caller needs
+        assert!(parse_response::<String>(
+            "getnewaddress",
+            200,
+            r#"{"result":null,"error":null,"id":"doppler"}"#
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,250 @@
+use anyhow::{anyhow, Error};
+use indexmap::IndexMap;
+use log::{error, info};
+use std::sync::{Arc, Mutex};
+
+// amounts the node implementations fall back to when a command has no AMT
+const DEFAULT_LN_AMT: i64 = 1000;
+const DEFAULT_CHANNEL_AMT: i64 = 100000;
+const DEFAULT_ON_CHAIN_AMT: i64 = 1000;
+
+// the names bitcoind (getblockchaininfo.chain), lnd, coreln and eclair (getinfo) report for the test networks
+const TEST_NETWORKS: [&str; 7] = [
+    "test", "testnet", "testnet3", "testnet4", "signet", "regtest", "simnet",
+];
+
+/// Refuses a node that reports mainnet, or doesn't say which network it's on, unless --i-know-this-is-mainnet is set
+pub fn check_reported_network(
+    allow_mainnet: bool,
+    name: &str,
+    reported: Result<String, Error>,
+) -> Result<(), Error> {
+    if allow_mainnet {
+        return Ok(());
+    }
+    match reported {
+        Ok(network) if TEST_NETWORKS.contains(&network.trim().to_lowercase().as_str()) => Ok(()),
+        Ok(network) => Err(anyhow!(
+            "{} reports it is on {}, refusing to run against mainnet without --i-know-this-is-mainnet",
+            name,
+            network
+        )),
+        Err(e) => Err(anyhow!(
+            "{} didn't report its network ({}), treating it as mainnet without --i-know-this-is-mainnet",
+            name,
+            e
+        )),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpendKind {
+    Ln,
+    Channel,
+    OnChain,
+    // miner fees paid by FILL_MEMPOOL, RBF and CPFP
+    Fees,
+}
+
+impl SpendKind {
+    pub fn get_amount(&self, amt: Option<i64>) -> i64 {
+        amt.unwrap_or(match self {
+            SpendKind::Ln => DEFAULT_LN_AMT,
+            SpendKind::Channel => DEFAULT_CHANNEL_AMT,
+            SpendKind::OnChain | SpendKind::Fees => DEFAULT_ON_CHAIN_AMT,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NodeSpend {
+    pub ln: i64,
+    pub channels: i64,
+    pub on_chain: i64,
+    pub fees: i64,
+}
+
+impl NodeSpend {
+    pub fn total(&self) -> i64 {
+        self.ln + self.channels + self.on_chain + self.fees
+    }
+
+    fn add(&mut self, kind: SpendKind, amt: i64) {
+        match kind {
+            SpendKind::Ln => self.ln += amt,
+            SpendKind::Channel => self.channels += amt,
+            SpendKind::OnChain => self.on_chain += amt,
+            SpendKind::Fees => self.fees += amt,
+        }
+    }
+}
+
+/// Sats the nodes have been asked to spend during this run, checked against the
+/// --budget-sats/--node-budget-sats limits before every payment, channel open and on chain send
+#[derive(Debug, Clone, Default)]
+pub struct SpendBudget {
+    run_limit: Option<i64>,
+    node_limit: Option<i64>,
+    spent: Arc<Mutex<IndexMap<String, NodeSpend>>>,
+}
+
+impl SpendBudget {
+    pub fn new(run_limit: Option<i64>, node_limit: Option<i64>) -> Self {
+        SpendBudget {
+            run_limit,
+            node_limit,
+            spent: Arc::new(Mutex::new(IndexMap::new())),
+        }
+    }
+
+    /// Books the amount for the node, fails without booking anything when a limit would be exceeded
+    pub fn reserve(&self, node: &str, kind: SpendKind, amt: i64) -> Result<(), Error> {
+        let mut spent = self.spent.lock().unwrap();
+        if let Some(run_limit) = self.run_limit {
+            let run_total: i64 = spent.values().map(NodeSpend::total).sum();
+            if run_total + amt > run_limit {
+                return Err(anyhow!(
+                    "spending {} sats from {} would go over the run budget of {} sats ({} spent)",
+                    amt,
+                    node,
+                    run_limit,
+                    run_total
+                ));
+            }
+        }
+        let node_spend = spent.entry(node.to_owned()).or_default();
+        if let Some(node_limit) = self.node_limit {
+            if node_spend.total() + amt > node_limit {
+                return Err(anyhow!(
+                    "spending {} sats from {} would go over the node budget of {} sats ({} spent)",
+                    amt,
+                    node,
+                    node_limit,
+                    node_spend.total()
+                ));
+            }
+        }
+        node_spend.add(kind, amt);
+        Ok(())
+    }
+
+    /// Gives back an amount that was booked for an action that failed
+    pub fn release(&self, node: &str, kind: SpendKind, amt: i64) {
+        if let Some(node_spend) = self.spent.lock().unwrap().get_mut(node) {
+            node_spend.add(kind, -amt);
+        }
+    }
+
+    /// Runs the action with the amount booked, an action over budget is skipped and a failed one gives it back
+    pub fn spend(
+        &self,
+        node: &str,
+        kind: SpendKind,
+        amt: i64,
+        action: impl FnOnce() -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        if let Err(e) = self.reserve(node, kind, amt) {
+            error!("({}): skipped, {}", node, e);
+            return Ok(());
+        }
+        action().inspect_err(|_| self.release(node, kind, amt))
+    }
+
+    pub fn log_summary(&self) {
+        let spent = self.spent.lock().unwrap();
+        if spent.is_empty() {
+            return;
+        }
+        info!("spend summary (sats):");
+        for (node, node_spend) in spent.iter() {
+            info!(
+                "  {}: ln {}, channels {}, on chain {}, fees {}, total {}",
+                node,
+                node_spend.ln,
+                node_spend.channels,
+                node_spend.on_chain,
+                node_spend.fees,
+                node_spend.total()
+            );
+        }
+        let run_total: i64 = spent.values().map(NodeSpend::total).sum();
+        match self.run_limit {
+            Some(run_limit) => info!("  run total: {} of {} budget", run_total, run_limit),
+            None => info!("  run total: {}", run_total),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spent(budget: &SpendBudget, node: &str) -> i64 {
+        budget
+            .spent
+            .lock()
+            .unwrap()
+            .get(node)
+            .map_or(0, NodeSpend::total)
+    }
+
+    #[test]
+    fn reserves_up_to_the_node_limit() {
+        let budget = SpendBudget::new(None, Some(1000));
+        budget.reserve("lnd1", SpendKind::Ln, 600).unwrap();
+        budget.reserve("lnd1", SpendKind::OnChain, 400).unwrap();
+        assert!(budget.reserve("lnd1", SpendKind::Ln, 1).is_err());
+        // the failed reserve books nothing and the other nodes have their own limit
+        assert_eq!(spent(&budget, "lnd1"), 1000);
+        budget.reserve("lnd2", SpendKind::Channel, 1000).unwrap();
+    }
+
+    #[test]
+    fn reserves_up_to_the_run_limit() {
+        let budget = SpendBudget::new(Some(1500), None);
+        budget.reserve("lnd1", SpendKind::Channel, 1000).unwrap();
+        assert!(budget.reserve("lnd2", SpendKind::Ln, 600).is_err());
+        assert_eq!(spent(&budget, "lnd2"), 0);
+        budget.reserve("lnd2", SpendKind::Ln, 500).unwrap();
+    }
+
+    #[test]
+    fn release_gives_the_amount_back() {
+        let budget = SpendBudget::new(Some(1000), Some(1000));
+        budget.reserve("lnd1", SpendKind::Ln, 1000).unwrap();
+        budget.release("lnd1", SpendKind::Ln, 1000);
+        assert_eq!(spent(&budget, "lnd1"), 0);
+        budget.reserve("lnd1", SpendKind::Ln, 1000).unwrap();
+        // releasing for a node that never reserved anything is a no-op
+        budget.release("lnd2", SpendKind::Ln, 1000);
+        assert_eq!(spent(&budget, "lnd2"), 0);
+    }
+
+    #[test]
+    fn only_test_networks_pass_the_mainnet_check() {
+        for network in [
+            "regtest", "signet", "test", "testnet", "testnet4", "Regtest ",
+        ] {
+            check_reported_network(false, "node", Ok(network.to_owned())).unwrap();
+        }
+        for network in ["main", "mainnet", "bitcoin", "", "liquid"] {
+            assert!(check_reported_network(false, "node", Ok(network.to_owned())).is_err());
+        }
+        assert!(check_reported_network(false, "node", Err(anyhow!("unreachable"))).is_err());
+    }
+
+    #[test]
+    fn allowing_mainnet_skips_the_check() {
+        check_reported_network(true, "node", Ok("bitcoin".to_owned())).unwrap();
+        check_reported_network(true, "node", Err(anyhow!("unreachable"))).unwrap();
+    }
+
+    #[test]
+    fn unlimited_budget_only_books() {
+        let budget = SpendBudget::default();
+        budget
+            .reserve("lnd1", SpendKind::OnChain, i64::MAX / 2)
+            .unwrap();
+        assert_eq!(spent(&budget, "lnd1"), i64::MAX / 2);
+    }
+}
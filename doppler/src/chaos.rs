@@ -0,0 +1,318 @@
+use crate::{get_local_container_name, heal_node, isolate_node, L1Node, NodeCommand, Options};
+use anyhow::{anyhow, Error, Result};
+use log::{error, info, warn};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    fmt,
+    sync::atomic::Ordering,
+    thread::{self, spawn},
+    time::{Duration, Instant},
+};
+
+// a restarted bitcoind needs a moment before its wallet can be loaded again
+const BITCOIND_READY_RETRIES: u32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChaosFault {
+    // graceful stop, started again after the downtime
+    Restart,
+    // SIGKILL, started again after the downtime
+    Kill,
+    // cut off the network, healed after the downtime
+    Partition,
+}
+
+impl fmt::Display for ChaosFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChaosFault::Restart => write!(f, "RESTART"),
+            ChaosFault::Kill => write!(f, "KILL"),
+            ChaosFault::Partition => write!(f, "PARTITION"),
+        }
+    }
+}
+
+impl TryFrom<&str> for ChaosFault {
+    type Error = Error;
+
+    fn try_from(value: &str) -> Result<Self, Error> {
+        match value {
+            "RESTART" => Ok(ChaosFault::Restart),
+            "KILL" => Ok(ChaosFault::Kill),
+            "PARTITION" => Ok(ChaosFault::Partition),
+            _ => Err(anyhow!("unknown chaos fault {}", value)),
+        }
+    }
+}
+
+/// Settings of a CHAOS block
+#[derive(Debug, Clone)]
+pub struct ChaosConfig {
+    pub interval: Duration,
+    // runs until doppler stops when not set
+    pub duration: Option<Duration>,
+    // random when not set, it's logged so the run can be repeated
+    pub seed: Option<u64>,
+    // how long a node stays down or partitioned
+    pub downtime: Duration,
+    // chance in percent of each fault being picked on every interval
+    pub faults: Vec<(ChaosFault, f64)>,
+    // all local nodes when empty
+    pub nodes: Vec<String>,
+}
+
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(60),
+            duration: None,
+            seed: None,
+            downtime: Duration::from_secs(30),
+            faults: vec![],
+            nodes: vec![],
+        }
+    }
+}
+
+struct InjectedFault {
+    node: String,
+    fault: ChaosFault,
+    recover_at: Instant,
+}
+
+/// Injects a random fault on a random node every interval, until the duration is over or doppler stops
+pub fn start_chaos(options: &Options, config: ChaosConfig) -> Result<(), Error> {
+    let total: f64 = config.faults.iter().map(|(_, chance)| chance).sum();
+    if total > 100.0 {
+        return Err(anyhow!(
+            "the chances of the CHAOS faults add up to {}%, more than 100%",
+            total
+        ));
+    }
+    let nodes = if config.nodes.is_empty() {
+        get_local_nodes(options)
+    } else {
+        config.nodes.clone()
+    };
+    for node in nodes.iter() {
+        get_local_container_name(options, node)?;
+    }
+    if nodes.is_empty() {
+        return Err(anyhow!("CHAOS needs at least one local node"));
+    }
+    let seed = config.seed.unwrap_or_else(rand::random);
+    info!(
+        "(chaos): seed {}, a fault every {}s on {}",
+        seed,
+        config.interval.as_secs(),
+        nodes.join(", ")
+    );
+
+    options.loop_count.as_ref().fetch_add(1, Ordering::SeqCst);
+    let thread_options = options.clone();
+    spawn(move || {
+        thread_options.add_thread(thread::current());
+        run_chaos(&thread_options, &config, nodes, seed);
+        thread_options
+            .loop_count
+            .as_ref()
+            .fetch_sub(1, Ordering::SeqCst);
+    });
+    Ok(())
+}
+
+fn run_chaos(options: &Options, config: &ChaosConfig, nodes: Vec<String>, seed: u64) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let started = Instant::now();
+    let mut next_fault = started + config.interval;
+    let mut injected: Vec<InjectedFault> = vec![];
+    let mut fault_count = 0;
+    while options.main_thread_active.val() {
+        if config
+            .duration
+            .is_some_and(|duration| started.elapsed() >= duration)
+        {
+            break;
+        }
+        thread::sleep(Duration::from_secs(1));
+        if options.main_thread_paused.val() {
+            continue;
+        }
+        let now = Instant::now();
+        injected.retain(|fault| {
+            if fault.recover_at > now {
+                return true;
+            }
+            recover(options, fault);
+            false
+        });
+        if now < next_fault {
+            continue;
+        }
+        next_fault = now + config.interval;
+
+        let Some(fault) = pick_fault(&mut rng, &config.faults) else {
+            continue;
+        };
+        let available: Vec<&String> = nodes
+            .iter()
+            .filter(|node| !injected.iter().any(|fault| &fault.node == *node))
+            .collect();
+        if available.is_empty() {
+            continue;
+        }
+        let node = available[rng.gen_range(0..available.len())].to_owned();
+        fault_count += 1;
+        info!(
+            "(chaos): #{} {} {} at {}s, recovers in {}s",
+            fault_count,
+            fault,
+            node,
+            started.elapsed().as_secs(),
+            config.downtime.as_secs()
+        );
+        if let Err(e) = inject(options, &node, fault) {
+            error!("(chaos): failed to {} {}: {}", fault, node, e);
+            continue;
+        }
+        injected.push(InjectedFault {
+            node,
+            fault,
+            recover_at: now + config.downtime,
+        });
+    }
+    // leave the cluster whole once the chaos is over
+    for fault in injected.iter() {
+        recover(options, fault);
+    }
+    info!("(chaos): done, injected {} fault(s)", fault_count);
+}
+
+// one roll per interval, the faults take up their chance of the 0-100 range and the rest is no fault
+fn pick_fault(rng: &mut StdRng, faults: &[(ChaosFault, f64)]) -> Option<ChaosFault> {
+    let roll = rng.gen_range(0.0..100.0);
+    let mut upper = 0.0;
+    for (fault, chance) in faults {
+        upper += chance;
+        if roll < upper {
+            return Some(*fault);
+        }
+    }
+    None
+}
+
+fn inject(options: &Options, node: &str, fault: ChaosFault) -> Result<(), Error> {
+    match fault {
+        ChaosFault::Restart => stop_node(options, node),
+        ChaosFault::Kill => {
+            let container = get_local_container_name(options, node)?;
+            let output = options.backend.kill(options, &container)?;
+            if !output.status.success() {
+                return Err(anyhow!("failed to kill {}", container));
+            }
+            Ok(())
+        }
+        ChaosFault::Partition => isolate_node(options, &get_node_command(node)),
+    }
+}
+
+fn recover(options: &Options, injected: &InjectedFault) {
+    let recovered = match injected.fault {
+        ChaosFault::Restart | ChaosFault::Kill => start_node(options, &injected.node),
+        ChaosFault::Partition => heal_node(options, &get_node_command(&injected.node)),
+    };
+    match recovered {
+        Ok(_) => info!(
+            "(chaos): {} recovered from {}",
+            injected.node, injected.fault
+        ),
+        Err(e) => error!(
+            "(chaos): {} failed to recover from {}: {}",
+            injected.node, injected.fault, e
+        ),
+    }
+}
+
+fn stop_node(options: &Options, node: &str) -> Result<(), Error> {
+    if let Ok(l2_node) = options.get_l2_by_name(node) {
+        return l2_node.stop(options);
+    }
+    options.get_bitcoind_by_name(node)?.stop(options)
+}
+
+fn start_node(options: &Options, node: &str) -> Result<(), Error> {
+    if let Ok(l2_node) = options.get_l2_by_name(node) {
+        return l2_node.start(options);
+    }
+    let bitcoind = options.get_bitcoind_by_name(node)?;
+    bitcoind.start(options)?;
+    // the wallet isn't loaded on startup, the miners need it for MINE_BLOCKS
+    for _ in 0..BITCOIND_READY_RETRIES {
+        if bitcoind.call(options, "getblockcount", &[]).is_ok() {
+            return bitcoind.load_wallet(options);
+        }
+        thread::sleep(Duration::from_secs(3));
+    }
+    warn!("(chaos): {} isn't answering, wallet not loaded", node);
+    Ok(())
+}
+
+fn get_local_nodes(options: &Options) -> Vec<String> {
+    let mut nodes: Vec<String> = options
+        .get_l2_nodes()
+        .iter()
+        .map(|node| node.get_name().to_owned())
+        .filter(|name| !options.is_external_node(name))
+        .collect();
+    nodes.extend(
+        options
+            .bitcoinds
+            .iter()
+            .filter(|bitcoind| !bitcoind.external)
+            .map(|bitcoind| bitcoind.get_name()),
+    );
+    nodes
+}
+
+fn get_node_command(node: &str) -> NodeCommand {
+    NodeCommand {
+        from: node.to_owned(),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAULTS: [(ChaosFault, f64); 3] = [
+        (ChaosFault::Restart, 20.0),
+        (ChaosFault::Kill, 10.0),
+        (ChaosFault::Partition, 30.0),
+    ];
+
+    fn roll(seed: u64, faults: &[(ChaosFault, f64)]) -> Vec<Option<ChaosFault>> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..100).map(|_| pick_fault(&mut rng, faults)).collect()
+    }
+
+    #[test]
+    fn same_seed_picks_the_same_faults() {
+        let picked = roll(42, &FAULTS);
+        assert_eq!(picked, roll(42, &FAULTS));
+        assert_ne!(picked, roll(43, &FAULTS));
+        // 60% of the range is a fault, a hundred rolls hit both outcomes
+        assert!(picked.iter().any(Option::is_none));
+        assert!(picked.iter().any(Option::is_some));
+    }
+
+    #[test]
+    fn chances_bound_the_picks() {
+        assert!(roll(7, &[(ChaosFault::Kill, 0.0)])
+            .iter()
+            .all(Option::is_none));
+        assert!(roll(7, &[(ChaosFault::Kill, 100.0)])
+            .iter()
+            .all(|fault| *fault == Some(ChaosFault::Kill)));
+    }
+}
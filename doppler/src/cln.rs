@@ -1,6 +1,7 @@
 use crate::{
-    copy_file, create_folder, get_absolute_path, run_command, ImageInfo, L1Node, L2Node,
-    NodeCommand, NodePair, Options, NETWORK,
+    add_net_admin, copy_file, create_folder, get_absolute_path, run_command, set_conf_overrides,
+    BuildDetails, ClnRest, ExternalNode, ImageInfo, L1Node, L2Node, LnNodeKind, NodeCommand,
+    NodePair, Options, NETWORK,
 };
 use anyhow::{anyhow, Error, Result};
 use conf_parser::processer::{read_to_file_conf, FileConf, Section};
@@ -32,6 +33,8 @@ pub struct Cln {
     pub path_vol: String,
     pub bitcoind_node_container_name: String,
     pub network: String,
+    // set when running with --rest, replaces the lightning-cli calls
+    pub cln_rest: Option<ClnRest>,
 }
 
 impl Cln {
@@ -54,6 +57,24 @@ impl Cln {
     pub fn add_rune(&mut self, options: &Options) -> Result<(), Error> {
         let rune = get_rune(self, options)?;
         self.rune = Some(rune);
+        if options.rest {
+            self.add_rest_client(options)?;
+        }
+        Ok(())
+    }
+
+    pub fn add_rest_client(&mut self, options: &Options) -> Result<(), Error> {
+        let rune = self.rune.clone().ok_or(anyhow!(
+            "a rune is needed to talk to {} over rest",
+            self.name
+        ))?;
+        // clnrest writes its certificates next to the network data in the lightning dir
+        let ca_path = get_absolute_path(&format!(
+            "data/{}/{}/ca.pem",
+            self.name,
+            options.network.cln_network()
+        ))?;
+        self.cln_rest = Some(ClnRest::new(&self.server_url, &rune, ca_path.to_str())?);
         Ok(())
     }
 }
@@ -96,30 +117,64 @@ impl L2Node for Cln {
         add_pubkey(self, options)
     }
     fn get_node_pubkey(&self, options: &Options) -> Result<String, Error> {
-        get_node_pubkey(self, options)
+        if let Some(rest) = self.cln_rest.as_ref() {
+            rest.get_node_pubkey(options)
+        } else {
+            get_node_pubkey(self, options)
+        }
+    }
+    fn get_network(&self, _options: &Options) -> Result<String, Error> {
+        match self.cln_rest.as_ref() {
+            Some(rest) => rest.get_network(),
+            None => Err(anyhow!("{} has no api to ask for its network", self.name)),
+        }
     }
     fn open_channel(&self, options: &Options, node_command: &NodeCommand) -> Result<(), Error> {
-        open_channel(self, options, node_command)
+        if let Some(rest) = self.cln_rest.as_ref() {
+            rest.open_channel(self, options, node_command)
+        } else {
+            open_channel(self, options, node_command)
+        }
     }
     fn connect(&self, options: &Options, node_command: &NodeCommand) -> Result<(), Error> {
-        connect(self, options, node_command)
+        if let Some(rest) = self.cln_rest.as_ref() {
+            rest.connect(self, options, node_command)
+        } else {
+            connect(self, options, node_command)
+        }
     }
-    fn close_channel(&self, options: &Options, node_command: &NodeCommand) -> Result<(), Error> {
-        close_channel(self, options, node_command)
+    fn close_channel(
+        &self,
+        options: &Options,
+        node_command: &NodeCommand,
+    ) -> Result<String, Error> {
+        if let Some(rest) = self.cln_rest.as_ref() {
+            rest.close_channel(self, options, node_command, false)
+        } else {
+            close_channel(self, options, node_command)
+        }
     }
     fn force_close_channel(
         &self,
         options: &Options,
         node_command: &NodeCommand,
-    ) -> std::result::Result<(), Error> {
-        force_close_channel(self, options, node_command)
+    ) -> std::result::Result<String, Error> {
+        if let Some(rest) = self.cln_rest.as_ref() {
+            rest.close_channel(self, options, node_command, true)
+        } else {
+            force_close_channel(self, options, node_command)
+        }
     }
     fn create_invoice(
         &self,
         options: &Options,
         node_command: &NodeCommand,
     ) -> Result<String, Error> {
-        create_invoice(self, options, node_command)
+        if let Some(rest) = self.cln_rest.as_ref() {
+            rest.create_invoice(self, options, node_command)
+        } else {
+            create_invoice(self, options, node_command)
+        }
     }
     fn pay_invoice(
         &self,
@@ -127,10 +182,18 @@ impl L2Node for Cln {
         node_command: &NodeCommand,
         payment_request: String,
     ) -> Result<(), Error> {
-        pay_invoice(self, options, node_command, payment_request)
+        if let Some(rest) = self.cln_rest.as_ref() {
+            rest.pay_invoice(options, node_command, payment_request)
+        } else {
+            pay_invoice(self, options, node_command, payment_request)
+        }
     }
     fn create_on_chain_address(&self, options: &Options) -> Result<String, Error> {
-        create_cln_address(self, options)
+        if let Some(rest) = self.cln_rest.as_ref() {
+            rest.create_address(options)
+        } else {
+            create_cln_address(self, options)
+        }
     }
     fn pay_address(
         &self,
@@ -138,65 +201,92 @@ impl L2Node for Cln {
         node_command: &NodeCommand,
         address: &str,
     ) -> Result<String, Error> {
-        pay_address(self, options, node_command, address)
+        if let Some(rest) = self.cln_rest.as_ref() {
+            rest.pay_address(options, node_command, address)
+        } else {
+            pay_address(self, options, node_command, address)
+        }
     }
     fn create_hold_invoice(
         &self,
         _option: &Options,
-        _node_command: &NodeCommand,
-        _rhash: String,
+        node_command: &NodeCommand,
+        rhash: String,
     ) -> Result<String, Error> {
-        // Not implemented yet, needs some more research into their api
-        unimplemented!("only implemented for LND nodes at the moment");
+        match self.cln_rest.as_ref() {
+            Some(rest) => rest.create_hold_invoice(node_command, rhash),
+            None => Err(hold_invoice_needs_rest()),
+        }
     }
-    fn settle_hold_invoice(&self, _options: &Options, _preimage: String) -> Result<(), Error> {
-        // Not implemented yet, needs some more research into their api
-        unimplemented!("only implemented for LND nodes at the moment");
+    fn settle_hold_invoice(&self, _options: &Options, preimage: String) -> Result<(), Error> {
+        match self.cln_rest.as_ref() {
+            Some(rest) => rest.settle_hold_invoice(preimage),
+            None => Err(hold_invoice_needs_rest()),
+        }
     }
     fn get_rhash(&self, option: &Options) -> Result<String, Error> {
-        get_rhash(self, option)
+        if let Some(rest) = self.cln_rest.as_ref() {
+            rest.get_rhash(self, option)
+        } else {
+            get_rhash(self, option)
+        }
     }
-    fn get_preimage(&self, _option: &Options, _rhash: String) -> Result<String, Error> {
-        unimplemented!("only implemented for LND nodes at the moment");
+    fn get_preimage(&self, option: &Options, rhash: String) -> Result<String, Error> {
+        match self.cln_rest.as_ref() {
+            Some(rest) => rest.get_preimage(option, rhash),
+            None => Err(hold_invoice_needs_rest()),
+        }
     }
-    fn wait_for_block(&self, _options: &Options, _num_of_blocks: i64) -> Result<(), Error> {
-        unimplemented!("only implemented for LND nodes at the moment");
+    fn wait_for_block(&self, options: &Options, num_of_blocks: i64) -> Result<(), Error> {
+        if let Some(rest) = self.cln_rest.as_ref() {
+            return rest.wait_for_block(options, num_of_blocks);
+        }
+        unimplemented!("only implemented for LND nodes and CoreLN over rest at the moment");
     }
     fn send_keysend(
         &self,
-        _options: &Options,
-        _node_command: &NodeCommand,
-        _to_pubkey: String,
+        options: &Options,
+        node_command: &NodeCommand,
+        to_pubkey: String,
     ) -> Result<(), Error> {
-        unimplemented!("only implemented for LND nodes at the moment");
+        if let Some(rest) = self.cln_rest.as_ref() {
+            return rest.send_keysend(options, node_command, to_pubkey);
+        }
+        unimplemented!("only implemented for LND nodes and CoreLN over rest at the moment");
     }
 }
 
+fn hold_invoice_needs_rest() -> Error {
+    anyhow!("hold invoices on CoreLN need --rest and the hold plugin loaded on the node")
+}
+
 pub fn build_cln(
     options: &mut Options,
     name: &str,
     image: &ImageInfo,
-    pair: &NodePair,
+    details: &BuildDetails,
 ) -> Result<()> {
-    let mut cln_conf = build_and_save_config(options, name, pair).unwrap();
+    let pair = details.get_pair()?;
+    let mut cln_conf = build_and_save_config(options, name, pair, &details.conf_overrides).unwrap();
     debug!("{} volume: {}", name, cln_conf.path_vol);
 
     // Passing these args on the command line is unavoidable due to how the docker image is setup
     let command = Command::Simple(
         format!(
             "--network={} --lightning-dir=/home/clightning --developer",
-            options.network
+            options.network.cln_network()
         )
         .to_string(),
     );
 
-    let rest_port = options.new_port();
-    let grpc_port = options.new_port();
-    let p2p_port = options.new_port();
+    let rest_port = options.new_port(&cln_conf.container_name, &cln_conf.rest_port)?;
+    let grpc_port = options.new_port(&cln_conf.container_name, &cln_conf.grpc_port)?;
+    let p2p_port = options.new_port(&cln_conf.container_name, &cln_conf.p2p_port)?;
     let bitcoind = vec![cln_conf.bitcoind_node_container_name.clone()];
-    let cln = Service {
+    let mut cln = Service {
         depends_on: DependsOnOptions::Simple(bitcoind),
         image: Some(image.get_image()),
+        build_: image.get_build_step(),
         container_name: Some(cln_conf.container_name.clone()),
         ports: Ports::Short(vec![
             format!("{}:{}", p2p_port, cln_conf.p2p_port),
@@ -209,6 +299,8 @@ pub fn build_cln(
         networks: Networks::Simple(vec![NETWORK.to_owned()]),
         ..Default::default()
     };
+    details.service_settings.apply(&mut cln);
+    add_net_admin(options, &mut cln);
     options
         .services
         .insert(cln_conf.container_name.clone(), Some(cln));
@@ -228,6 +320,7 @@ pub fn build_and_save_config(
     options: &mut Options,
     name: &str,
     pair: &NodePair,
+    conf_overrides: &[String],
 ) -> Result<Cln, Error> {
     if options.bitcoinds.is_empty() {
         return Err(anyhow!(
@@ -258,10 +351,15 @@ pub fn build_and_save_config(
         container_name.clone(),
         bitcoind_node,
     )?;
+    set_conf_overrides(&mut conf, conf_overrides, "")?;
     let _ = copy_file(&conf, &destination_dir.clone(), "config")?;
 
     // Needed so that the data store in the network folder have permissions by the current user and not root
-    create_folder(&format!("{}/{}", destination_dir, options.network))?;
+    create_folder(&format!(
+        "{}/{}",
+        destination_dir,
+        options.network.cln_network()
+    ))?;
     let full_path = get_absolute_path(destination_dir)?
         .to_str()
         .unwrap()
@@ -281,7 +379,8 @@ pub fn build_and_save_config(
         p2p_port: "9735".to_owned(),
         rest_port: "8080".to_owned(),
         bitcoind_node_container_name: bitcoind_node.container_name.clone(),
-        network: options.network.clone(),
+        network: options.network.cln_network().to_owned(),
+        cln_rest: None,
     })
 }
 
@@ -332,7 +431,7 @@ pub fn add_coreln_nodes(options: &mut Options) -> Result<()> {
             load_config(
                 node_name,
                 container_name.to_owned(),
-                options.network.clone(),
+                options.network.cln_network().to_owned(),
                 bitcoind_service,
             )
         })
@@ -342,7 +441,17 @@ pub fn add_coreln_nodes(options: &mut Options) -> Result<()> {
     let nodes: Vec<_> = node_l2
         .iter_mut()
         .map(|node| {
+            if let Some(rest_port) =
+                options.get_assigned_port(&node.container_name, &node.rest_port)
+            {
+                node.server_url = format!("https://localhost:{}", rest_port);
+            }
             node.add_pubkey(options);
+            if options.rest {
+                if let Err(e) = node.add_rune(options) {
+                    error!("failed to add rest client for {}: {}", node.get_name(), e);
+                }
+            }
             node.clone()
         })
         .collect();
@@ -352,6 +461,64 @@ pub fn add_coreln_nodes(options: &mut Options) -> Result<()> {
     Ok(())
 }
 
+pub fn add_external_cln_nodes(options: &mut Options) -> Result<(), Error> {
+    let mut node_l2: Vec<_> = options
+        .external_nodes
+        .clone()
+        .unwrap()
+        .iter()
+        .filter(|external_node| external_node.kind == LnNodeKind::Coreln)
+        .map(load_external_cln_config)
+        .filter_map(|res| {
+            res.map_err(|e| error!("failed to load external coreln node: {}", e))
+                .ok()
+        })
+        .collect();
+
+    let nodes: Vec<_> = node_l2
+        .iter_mut()
+        .map(|node| {
+            node.add_pubkey(options);
+            node.clone()
+        })
+        .collect();
+
+    options.cln_nodes.extend(nodes);
+    Ok(())
+}
+
+pub fn load_external_cln_config(external_node: &ExternalNode) -> Result<Cln, Error> {
+    if external_node.rune.is_empty() {
+        return Err(anyhow!(
+            "a RUNE is needed to talk to {}",
+            external_node.node_alias
+        ));
+    }
+    let server_url = external_node.get_api_url();
+    let ca_path = Some(external_node.tls_cert_path.as_str()).filter(|path| !path.is_empty());
+    let cln_rest = ClnRest::new(&server_url, &external_node.rune, ca_path)?;
+
+    // other nodes reach it through its container name, which for external nodes is its p2p host
+    let (p2p_host, p2p_port) = external_node.get_p2p_address();
+    Ok(Cln {
+        wallet_starting_balance: 0,
+        name: external_node.node_alias.to_owned(),
+        alias: external_node.node_alias.to_owned(),
+        container_name: p2p_host,
+        pubkey: None,
+        rune: Some(external_node.rune.clone()),
+        server_url,
+        path_vol: String::from(""),
+        rpc_server: format!("{}:10000", external_node.api_endpoint),
+        grpc_port: "10000".to_owned(),
+        p2p_port,
+        rest_port: "3010".to_owned(),
+        bitcoind_node_container_name: String::from(""),
+        network: external_node.network.clone(),
+        cln_rest: Some(cln_rest),
+    })
+}
+
 fn add_pubkey(node: &mut Cln, options: &Options) {
     let result = node.get_node_pubkey(options);
     match result {
@@ -387,12 +554,13 @@ fn load_config(
         rest_port: "8080".to_owned(),
         bitcoind_node_container_name: bitcoind_service,
         network,
+        cln_rest: None,
     })
 }
 
 fn get_node_pubkey(node: &Cln, options: &Options) -> Result<String, Error> {
     let compose_path = options.compose_path.as_ref().unwrap();
-    let network = format!("--network={}", options.network);
+    let network = format!("--network={}", options.network.cln_network());
     let commands = vec![
         "-f",
         compose_path,
@@ -439,7 +607,7 @@ fn get_node_pubkey(node: &Cln, options: &Options) -> Result<String, Error> {
 
 fn create_cln_address(node: &Cln, options: &Options) -> Result<String, Error> {
     let compose_path = options.compose_path.as_ref().unwrap();
-    let network = format!("--network={}", options.network);
+    let network = format!("--network={}", options.network.cln_network());
     let commands = vec![
         "-f",
         compose_path.as_ref(),
@@ -468,7 +636,7 @@ fn open_channel(node: &Cln, options: &Options, node_command: &NodeCommand) -> Re
     let to_node = options.get_l2_by_name(node_command.to.as_str())?;
     let to_pubkey = to_node.get_cached_pubkey();
     let compose_path = options.compose_path.as_ref().unwrap();
-    let network = format!("--network={}", options.network);
+    let network = format!("--network={}", options.network.cln_network());
     let commands = vec![
         "-f",
         compose_path.as_ref(),
@@ -503,7 +671,7 @@ fn connect(node: &Cln, options: &Options, node_command: &NodeCommand) -> Result<
     let to_node = options.get_l2_by_name(node_command.to.as_str())?;
     let to_pubkey = to_node.get_cached_pubkey();
     let compose_path = options.compose_path.as_ref().unwrap();
-    let network = format!("--network={}", options.network);
+    let network = format!("--network={}", options.network.cln_network());
     let commands = vec![
         "-f",
         compose_path.as_ref(),
@@ -544,7 +712,7 @@ fn create_invoice(
     let compose_path = options.compose_path.as_ref().unwrap();
     let uuid = Uuid::new_v4();
     let random_label = uuid.to_string();
-    let network: String = format!("--network={}", options.network);
+    let network: String = format!("--network={}", options.network.cln_network());
 
     let commands = vec![
         "-f",
@@ -574,7 +742,7 @@ fn pay_invoice(
     payment_request: String,
 ) -> Result<(), Error> {
     let compose_path = options.compose_path.as_ref().unwrap();
-    let network: String = format!("--network={}", options.network);
+    let network: String = format!("--network={}", options.network.cln_network());
     let commands = vec![
         "-f",
         compose_path,
@@ -601,6 +769,11 @@ fn pay_invoice(
     Ok(())
 }
 
+/// CoreLN takes feerates per kilo-vbyte, doppler's FEERATE is in sat/vB
+pub fn cln_fee_rate(sat_per_vbyte: u64) -> String {
+    format!("{}perkb", sat_per_vbyte * 1000)
+}
+
 fn pay_address(
     node: &Cln,
     options: &Options,
@@ -609,9 +782,10 @@ fn pay_address(
 ) -> Result<String, Error> {
     let amt = node_command.amt.unwrap_or(1000).to_string();
     let compose_path = options.compose_path.as_ref().unwrap();
-    let network: String = format!("--network={}", options.network);
+    let network: String = format!("--network={}", options.network.cln_network());
+    let fee_rate = node_command.fee_rate.map(cln_fee_rate);
 
-    let commands = vec![
+    let mut commands = vec![
         "-f",
         compose_path,
         "exec",
@@ -623,6 +797,9 @@ fn pay_address(
         address,
         &amt,
     ];
+    if let Some(fee_rate) = fee_rate.as_ref() {
+        commands.push(fee_rate);
+    }
     let output = run_command(options, "withdraw".to_owned(), commands)?;
     if !output.status.success() {
         error!("failed to pay on chain tx");
@@ -633,7 +810,11 @@ fn pay_address(
     Ok(found_tx_id.to_owned())
 }
 
-fn close_channel(node: &Cln, options: &Options, node_command: &NodeCommand) -> Result<(), Error> {
+fn close_channel(
+    node: &Cln,
+    options: &Options,
+    node_command: &NodeCommand,
+) -> Result<String, Error> {
     let compose_path = options.compose_path.as_ref().unwrap();
     //TODO: find a way to specify which channel to close, right now we just grab a random one for this peer
     let to_node = options.get_l2_by_name(node_command.to.as_str())?;
@@ -644,9 +825,9 @@ fn close_channel(node: &Cln, options: &Options, node_command: &NodeCommand) -> R
             node.get_name(),
             to_node.get_name()
         );
-        return Ok(());
+        return Ok("".to_owned());
     }
-    let network: String = format!("--network={}", options.network);
+    let network: String = format!("--network={}", options.network.cln_network());
 
     let commands = vec![
         "-f",
@@ -672,15 +853,16 @@ fn close_channel(node: &Cln, options: &Options, node_command: &NodeCommand) -> R
             node.get_name(),
             to_node.get_name()
         );
+        return Ok("".to_owned());
     }
-    Ok(())
+    get_closing_txid(&output.stdout)
 }
 
 fn force_close_channel(
     node: &Cln,
     options: &Options,
     node_command: &NodeCommand,
-) -> Result<(), Error> {
+) -> Result<String, Error> {
     let compose_path = options.compose_path.as_ref().unwrap();
     //TODO: find a way to specify which channel to close, right now we just grab a random one for this peer
     let to_node = options.get_l2_by_name(node_command.to.as_str())?;
@@ -691,9 +873,9 @@ fn force_close_channel(
             node.get_name(),
             to_node.get_name()
         );
-        return Ok(());
+        return Ok("".to_owned());
     }
-    let network: String = format!("--network={}", options.network);
+    let network: String = format!("--network={}", options.network.cln_network());
 
     let commands = vec![
         "-f",
@@ -720,8 +902,14 @@ fn force_close_channel(
             node.get_name(),
             to_node.get_name()
         );
+        return Ok("".to_owned());
     }
-    Ok(())
+    get_closing_txid(&output.stdout)
+}
+
+fn get_closing_txid(stdout: &[u8]) -> Result<String, Error> {
+    let response: Value = from_slice(stdout)?;
+    Ok(response["txid"].as_str().unwrap_or_default().to_owned())
 }
 
 fn get_peers_short_channel_id(
@@ -733,7 +921,7 @@ fn get_peers_short_channel_id(
     let compose_path = options.compose_path.as_ref().unwrap();
     let to_node = options.get_l2_by_name(node_command.to.as_str())?;
     let to_pubkey = format!("{}={}", param, to_node.get_cached_pubkey());
-    let network: String = format!("--network={}", options.network);
+    let network: String = format!("--network={}", options.network.cln_network());
     let commands = vec![
         "-f",
         compose_path,
@@ -776,7 +964,7 @@ fn get_rhash(node: &Cln, options: &Options) -> Result<String, Error> {
     let compose_path = options.compose_path.as_ref().unwrap();
     let uuid = Uuid::new_v4();
     let random_label = uuid.to_string();
-    let network: String = format!("--network={}", options.network);
+    let network: String = format!("--network={}", options.network.cln_network());
 
     let commands = vec![
         "-f",
@@ -800,7 +988,7 @@ fn get_rhash(node: &Cln, options: &Options) -> Result<String, Error> {
 }
 
 fn get_rune(node: &Cln, options: &Options) -> Result<String, Error> {
-    let network: String = format!("--network={}", options.network);
+    let network: String = format!("--network={}", options.network.cln_network());
     let compose_path = options.compose_path.as_ref().unwrap();
 
     let commands = vec![
@@ -820,3 +1008,14 @@ fn get_rune(node: &Cln, options: &Options) -> Result<String, Error> {
     }
     Ok(found_rune.unwrap())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_fee_rates_to_perkb() {
+        assert_eq!(cln_fee_rate(1), "1000perkb");
+        assert_eq!(cln_fee_rate(15), "15000perkb");
+    }
+}
@@ -0,0 +1,395 @@
+use crate::{cln_fee_rate, wait_for_height, Cln, L2Node, NodeCommand, Options, Tag};
+use anyhow::{anyhow, Error, Result};
+use log::{debug, error, info};
+use reqwest::{
+    blocking::Client,
+    header::{HeaderMap, HeaderValue},
+    Certificate,
+};
+use serde_json::{json, Value};
+use std::{fs, path::Path, thread, time::Duration};
+use uuid::Uuid;
+
+// preimages doppler generated for hold invoice hashes are kept as tags under this prefix and the rhash
+const PREIMAGE_TAG_PREFIX: &str = "doppler-preimage-";
+
+/// Talks to a Core Lightning node through the clnrest plugin, authenticated with a rune
+#[derive(Debug, Clone)]
+pub struct ClnRest {
+    base_url: String,
+    client: Client,
+}
+
+impl ClnRest {
+    pub fn new(base_url: &str, rune: &str, ca_path: Option<&str>) -> Result<Self, Error> {
+        let mut rune_value = HeaderValue::from_str(rune)?;
+        rune_value.set_sensitive(true);
+        let mut headers = HeaderMap::new();
+        headers.insert("Rune", rune_value);
+        let mut client_builder = Client::builder()
+            .default_headers(headers)
+            .timeout(Duration::from_secs(120));
+        match ca_path {
+            Some(ca_path) if Path::new(ca_path).exists() => {
+                let cert = Certificate::from_pem(&fs::read(ca_path)?)?;
+                // clnrest's self signed certificate is issued for the container, not localhost
+                client_builder = client_builder
+                    .add_root_certificate(cert)
+                    .danger_accept_invalid_hostnames(true);
+            }
+            _ => {
+                debug!(
+                    "no ca found for {}, accepting its self signed certificate",
+                    base_url
+                );
+                client_builder = client_builder.danger_accept_invalid_certs(true);
+            }
+        }
+        Ok(Self {
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            client: client_builder.build()?,
+        })
+    }
+
+    fn call(&self, method: &str, params: Value) -> Result<Value, Error> {
+        let url = format!("{}/v1/{}", self.base_url, method);
+        info!("({}): {} {}", method, url, params);
+        let response = self.client.post(&url).json(&params).send()?;
+        let status = response.status();
+        let body: Value = response.json()?;
+        debug!("response ({}): {}", status, body);
+        if !status.is_success() {
+            return Err(anyhow!("{} failed ({}): {}", method, status, body));
+        }
+        Ok(body)
+    }
+
+    pub fn get_node_pubkey(&self, _options: &Options) -> Result<String, Error> {
+        let mut retries = 8;
+        while retries > 0 {
+            match self.call("getinfo", json!({})) {
+                Ok(info) if info.get("warning_lightningd_sync").is_none() => {
+                    if let Some(pubkey) = info.get("id").and_then(Value::as_str) {
+                        return Ok(pubkey.to_owned());
+                    }
+                    error!("no pubkey found");
+                    return Ok("".to_owned());
+                }
+                Ok(_) => debug!("node still syncing, trying to get pubkey again"),
+                Err(e) => debug!("trying to get pubkey again: {}", e),
+            }
+            thread::sleep(Duration::from_secs(4));
+            retries -= 1;
+        }
+        Ok("".to_owned())
+    }
+
+    pub fn get_network(&self) -> Result<String, Error> {
+        let info = self.call("getinfo", json!({}))?;
+        info["network"]
+            .as_str()
+            .map(str::to_owned)
+            .ok_or(anyhow!("getinfo has no network: {}", info))
+    }
+
+    pub fn create_address(&self, _options: &Options) -> Result<String, Error> {
+        let result = self.call("newaddr", json!({ "addresstype": "bech32" }))?;
+        match result.get("bech32").and_then(Value::as_str) {
+            Some(address) => Ok(address.to_owned()),
+            None => {
+                error!("no addess found");
+                Ok("".to_owned())
+            }
+        }
+    }
+
+    pub fn connect(
+        &self,
+        node: &Cln,
+        options: &Options,
+        node_command: &NodeCommand,
+    ) -> Result<(), Error> {
+        let to_node = options.get_l2_by_name(node_command.to.as_str())?;
+        let port = to_node.get_p2p_port().parse::<u16>()?;
+        let result = self.call(
+            "connect",
+            json!({
+                "id": to_node.get_cached_pubkey(),
+                "host": to_node.get_container_name(),
+                "port": port,
+            }),
+        );
+        match result {
+            Ok(_) => info!(
+                "successfully connected from {} to {}",
+                node.get_name(),
+                to_node.get_name()
+            ),
+            Err(e) => error!(
+                "failed to connect from {} to {}: {}",
+                node.get_name(),
+                to_node.get_name(),
+                e
+            ),
+        }
+        Ok(())
+    }
+
+    pub fn open_channel(
+        &self,
+        node: &Cln,
+        options: &Options,
+        node_command: &NodeCommand,
+    ) -> Result<(), Error> {
+        let _ = self.connect(node, options, node_command);
+        let to_node = options.get_l2_by_name(node_command.to.as_str())?;
+        let result = self.call(
+            "fundchannel",
+            json!({
+                "id": to_node.get_cached_pubkey(),
+                "amount": node_command.amt.unwrap_or(100000),
+                "feerate": "normal",
+            }),
+        );
+        match result {
+            Ok(_) => info!(
+                "successfully opened channel from {} to {}",
+                node.get_name(),
+                to_node.get_name()
+            ),
+            Err(e) => error!(
+                "failed to open channel from {} to {}: {}",
+                node.get_name(),
+                to_node.get_name(),
+                e
+            ),
+        }
+        Ok(())
+    }
+
+    pub fn create_invoice(
+        &self,
+        node: &Cln,
+        _options: &Options,
+        node_command: &NodeCommand,
+    ) -> Result<String, Error> {
+        let result = self.call(
+            "invoice",
+            json!({
+                "amount_msat": node_command.amt.unwrap_or(1000) * 1000,
+                "label": Uuid::new_v4().to_string(),
+                "description": node.generate_memo(),
+            }),
+        )?;
+        result
+            .get("bolt11")
+            .and_then(Value::as_str)
+            .map(str::to_owned)
+            .ok_or(anyhow!("no payment requests found"))
+    }
+
+    pub fn pay_invoice(
+        &self,
+        _options: &Options,
+        node_command: &NodeCommand,
+        payment_request: String,
+    ) -> Result<(), Error> {
+        if let Err(e) = self.call("pay", json!({ "bolt11": payment_request })) {
+            error!(
+                "failed to make payment from {} to {}: {}",
+                node_command.from, node_command.to, e
+            )
+        }
+        Ok(())
+    }
+
+    pub fn send_keysend(
+        &self,
+        _options: &Options,
+        node_command: &NodeCommand,
+        to_pubkey: String,
+    ) -> Result<(), Error> {
+        let result = self.call(
+            "keysend",
+            json!({
+                "destination": to_pubkey,
+                "amount_msat": node_command.amt.unwrap_or(1000) * 1000,
+            }),
+        );
+        if let Err(e) = result {
+            error!(
+                "failed to keysend from {} to {}: {}",
+                node_command.from, node_command.to, e
+            )
+        }
+        Ok(())
+    }
+
+    pub fn pay_address(
+        &self,
+        _options: &Options,
+        node_command: &NodeCommand,
+        address: &str,
+    ) -> Result<String, Error> {
+        let mut params = json!({
+            "destination": address,
+            "satoshi": node_command.amt.unwrap_or(1000),
+        });
+        if let Some(fee_rate) = node_command.fee_rate {
+            params["feerate"] = json!(cln_fee_rate(fee_rate));
+        }
+        let result = self.call("withdraw", params);
+        match result {
+            Ok(withdraw) => Ok(withdraw
+                .get("txid")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_owned()),
+            Err(e) => {
+                error!("failed to pay on chain tx: {}", e);
+                Ok("".to_owned())
+            }
+        }
+    }
+
+    pub fn close_channel(
+        &self,
+        node: &Cln,
+        options: &Options,
+        node_command: &NodeCommand,
+        force: bool,
+    ) -> Result<String, Error> {
+        //TODO: find a way to specify which channel to close, right now we just grab a random one for this peer
+        let to_node = options.get_l2_by_name(node_command.to.as_str())?;
+        let channel_id = self.get_peers_short_channel_id(to_node.get_cached_pubkey())?;
+        if channel_id.is_empty() {
+            info!(
+                "no channels to closed from {} to {}",
+                node.get_name(),
+                to_node.get_name()
+            );
+            return Ok("".to_owned());
+        }
+        let params = if force {
+            json!({ "id": channel_id, "unilateraltimeout": 1 })
+        } else {
+            json!({ "id": channel_id })
+        };
+        match self.call("close", params) {
+            Ok(result) => {
+                info!(
+                    "successfully closed channel from {} to {}",
+                    node.get_name(),
+                    to_node.get_name()
+                );
+                Ok(result["txid"].as_str().unwrap_or_default().to_owned())
+            }
+            Err(e) => {
+                error!(
+                    "failed to close channel from {} to {}: {}",
+                    node.get_name(),
+                    to_node.get_name(),
+                    e
+                );
+                Ok("".to_owned())
+            }
+        }
+    }
+
+    fn get_peers_short_channel_id(&self, peer_pubkey: String) -> Result<String, Error> {
+        for param in ["source", "destination"] {
+            let result = self.call("listchannels", json!({ param: peer_pubkey }))?;
+            let short_channel_id = result
+                .get("channels")
+                .and_then(Value::as_array)
+                .and_then(|channels| channels.first())
+                .and_then(|channel| channel.get("short_channel_id"))
+                .and_then(Value::as_str);
+            if let Some(short_channel_id) = short_channel_id {
+                return Ok(short_channel_id.to_owned());
+            }
+        }
+        Ok(String::from(""))
+    }
+
+    pub fn get_rhash(&self, node: &Cln, options: &Options) -> Result<String, Error> {
+        // the preimage stays with doppler, the node only learns it once the invoice is paid
+        let preimage = hex::encode(rand::random::<[u8; 32]>());
+        let result = self.call(
+            "invoice",
+            json!({
+                "amount_msat": "any",
+                "label": Uuid::new_v4().to_string(),
+                "description": node.generate_memo(),
+                "preimage": preimage,
+            }),
+        )?;
+        let rhash = result
+            .get("payment_hash")
+            .and_then(Value::as_str)
+            .map(str::to_owned)
+            .ok_or(anyhow!("no r_hash found"))?;
+        options.save_tag(&Tag {
+            name: format!("{}{}", PREIMAGE_TAG_PREFIX, rhash),
+            val: preimage,
+        })?;
+        Ok(rhash)
+    }
+
+    pub fn get_preimage(&self, options: &Options, rhash: String) -> Result<String, Error> {
+        let tag = options.get_tag_by_name(format!("{}{}", PREIMAGE_TAG_PREFIX, rhash));
+        if !tag.val.is_empty() {
+            return Ok(tag.val);
+        }
+        // listinvoices only shows the preimage of paid invoices
+        let result = self.call("listinvoices", json!({ "payment_hash": rhash }))?;
+        result
+            .get("invoices")
+            .and_then(Value::as_array)
+            .and_then(|invoices| invoices.first())
+            .and_then(|invoice| invoice.get("payment_preimage"))
+            .and_then(Value::as_str)
+            .map(str::to_owned)
+            .ok_or(anyhow!(
+                "the preimage of {} is unknown, the invoice wasn't created by doppler",
+                rhash
+            ))
+    }
+
+    /// Hold invoices come from the hold plugin (https://github.com/BoltzExchange/hold), it has to be loaded on the node
+    pub fn create_hold_invoice(
+        &self,
+        node_command: &NodeCommand,
+        rhash: String,
+    ) -> Result<String, Error> {
+        let amt = node_command.amt.unwrap_or(1000);
+        let result = self.call(
+            "holdinvoice",
+            json!({ "payment_hash": rhash, "amount": amt * 1000 }),
+        )?;
+        result
+            .get("bolt11")
+            .and_then(Value::as_str)
+            .map(str::to_owned)
+            .ok_or(anyhow!("no bolt11 in the hold invoice"))
+    }
+
+    pub fn settle_hold_invoice(&self, preimage: String) -> Result<(), Error> {
+        self.call("settleholdinvoice", json!({ "preimage": preimage }))?;
+        info!("successfully settled invoice");
+        Ok(())
+    }
+
+    fn get_block_height(&self) -> Result<i64, Error> {
+        let info = self.call("getinfo", json!({}))?;
+        info.get("blockheight")
+            .and_then(Value::as_i64)
+            .ok_or(anyhow!("no blockheight found"))
+    }
+
+    pub fn wait_for_block(&self, options: &Options, num_of_blocks: i64) -> Result<(), Error> {
+        let target = self.get_block_height()? + num_of_blocks;
+        info!("waiting for block {}", target);
+        wait_for_height(options, target, || self.get_block_height())
+    }
+}
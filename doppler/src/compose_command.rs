@@ -0,0 +1,107 @@
+use anyhow::{anyhow, Error};
+use log::{debug, info};
+use std::{
+    fmt,
+    process::{Command, Stdio},
+};
+
+/// The compose implementation found on the host, every cluster command is routed through it
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ComposeCommand {
+    #[default]
+    DockerPlugin,
+    DockerStandalone,
+    PodmanCompose,
+    PodmanPlugin,
+}
+
+impl ComposeCommand {
+    // order of preference when detecting
+    const ALL: [ComposeCommand; 4] = [
+        ComposeCommand::DockerPlugin,
+        ComposeCommand::DockerStandalone,
+        ComposeCommand::PodmanCompose,
+        ComposeCommand::PodmanPlugin,
+    ];
+
+    /// Picks the first compose implementation that answers `version`,
+    /// `docker_dash` forces the standalone `docker-compose` binary
+    pub fn detect(docker_dash: bool) -> Result<ComposeCommand, Error> {
+        if docker_dash {
+            let compose = ComposeCommand::DockerStandalone;
+            if !compose.is_available() {
+                return Err(anyhow!(
+                    "`{}` was requested with --docker-dash but is not installed",
+                    compose
+                ));
+            }
+            return Ok(compose);
+        }
+        let compose = ComposeCommand::ALL
+            .into_iter()
+            .find(|compose| compose.is_available())
+            .ok_or(anyhow!(
+                "no compose implementation found, install one of: {}",
+                ComposeCommand::ALL
+                    .iter()
+                    .map(|compose| format!("`{}`", compose))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))?;
+        info!("using {} to run the cluster", compose);
+        Ok(compose)
+    }
+
+    pub fn program(&self) -> &'static str {
+        match self {
+            ComposeCommand::DockerPlugin => "docker",
+            ComposeCommand::DockerStandalone => "docker-compose",
+            ComposeCommand::PodmanCompose => "podman-compose",
+            ComposeCommand::PodmanPlugin => "podman",
+        }
+    }
+
+    /// Container engine CLI behind the compose implementation, for the commands compose doesn't cover
+    pub fn engine_program(&self) -> &'static str {
+        match self {
+            ComposeCommand::DockerPlugin | ComposeCommand::DockerStandalone => "docker",
+            ComposeCommand::PodmanCompose | ComposeCommand::PodmanPlugin => "podman",
+        }
+    }
+
+    /// Prepends the subcommand needed by the plugin style implementations
+    pub fn args<'a>(&self, additional_commands: Vec<&'a str>) -> Vec<&'a str> {
+        let mut commands = vec![];
+        if matches!(
+            self,
+            ComposeCommand::DockerPlugin | ComposeCommand::PodmanPlugin
+        ) {
+            commands.push("compose");
+        }
+        commands.extend(additional_commands);
+        commands
+    }
+
+    fn is_available(&self) -> bool {
+        let found = Command::new(self.program())
+            .args(self.args(vec!["version"]))
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map(|status| status.success())
+            .unwrap_or(false);
+        debug!("{} available: {}", self, found);
+        found
+    }
+}
+
+impl fmt::Display for ComposeCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ComposeCommand::DockerPlugin => write!(f, "docker compose"),
+            ComposeCommand::DockerStandalone => write!(f, "docker-compose"),
+            ComposeCommand::PodmanCompose => write!(f, "podman-compose"),
+            ComposeCommand::PodmanPlugin => write!(f, "podman compose"),
+        }
+    }
+}
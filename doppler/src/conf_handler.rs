@@ -18,10 +18,13 @@ use std::{
 };
 
 use crate::{
-    add_bitcoinds, add_coreln_nodes, add_eclair_nodes, add_external_lnd_nodes, add_lnd_nodes,
-    get_latest_polar_images, get_polar_images, get_supported_tool_images, new,
-    update_bash_alias_external, Bitcoind, Cln, CloneableHashMap, Eclair, Esplora, ImageInfo,
-    L1Node, L2Node, Lnd, NodeKind, SupportedTool, Tag, Tags, ToolImageInfo, NETWORK,
+    add_bitcoinds, add_coreln_nodes, add_eclair_nodes, add_external_bitcoinds,
+    add_external_cln_nodes, add_external_eclair_nodes, add_external_lnd_nodes, add_lnd_nodes,
+    check_reported_network, get_latest_polar_images, get_polar_images, get_supported_tool_images,
+    new, update_bash_alias_external, Bitcoind, CliBackend, Cln, CloneableHashMap, ComposeCommand,
+    ContainerBackend, CustomSignet, Eclair, Esplora, FaucetConfig, ImageInfo, L1Node, L2Node,
+    LnNodeKind, Lnd, Network, NodeKind, Partition, PortAllocator, PortRange, SpendBudget,
+    SupportedTool, Tag, Tags, ToolImageInfo, NETWORK,
 };
 
 #[derive(Subcommand)]
@@ -70,7 +73,7 @@ pub struct Options {
     pub lnd_nodes: Vec<Lnd>,
     pub eclair_nodes: Vec<Eclair>,
     pub cln_nodes: Vec<Cln>,
-    ports: Vec<i64>,
+    ports: PortAllocator,
     pub compose_path: Option<String>,
     pub services: IndexMap<String, Option<Service>>,
     pub main_thread_active: ThreadController,
@@ -78,24 +81,89 @@ pub struct Options {
     thread_handlers: Arc<Mutex<Vec<Thread>>>,
     pub aliases: bool,
     pub shell_type: Option<ShellType>,
-    pub docker_command: String,
+    pub docker_command: ComposeCommand,
+    // runs exec/stop/start on the containers, `docker compose` unless --docker-api is set
+    pub backend: Arc<dyn ContainerBackend>,
     pub loop_count: Arc<AtomicI64>,
     pub read_end_of_doppler_file: Arc<AtomicBool>,
     pub tags: Arc<Mutex<Tags>>,
     pub rest: bool,
     pub external_nodes_path: Option<String>,
     pub external_nodes: Option<Vec<ExternalNode>>,
+    pub external_bitcoinds: Vec<ExternalBitcoind>,
+    // run the local docker cluster and the external nodes side by side
+    pub hybrid: bool,
+    // set with --i-know-this-is-mainnet, otherwise mainnet nodes are refused
+    pub allow_mainnet: bool,
+    pub budget: SpendBudget,
+    // funds the l2 wallets when not on regtest, or instead of the miner when set on regtest
+    pub faucet: Option<FaucetConfig>,
+    pub faucet_confirmations: i64,
+    // set with --custom-signet, doppler signs the blocks of its own signet
+    pub custom_signet: Option<CustomSignet>,
+    // active PARTITIONs, HEAL needs them to clean up the rules on the other side
+    pub partitions: Arc<Mutex<Vec<Partition>>>,
+    // the script has NETEM lines, the node containers get NET_ADMIN to run tc themselves
+    pub net_admin: bool,
     pub ui_config_path: String,
-    pub network: String,
+    pub network: Network,
 }
 
 #[derive(Clone)]
 pub struct ExternalNode {
     pub node_alias: String,
+    pub kind: LnNodeKind,
     pub macaroon_path: String,
     pub api_endpoint: String,
     pub tls_cert_path: String,
     pub network: String,
+    pub p2p_address: String,
+    // only used by CoreLN nodes
+    pub rune: String,
+    // only used by Eclair nodes
+    pub api_password: String,
+}
+
+/// A bitcoind from the external nodes file, only reached over its json-rpc api
+#[derive(Clone, Debug)]
+pub struct ExternalBitcoind {
+    pub name: String,
+    pub rpc_url: String,
+    pub user: String,
+    pub password: String,
+    pub wallet: Option<String>,
+    pub network: String,
+}
+
+impl ExternalNode {
+    /// API_ENDPOINT can be a full url or just a host, in which case the node kind's default api port is used
+    pub fn get_api_url(&self) -> String {
+        if self.api_endpoint.starts_with("http") {
+            return self.api_endpoint.trim_end_matches('/').to_owned();
+        }
+        match self.kind {
+            LnNodeKind::Lnd => format!("https://{}:8080", self.api_endpoint),
+            LnNodeKind::Coreln => format!("https://{}:3010", self.api_endpoint),
+            LnNodeKind::Eclair => format!("http://{}:8080", self.api_endpoint),
+        }
+    }
+
+    /// Host and port other nodes connect to, P2P_ADDRESS or the api's host on the default p2p port
+    pub fn get_p2p_address(&self) -> (String, String) {
+        if let Some((host, port)) = self.p2p_address.rsplit_once(':') {
+            return (host.to_owned(), port.to_owned());
+        }
+        if !self.p2p_address.is_empty() {
+            return (self.p2p_address.clone(), "9735".to_owned());
+        }
+        let api_host = self
+            .api_endpoint
+            .split("://")
+            .last()
+            .and_then(|address| address.split(['/', ':']).next())
+            .unwrap_or_default();
+        (api_host.to_owned(), "9735".to_owned())
+    }
 }
 
 #[derive(Default, Debug, Clone)]
@@ -119,16 +187,18 @@ impl ThreadController {
 }
 
 impl Options {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        docker_dash: bool,
+        docker_command: ComposeCommand,
         ui_config_path: String,
         app_sub_commands: Option<AppSubCommands>,
         connection: Connection,
         mut rest: bool,
         external_nodes_path: Option<String>,
-        network: String,
+        hybrid: bool,
+        network: Network,
+        port_range: PortRange,
     ) -> Self {
-        let starting_port = vec![9089];
         let (aliases, shell_type) = if app_sub_commands.is_some() {
             if let Some(AppSubCommands::DetailedCommand(sub_commands)) = app_sub_commands {
                 (true, sub_commands.shell_type)
@@ -139,11 +209,6 @@ impl Options {
             (true, Some(ShellType::default()))
         };
 
-        let docker_command = if docker_dash {
-            "docker-compose"
-        } else {
-            "docker"
-        };
         let latest_polar_images = match get_latest_polar_images() {
             Ok(images) => images,
             Err(err) => panic!("error pulling down latest images: {}", err),
@@ -152,10 +217,13 @@ impl Options {
             Ok(images) => images,
             Err(err) => panic!("error pulling down images: {}", err),
         };
-        if external_nodes_path.is_some() {
+        // external nodes are always talked to over their apis, in hybrid mode the local nodes keep using the cli unless --rest is set
+        if external_nodes_path.is_some() && !hybrid {
             rest = true;
         }
         let default_tool_images = get_supported_tool_images();
+        let tags = new(connection);
+        let ports = PortAllocator::new(port_range, tags.get_connection());
         Self {
             default_images: latest_polar_images,
             default_tool_images,
@@ -166,7 +234,7 @@ impl Options {
             eclair_nodes: vec::Vec::new(),
             cln_nodes: vec::Vec::new(),
             esplora: vec::Vec::new(),
-            ports: starting_port,
+            ports,
             compose_path: None,
             services: indexmap::IndexMap::new(),
             main_thread_active: ThreadController::new(true),
@@ -174,13 +242,23 @@ impl Options {
             thread_handlers: Arc::new(Mutex::new(Vec::new())),
             aliases,
             shell_type,
-            docker_command: docker_command.to_owned(),
+            docker_command,
+            backend: Arc::new(CliBackend),
             loop_count: Arc::new(AtomicI64::new(0)),
             read_end_of_doppler_file: Arc::new(AtomicBool::new(true)),
-            tags: Arc::new(Mutex::new(new(connection))),
+            tags: Arc::new(Mutex::new(tags)),
             rest,
             external_nodes_path,
             external_nodes: None,
+            external_bitcoinds: vec::Vec::new(),
+            hybrid,
+            allow_mainnet: false,
+            budget: SpendBudget::default(),
+            faucet: None,
+            faucet_confirmations: 1,
+            custom_signet: None,
+            partitions: Arc::new(Mutex::new(Vec::new())),
+            net_admin: false,
             ui_config_path,
             network,
         }
@@ -215,11 +293,20 @@ impl Options {
     pub fn get_thread_handlers(&self) -> Arc<Mutex<Vec<Thread>>> {
         self.thread_handlers.clone()
     }
-    pub fn new_port(&mut self) -> i64 {
-        let last_port = self.ports.last().unwrap();
-        let next_port = last_port + 1;
-        self.ports.push(next_port);
-        next_port
+    pub fn new_port(&mut self, service: &str, container_port: &str) -> Result<i64, Error> {
+        self.ports.allocate(service, container_port)
+    }
+    pub fn get_assigned_port(&self, service: &str, container_port: &str) -> Option<i64> {
+        self.ports
+            .get_assignment(service, container_port)
+            .map(|assignment| assignment.host_port)
+    }
+    pub fn load_ports(&mut self) -> Result<(), Error> {
+        self.ports.load_assignments()
+    }
+    pub fn prune_ports(&mut self) -> Result<(), Error> {
+        let services: Vec<&str> = self.services.keys().map(String::as_str).collect();
+        self.ports.prune(&services)
     }
     pub fn is_known_polar_image(&self, kind: NodeKind, name: &str, tag: &str) -> bool {
         match self.known_polar_images.get(kind) {
@@ -232,7 +319,7 @@ impl Options {
     }
     pub fn save_compose(
         &mut self,
-        docker_command: String,
+        docker_command: ComposeCommand,
         file_path: &str,
     ) -> Result<(), io::Error> {
         let full_path =
@@ -312,21 +399,48 @@ impl Options {
                 io::Error::new(ErrorKind::NotFound, e)
             })?;
         let mut external_nodes: Vec<ExternalNode> = vec![];
+        let mut external_bitcoinds: Vec<ExternalBitcoind> = vec![];
         let conf = conf_parser::processer::read_to_file_conf_mut(&file)?;
         for node in conf.sections.clone() {
             if node.0 == "*placeholder*" {
                 continue;
             }
+            // nodes without a TYPE are LND nodes, which was the only supported kind before
+            let node_type = node.1.get_property("TYPE");
+            if node_type.trim().eq_ignore_ascii_case("bitcoind") {
+                let wallet = node.1.get_property("WALLET");
+                external_bitcoinds.push(ExternalBitcoind {
+                    name: node.0,
+                    rpc_url: node.1.get_property("RPC_URL"),
+                    user: node.1.get_property("RPC_USER"),
+                    password: node.1.get_property("RPC_PASSWORD"),
+                    wallet: Some(wallet).filter(|wallet| !wallet.is_empty()),
+                    network: node.1.get_property("NETWORK"),
+                });
+                continue;
+            }
+            let kind = if node_type.is_empty() {
+                LnNodeKind::Lnd
+            } else {
+                LnNodeKind::try_from(node_type.as_str())
+                    .map_err(|e| anyhow!("external node {}: {}", node.0, e))?
+            };
             external_nodes.push(ExternalNode {
                 node_alias: node.0,
+                kind,
                 macaroon_path: node.1.get_property("ADMIN_MACAROON_PATH"),
                 api_endpoint: node.1.get_property("API_ENDPOINT"),
                 tls_cert_path: node.1.get_property("TLS_CERT_PATH"),
                 network: node.1.get_property("NETWORK"),
+                p2p_address: node.1.get_property("P2P_ADDRESS"),
+                rune: node.1.get_property("RUNE"),
+                api_password: node.1.get_property("API_PASSWORD"),
             })
         }
         self.external_nodes = Some(external_nodes);
-        if self.aliases {
+        self.external_bitcoinds = external_bitcoinds;
+        // in hybrid mode the external aliases are written along with the local cluster's
+        if self.aliases && !self.hybrid {
             update_bash_alias_external(self)?;
         }
         Ok(())
@@ -383,24 +497,50 @@ impl Options {
         }
         Ok(())
     }
+    /// Re-reads the pubkey (and rune for CoreLN) of a single node, ie. after its container was recreated
+    pub fn refresh_l2_node(&mut self, name: &str) -> Result<(), Error> {
+        let options_clone = self.clone();
+        if let Some(lnd) = self
+            .lnd_nodes
+            .iter_mut()
+            .find(|node| node.get_name() == name)
+        {
+            lnd.add_pubkey(&options_clone);
+        } else if let Some(eclair) = self
+            .eclair_nodes
+            .iter_mut()
+            .find(|node| node.get_name() == name)
+        {
+            eclair.add_pubkey(&options_clone);
+        } else if let Some(coreln) = self
+            .cln_nodes
+            .iter_mut()
+            .find(|node| node.get_name() == name)
+        {
+            coreln.add_pubkey(&options_clone);
+            coreln.add_rune(&options_clone)?;
+        } else {
+            return Err(anyhow!("node not found"));
+        }
+        Ok(())
+    }
     pub fn get_bitcoind_by_name(&self, name: &str) -> Result<&Bitcoind, Error> {
         let btcd = self
             .bitcoinds
             .iter()
             .find(|node| node.get_name() == *name)
-            .unwrap_or_else(|| panic!("invalid node name: {:?}", name));
+            .ok_or(anyhow!("no bitcoind named {}", name))?;
         Ok(btcd)
     }
     pub fn load_bitcoinds(&mut self) -> Result<(), Error> {
         add_bitcoinds(self)?;
         Ok(())
     }
+    pub fn load_external_bitcoinds(&mut self) -> Result<(), Error> {
+        add_external_bitcoinds(self)
+    }
     pub fn load_lnds(&mut self) -> Result<(), Error> {
-        if let Some(_) = self.external_nodes.clone() {
-            add_external_lnd_nodes(self)
-        } else {
-            add_lnd_nodes(self)
-        }
+        add_lnd_nodes(self)
     }
     pub fn load_eclairs(&mut self) -> Result<(), Error> {
         add_eclair_nodes(self)
@@ -408,6 +548,63 @@ impl Options {
     pub fn load_coreln(&mut self) -> Result<(), Error> {
         add_coreln_nodes(self)
     }
+    /// Adds the nodes from the external nodes file next to any nodes already loaded from the local cluster
+    pub fn load_external_l2_nodes(&mut self) -> Result<(), Error> {
+        let external_nodes = self
+            .external_nodes
+            .clone()
+            .ok_or(anyhow!("no external nodes file has been loaded"))?;
+        if let Some(node) = external_nodes
+            .iter()
+            .find(|node| self.get_l2_by_name(&node.node_alias).is_ok())
+        {
+            return Err(anyhow!(
+                "external node {} has the same name as a node in the local cluster",
+                node.node_alias
+            ));
+        }
+        add_external_lnd_nodes(self)?;
+        add_external_cln_nodes(self)?;
+        add_external_eclair_nodes(self)
+    }
+    /// Asks every external node which network it is on, a NETWORK in the external nodes file can be missing or wrong
+    pub fn check_external_networks(&self) -> Result<(), Error> {
+        for bitcoind in self.bitcoinds.iter().filter(|bitcoind| bitcoind.external) {
+            let chain = bitcoind
+                .call(self, "getblockchaininfo", &[])
+                .and_then(|info| {
+                    info["chain"]
+                        .as_str()
+                        .map(str::to_owned)
+                        .ok_or(anyhow!("getblockchaininfo has no chain: {}", info))
+                });
+            check_reported_network(self.allow_mainnet, &bitcoind.name, chain)?;
+        }
+        for node in self.get_l2_nodes() {
+            if self.is_external_node(node.get_name()) {
+                check_reported_network(
+                    self.allow_mainnet,
+                    node.get_name(),
+                    node.get_network(self),
+                )?;
+            }
+        }
+        Ok(())
+    }
+    pub fn is_external_node(&self, name: &str) -> bool {
+        self.external_nodes
+            .as_ref()
+            .is_some_and(|nodes| nodes.iter().any(|node| node.node_alias == name))
+            || self.external_bitcoinds.iter().any(|node| node.name == name)
+    }
+    /// Only external nodes are used, no local docker cluster is run
+    pub fn is_external_only(&self) -> bool {
+        self.external_nodes_path.is_some() && !self.hybrid
+    }
+    /// Whether the cluster's miners can produce blocks on demand, regtest or a custom signet
+    pub fn can_mine(&self) -> bool {
+        self.network == Network::Regtest || self.custom_signet.is_some()
+    }
     pub fn save_tag(&self, tag: &Tag) -> Result<(), Error> {
         self.tags
             .lock()
@@ -420,6 +617,53 @@ impl Options {
     }
 }
 
+#[cfg(test)]
+impl Options {
+    // no polar images and an in memory db, the tests add the nodes they need
+    pub(crate) fn for_tests(network: Network) -> Self {
+        let tags = new(crate::create_db(":memory:".to_owned()).unwrap());
+        let ports = PortAllocator::new(PortRange::default(), tags.get_connection());
+        Self {
+            default_images: CloneableHashMap::new(),
+            default_tool_images: CloneableHashMap::new(),
+            known_polar_images: CloneableHashMap::new(),
+            images: vec::Vec::new(),
+            bitcoinds: vec::Vec::new(),
+            lnd_nodes: vec::Vec::new(),
+            eclair_nodes: vec::Vec::new(),
+            cln_nodes: vec::Vec::new(),
+            esplora: vec::Vec::new(),
+            ports,
+            compose_path: Some("docker-compose.yaml".to_owned()),
+            services: indexmap::IndexMap::new(),
+            main_thread_active: ThreadController::new(true),
+            main_thread_paused: ThreadController::new(false),
+            thread_handlers: Arc::new(Mutex::new(Vec::new())),
+            aliases: false,
+            shell_type: None,
+            docker_command: ComposeCommand::default(),
+            backend: Arc::new(CliBackend),
+            loop_count: Arc::new(AtomicI64::new(0)),
+            read_end_of_doppler_file: Arc::new(AtomicBool::new(true)),
+            tags: Arc::new(Mutex::new(tags)),
+            rest: false,
+            external_nodes_path: None,
+            external_nodes: None,
+            external_bitcoinds: vec::Vec::new(),
+            hybrid: false,
+            allow_mainnet: false,
+            budget: SpendBudget::default(),
+            faucet: None,
+            faucet_confirmations: 1,
+            custom_signet: None,
+            partitions: Arc::new(Mutex::new(Vec::new())),
+            net_admin: false,
+            ui_config_path: String::new(),
+            network,
+        }
+    }
+}
+
 pub fn get_absolute_path(relative_path: &str) -> Result<PathBuf, Error> {
     let current_dir = std::env::current_dir()?;
     let absolute_path = current_dir.join(relative_path);
@@ -450,3 +694,180 @@ pub fn create_folder(destination_directory: &str) -> Result<(), anyhow::Error> {
     create_dir_all(destination_directory)?;
     Ok(())
 }
+
+/// Applies the `key=value` overrides from a node definition on top of its config template.
+/// Keys already in the template are updated where they are found, a new dotted key goes into
+/// the section matching its prefix (ie. `protocol.zero-conf` into `[protocol]`) and anything
+/// else into `default_section`. An override without `=` is written as a flag.
+pub fn set_conf_overrides(
+    conf: &mut FileConf,
+    overrides: &[String],
+    default_section: &str,
+) -> Result<(), Error> {
+    for conf_override in overrides {
+        let (key, value) = match conf_override.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => (conf_override.trim(), ""),
+        };
+        if key.is_empty() {
+            return Err(anyhow!("invalid config override: {}", conf_override));
+        }
+        let mut found = false;
+        for section in conf.sections.values_mut() {
+            let existing_keys: Vec<String> = section
+                .clone()
+                .get_properties()
+                .into_keys()
+                .filter(|existing_key| existing_key.trim() == key)
+                .collect();
+            for existing_key in existing_keys {
+                section.set_property(&existing_key, value);
+                found = true;
+            }
+        }
+        if found {
+            debug!("overrode config value {}={}", key, value);
+            continue;
+        }
+        let section_name = key
+            .split_once('.')
+            .and_then(|(prefix, _)| {
+                conf.sections
+                    .keys()
+                    .find(|section_name| section_name.eq_ignore_ascii_case(prefix))
+                    .cloned()
+            })
+            .unwrap_or(default_section.to_owned());
+        conf.sections
+            .entry(section_name)
+            .or_default()
+            .set_property(key, value);
+        debug!("added config value {}={}", key, value);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn load_external(name: &str, content: &str) -> Result<Options, Error> {
+        let path =
+            std::env::temp_dir().join(format!("doppler-{}-{}.conf", name, std::process::id()));
+        fs::write(&path, content)?;
+        let mut options = Options::for_tests(Network::Signet);
+        let loaded = options.load_external_nodes(path.to_str().unwrap());
+        fs::remove_file(&path)?;
+        loaded.map(|_| options)
+    }
+
+    #[test]
+    fn loads_every_external_node_type() {
+        let options = load_external(
+            "external-types",
+            r#"
+[exchange]
+ADMIN_MACAROON_PATH=external_nodes/exchange/admin.macaroon
+API_ENDPOINT=exchange.example.com
+NETWORK=signet
+
+[merchant]
+TYPE=coreln
+RUNE=abc
+API_ENDPOINT=https://merchant.example.com:3011/
+P2P_ADDRESS=merchant.example.com:9736
+NETWORK=signet
+
+[customer]
+TYPE=eclair
+API_PASSWORD=secret
+API_ENDPOINT=customer.example.com
+NETWORK=signet
+
+[bd1]
+TYPE=bitcoind
+RPC_URL=http://bitcoind.example.com:38332
+RPC_USER=doppler
+RPC_PASSWORD=pass
+NETWORK=signet
+"#,
+        )
+        .unwrap();
+        // sections come back in no particular order
+        let mut nodes = options.external_nodes.clone().unwrap();
+        nodes.sort_by(|a, b| b.node_alias.cmp(&a.node_alias));
+        let kinds: Vec<(&str, LnNodeKind)> = nodes
+            .iter()
+            .map(|node| (node.node_alias.as_str(), node.kind.clone()))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("merchant", LnNodeKind::Coreln),
+                ("exchange", LnNodeKind::Lnd),
+                ("customer", LnNodeKind::Eclair),
+            ]
+        );
+        let (merchant, exchange, customer) = (&nodes[0], &nodes[1], &nodes[2]);
+        assert_eq!(exchange.get_api_url(), "https://exchange.example.com:8080");
+        assert_eq!(
+            exchange.get_p2p_address(),
+            ("exchange.example.com".to_owned(), "9735".to_owned())
+        );
+        assert_eq!(merchant.rune, "abc");
+        assert_eq!(merchant.get_api_url(), "https://merchant.example.com:3011");
+        assert_eq!(
+            merchant.get_p2p_address(),
+            ("merchant.example.com".to_owned(), "9736".to_owned())
+        );
+        assert_eq!(customer.api_password, "secret");
+        assert_eq!(customer.get_api_url(), "http://customer.example.com:8080");
+
+        assert_eq!(options.external_bitcoinds.len(), 1);
+        assert_eq!(options.external_bitcoinds[0].name, "bd1");
+        assert_eq!(options.external_bitcoinds[0].wallet, None);
+        assert!(options.is_external_node("merchant"));
+        assert!(options.is_external_node("bd1"));
+        assert!(!options.is_external_node("lnd1"));
+    }
+
+    #[test]
+    fn rejects_unknown_external_node_types() {
+        let Err(error) = load_external(
+            "external-unknown",
+            "[exchange]\nTYPE=ldk\nAPI_ENDPOINT=exchange.example.com\n",
+        ) else {
+            panic!("loaded an unknown node type");
+        };
+        assert_eq!(
+            error.to_string(),
+            "external node exchange: unknown ln node type: ldk"
+        );
+    }
+
+    #[test]
+    fn hybrid_runs_keep_the_local_cluster() {
+        let mut options = load_external(
+            "external-hybrid",
+            "[lnd1]\nADMIN_MACAROON_PATH=admin.macaroon\nAPI_ENDPOINT=lnd1.example.com\n",
+        )
+        .unwrap();
+        options.external_nodes_path = Some("external_nodes/info.conf".to_owned());
+        assert!(options.is_external_only());
+        options.hybrid = true;
+        assert!(!options.is_external_only());
+
+        // an external node can't shadow a node of the local cluster
+        options.lnd_nodes = vec![Lnd {
+            name: "lnd1".to_owned(),
+            ..Default::default()
+        }];
+        let error = options.load_external_l2_nodes().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "external node lnd1 has the same name as a node in the local cluster"
+        );
+        assert_eq!(options.lnd_nodes.len(), 1);
+    }
+}
@@ -0,0 +1,398 @@
+use crate::{run_compose_command, Options};
+use anyhow::{anyhow, Error};
+use log::{debug, info};
+use serde_json::{json, Value};
+use std::{
+    env,
+    io::{Read, Write},
+    net::TcpStream,
+    os::unix::{net::UnixStream, process::ExitStatusExt},
+    process::{ExitStatus, Output},
+    str::from_utf8,
+    thread,
+    time::{Duration, Instant},
+};
+
+const DEFAULT_DOCKER_SOCKET: &str = "/var/run/docker.sock";
+// the compose cli waits on the exec without a limit, this only guards against a wedged engine
+const EXEC_EXIT_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Runs the per node commands (exec, stop, start) against the cluster's containers
+pub trait ContainerBackend: Send + Sync {
+    fn exec(
+        &self,
+        options: &Options,
+        command_name: &str,
+        container: &str,
+        user: Option<&str>,
+        cmd: &[&str],
+    ) -> Result<Output, Error>;
+    fn stop(&self, options: &Options, container: &str) -> Result<Output, Error>;
+    fn start(&self, options: &Options, container: &str) -> Result<Output, Error>;
+    fn kill(&self, options: &Options, container: &str) -> Result<Output, Error>;
+}
+
+/// Spawns a `docker compose` process per call
+#[derive(Default, Debug, Clone)]
+pub struct CliBackend;
+
+impl ContainerBackend for CliBackend {
+    fn exec(
+        &self,
+        options: &Options,
+        command_name: &str,
+        container: &str,
+        user: Option<&str>,
+        cmd: &[&str],
+    ) -> Result<Output, Error> {
+        let compose_path = options.compose_path.as_ref().unwrap();
+        let mut commands = vec!["-f", compose_path.as_str(), "exec"];
+        if let Some(user) = user {
+            commands.extend(["--user", user]);
+        }
+        commands.push(container);
+        commands.extend(cmd);
+        run_compose_command(options, command_name.to_owned(), commands)
+    }
+
+    fn stop(&self, options: &Options, container: &str) -> Result<Output, Error> {
+        let compose_path = options.compose_path.as_ref().unwrap();
+        let commands = vec!["-f", compose_path.as_str(), "stop", container];
+        run_compose_command(options, "stop".to_owned(), commands)
+    }
+
+    fn start(&self, options: &Options, container: &str) -> Result<Output, Error> {
+        let compose_path = options.compose_path.as_ref().unwrap();
+        let commands = vec!["-f", compose_path.as_str(), "start", container];
+        run_compose_command(options, "start".to_owned(), commands)
+    }
+
+    fn kill(&self, options: &Options, container: &str) -> Result<Output, Error> {
+        let compose_path = options.compose_path.as_ref().unwrap();
+        let commands = vec![
+            "-f",
+            compose_path.as_str(),
+            "kill",
+            "-s",
+            "SIGKILL",
+            container,
+        ];
+        run_compose_command(options, "kill".to_owned(), commands)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum EngineHost {
+    Unix(String),
+    Tcp(String),
+}
+
+/// Talks to the Docker Engine API directly, over the socket set in DOCKER_HOST or /var/run/docker.sock
+#[derive(Debug, Clone)]
+pub struct EngineBackend {
+    host: EngineHost,
+}
+
+impl EngineBackend {
+    pub fn from_env() -> Result<EngineBackend, Error> {
+        let docker_host = env::var("DOCKER_HOST").unwrap_or_default();
+        let host = if docker_host.is_empty() {
+            EngineHost::Unix(DEFAULT_DOCKER_SOCKET.to_owned())
+        } else if let Some(path) = docker_host.strip_prefix("unix://") {
+            EngineHost::Unix(path.to_owned())
+        } else if let Some(address) = docker_host.strip_prefix("tcp://") {
+            EngineHost::Tcp(address.to_owned())
+        } else {
+            return Err(anyhow!(
+                "unsupported DOCKER_HOST {}, expected unix:// or tcp://",
+                docker_host
+            ));
+        };
+        let backend = EngineBackend { host };
+        let (status, _) = backend
+            .request("GET", "/_ping", None)
+            .map_err(|e| anyhow!("docker engine not reachable @ {:?}: {}", backend.host, e))?;
+        if status != 200 {
+            return Err(anyhow!(
+                "docker engine @ {:?} answered ping with {}",
+                backend.host,
+                status
+            ));
+        }
+        info!("using docker engine api @ {:?}", backend.host);
+        Ok(backend)
+    }
+
+    fn request(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<Value>,
+    ) -> Result<(u16, Vec<u8>), Error> {
+        let body = body.map(|body| body.to_string()).unwrap_or_default();
+        let request = format!(
+            "{} {} HTTP/1.1\r\nHost: docker\r\nConnection: close\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        );
+        let mut response = vec![];
+        match &self.host {
+            EngineHost::Unix(path) => {
+                let mut stream = UnixStream::connect(path)?;
+                stream.write_all(request.as_bytes())?;
+                stream.read_to_end(&mut response)?;
+            }
+            EngineHost::Tcp(address) => {
+                let mut stream = TcpStream::connect(address)?;
+                stream.write_all(request.as_bytes())?;
+                stream.read_to_end(&mut response)?;
+            }
+        }
+        parse_response(&response)
+    }
+
+    fn request_json(&self, method: &str, path: &str, body: Option<Value>) -> Result<Value, Error> {
+        let (status, body) = self.request(method, path, body)?;
+        if !(200..300).contains(&status) {
+            return Err(anyhow!(
+                "{} {} failed with {}: {}",
+                method,
+                path,
+                status,
+                String::from_utf8_lossy(&body)
+            ));
+        }
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    // the stream closes as the process exits, but the exec can still report Running for a while after,
+    // e.g. when the process left children holding the streams open
+    fn wait_for_exit_code(&self, container: &str, exec_id: &str) -> Result<i64, Error> {
+        let deadline = Instant::now() + EXEC_EXIT_TIMEOUT;
+        let mut interval = Duration::from_millis(50);
+        loop {
+            let inspect = self.request_json("GET", &format!("/exec/{}/json", exec_id), None)?;
+            match inspect["Running"].as_bool() {
+                Some(false) => {
+                    return inspect["ExitCode"].as_i64().ok_or(anyhow!(
+                        "exec in {} finished without an exit code",
+                        container
+                    ))
+                }
+                Some(true) => {}
+                None => return Err(anyhow!("invalid exec state for {}: {}", container, inspect)),
+            }
+            if Instant::now() >= deadline {
+                return Err(anyhow!(
+                    "exec in {} still running after {}s",
+                    container,
+                    EXEC_EXIT_TIMEOUT.as_secs()
+                ));
+            }
+            thread::sleep(interval);
+            interval = (interval * 2).min(Duration::from_secs(1));
+        }
+    }
+
+    fn container_action(
+        &self,
+        container: &str,
+        action: &str,
+        query: &str,
+    ) -> Result<Output, Error> {
+        let path = format!("/containers/{}/{}{}", container, action, query);
+        info!("({}): POST {}", action, path);
+        let (status, body) = self.request("POST", &path, None)?;
+        // 304 means the container already was in the requested state
+        let code = if (200..300).contains(&status) || status == 304 {
+            0
+        } else {
+            1
+        };
+        Ok(Output {
+            status: ExitStatus::from_raw(code << 8),
+            stdout: vec![],
+            stderr: if code == 0 { vec![] } else { body },
+        })
+    }
+}
+
+impl ContainerBackend for EngineBackend {
+    fn exec(
+        &self,
+        _options: &Options,
+        command_name: &str,
+        container: &str,
+        user: Option<&str>,
+        cmd: &[&str],
+    ) -> Result<Output, Error> {
+        info!("({}): exec {} {}", command_name, container, cmd.join(" "));
+        let created = self.request_json(
+            "POST",
+            &format!("/containers/{}/exec", container),
+            Some(json!({
+                "AttachStdout": true,
+                "AttachStderr": true,
+                "Tty": false,
+                "User": user.unwrap_or_default(),
+                "Cmd": cmd,
+            })),
+        )?;
+        let exec_id = created["Id"]
+            .as_str()
+            .ok_or(anyhow!("no exec id returned for {}", container))?
+            .to_owned();
+
+        let (status, stream) = self.request(
+            "POST",
+            &format!("/exec/{}/start", exec_id),
+            Some(json!({ "Detach": false, "Tty": false })),
+        )?;
+        if status != 200 {
+            return Err(anyhow!(
+                "failed to start exec in {}: {}",
+                container,
+                String::from_utf8_lossy(&stream)
+            ));
+        }
+        let (stdout, stderr) = demux_stream(&stream);
+
+        let exit_code = self.wait_for_exit_code(container, &exec_id)?;
+
+        debug!(
+            "output.stdout: {}, output.stderr: {}",
+            from_utf8(&stdout)?,
+            from_utf8(&stderr)?
+        );
+        Ok(Output {
+            status: ExitStatus::from_raw((exit_code as i32) << 8),
+            stdout,
+            stderr,
+        })
+    }
+
+    fn stop(&self, _options: &Options, container: &str) -> Result<Output, Error> {
+        self.container_action(container, "stop", "")
+    }
+
+    fn start(&self, _options: &Options, container: &str) -> Result<Output, Error> {
+        self.container_action(container, "start", "")
+    }
+
+    fn kill(&self, _options: &Options, container: &str) -> Result<Output, Error> {
+        self.container_action(container, "kill", "?signal=SIGKILL")
+    }
+}
+
+fn parse_response(response: &[u8]) -> Result<(u16, Vec<u8>), Error> {
+    let header_end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or(anyhow!("invalid response from docker engine"))?;
+    let headers = from_utf8(&response[..header_end])?;
+    let body = &response[header_end + 4..];
+    let status = headers
+        .lines()
+        .next()
+        .and_then(|status_line| status_line.split_whitespace().nth(1))
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or(anyhow!("invalid status line from docker engine"))?;
+    let is_chunked = headers.lines().any(|header| {
+        let header = header.to_lowercase();
+        header.starts_with("transfer-encoding") && header.contains("chunked")
+    });
+    if is_chunked {
+        Ok((status, decode_chunked(body)?))
+    } else {
+        Ok((status, body.to_vec()))
+    }
+}
+
+fn decode_chunked(mut body: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut decoded = vec![];
+    loop {
+        let line_end = body
+            .windows(2)
+            .position(|window| window == b"\r\n")
+            .ok_or(anyhow!("invalid chunked body from docker engine"))?;
+        let size_line = from_utf8(&body[..line_end])?;
+        let size = usize::from_str_radix(size_line.split(';').next().unwrap_or("").trim(), 16)?;
+        if size == 0 {
+            return Ok(decoded);
+        }
+        let chunk_start = line_end + 2;
+        let chunk_end = chunk_start + size;
+        if chunk_end > body.len() {
+            return Err(anyhow!("truncated chunked body from docker engine"));
+        }
+        decoded.extend_from_slice(&body[chunk_start..chunk_end]);
+        body = &body[(chunk_end + 2).min(body.len())..];
+    }
+}
+
+// without a tty docker multiplexes the streams, each frame has an 8 byte header:
+// [stream type, 0, 0, 0, size (4 bytes, big endian)]
+fn demux_stream(mut stream: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let mut stdout = vec![];
+    let mut stderr = vec![];
+    while stream.len() >= 8 {
+        let size = u32::from_be_bytes([stream[4], stream[5], stream[6], stream[7]]) as usize;
+        let end = (8 + size).min(stream.len());
+        match stream[0] {
+            2 => stderr.extend_from_slice(&stream[8..end]),
+            _ => stdout.extend_from_slice(&stream[8..end]),
+        }
+        stream = &stream[end..];
+    }
+    (stdout, stderr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(stream: u8, data: &[u8]) -> Vec<u8> {
+        let mut frame = vec![stream, 0, 0, 0];
+        frame.extend((data.len() as u32).to_be_bytes());
+        frame.extend(data);
+        frame
+    }
+
+    #[test]
+    fn parses_plain_and_chunked_responses() {
+        let (status, body) =
+            parse_response(b"HTTP/1.1 201 Created\r\nContent-Length: 9\r\n\r\n{\"Id\":1}\n")
+                .unwrap();
+        assert_eq!(status, 201);
+        assert_eq!(body, b"{\"Id\":1}\n");
+
+        let (status, body) = parse_response(
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n7;ext=1\r\n, world\r\n0\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(status, 200);
+        assert_eq!(body, b"hello, world");
+    }
+
+    #[test]
+    fn rejects_broken_responses() {
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\n").is_err());
+        assert!(parse_response(b"garbage\r\n\r\n").is_err());
+        assert!(decode_chunked(b"a\r\nshort\r\n").is_err());
+        assert!(decode_chunked(b"zz\r\n").is_err());
+    }
+
+    #[test]
+    fn splits_the_multiplexed_streams() {
+        let mut stream = frame(1, b"out ");
+        stream.extend(frame(2, b"err"));
+        stream.extend(frame(1, b"more"));
+        // a frame cut short keeps what arrived
+        stream.extend(&frame(2, b"cut off")[..10]);
+        let (stdout, stderr) = demux_stream(&stream);
+        assert_eq!(stdout, b"out more");
+        assert_eq!(stderr, b"errcu");
+    }
+}
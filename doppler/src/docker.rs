@@ -1,8 +1,13 @@
 use crate::{
-    create_ui_config_files, get_absolute_path, pair_bitcoinds, L1Node, L2Node, NodeCommand, Options,
+    create_ui_config_files, fund_l2_nodes_from_faucet, get_absolute_path, pair_bitcoinds,
+    start_miner_schedules, start_signet_miner, ImageInfo, L1Node, L2Node, LnNodeKind, NodeCommand,
+    Options,
 };
 use anyhow::{anyhow, Error};
-use log::{debug, error, info};
+use docker_compose_types::{
+    AdvancedVolumes, Deploy, Environment, Limits, Resources, Service, SingleValue, Volumes,
+};
+use log::{debug, error, info, warn};
 use std::fs::{self, File, OpenOptions};
 use std::io::Write;
 use std::os::unix::prelude::PermissionsExt;
@@ -14,6 +19,76 @@ use std::time::Duration;
 
 pub const NETWORK: &str = "doppler";
 
+/// Extra docker settings for a single node's service, set in the doppler file on the node definition
+#[derive(Debug, Default, Clone)]
+pub struct ServiceSettings {
+    pub cpus: Option<String>,
+    pub memory: Option<String>,
+    // `KEY=value` pairs
+    pub environment: Vec<String>,
+    // `host_path:container_path[:mode]` mounts
+    pub volumes: Vec<String>,
+}
+
+impl ServiceSettings {
+    pub fn apply(&self, service: &mut Service) {
+        if self.cpus.is_some() || self.memory.is_some() {
+            service.deploy = Some(Deploy {
+                resources: Some(Resources {
+                    limits: Some(Limits {
+                        cpus: self.cpus.clone(),
+                        memory: self.memory.clone(),
+                    }),
+                    reservations: None,
+                }),
+                ..Default::default()
+            });
+        }
+        // merged into whichever form the service already uses, a key set on the node replaces the service's
+        for env_var in self.environment.iter() {
+            let (key, value) = env_var.split_once('=').unwrap_or((env_var, ""));
+            match &mut service.environment {
+                Environment::List(environment) => {
+                    environment.retain(|existing| existing.split('=').next() != Some(key));
+                    environment.push(env_var.clone());
+                }
+                Environment::KvPair(environment) => {
+                    environment.insert(key.to_owned(), Some(SingleValue::String(value.to_owned())));
+                }
+            }
+        }
+        for volume in self.volumes.iter() {
+            match &mut service.volumes {
+                Volumes::Simple(volumes) => volumes.push(volume.clone()),
+                Volumes::Advanced(volumes) => volumes.push(to_bind_mount(volume)),
+            }
+        }
+    }
+}
+
+// `host_path:container_path[:ro]` in the long volume syntax
+fn to_bind_mount(volume: &str) -> AdvancedVolumes {
+    let mut parts = volume.splitn(3, ':');
+    let source = parts.next().unwrap_or_default().to_owned();
+    let target = parts.next().unwrap_or_default().to_owned();
+    AdvancedVolumes {
+        source: Some(source),
+        target,
+        _type: "bind".to_owned(),
+        read_only: parts.next() == Some("ro"),
+        bind: None,
+        volume: None,
+        tmpfs: None,
+    }
+}
+
+/// Lets NETEM shape the node's traffic from inside its container, only added when the script uses NETEM
+pub fn add_net_admin(options: &Options, service: &mut Service) {
+    if options.net_admin && !service.cap_add.iter().any(|cap| cap == "NET_ADMIN") {
+        service.cap_add.push("NET_ADMIN".to_owned());
+    }
+}
+
 pub fn load_options_from_external_nodes(
     options: &mut Options,
     external_nodes_folder_path: &str,
@@ -21,19 +96,52 @@ pub fn load_options_from_external_nodes(
     //Skips any docker setup/calls, using external nodes instead
     options.load_external_nodes(external_nodes_folder_path)?;
     debug!("loaded {} file", external_nodes_folder_path);
-    options.load_lnds()?;
-    debug!("loaded lnds");
-    let network = options.external_nodes.clone().unwrap()[0].network.clone();
+    options.load_external_bitcoinds()?;
+    debug!("loaded external bitcoinds");
+    options.load_external_l2_nodes()?;
+    debug!("loaded external nodes");
+    options.check_external_networks()?;
+    let network = options
+        .external_nodes
+        .as_ref()
+        .and_then(|nodes| nodes.first())
+        .map(|node| node.network.clone())
+        .or(options
+            .external_bitcoinds
+            .first()
+            .map(|node| node.network.clone()))
+        .unwrap_or(options.network.to_string());
 
     create_ui_config_files(options, &network)?;
 
     Ok(())
 }
 
+/// Hybrid mode, adds the external nodes to the nodes of the local cluster
+pub fn add_external_nodes_to_cluster(
+    options: &mut Options,
+    external_nodes_path: &str,
+) -> Result<(), Error> {
+    options.load_external_nodes(external_nodes_path)?;
+    options.load_external_bitcoinds()?;
+    options.load_external_l2_nodes()?;
+    options.check_external_networks()?;
+    info!(
+        "added {} external nodes to the local cluster",
+        options
+            .external_nodes
+            .as_ref()
+            .map_or(0, |nodes| nodes.len())
+    );
+    Ok(())
+}
+
 pub fn load_options_from_compose(options: &mut Options, compose_path: &str) -> Result<(), Error> {
     options.compose_path = Some(compose_path.to_owned());
     options.load_compose()?;
     debug!("loaded {} file", options.docker_command);
+    options.load_ports()?;
+    debug!("loaded port assignments");
     options.load_bitcoinds()?;
     debug!("loaded bitcoinds");
     options.load_lnds()?;
@@ -45,14 +153,42 @@ pub fn load_options_from_compose(options: &mut Options, compose_path: &str) -> R
     Ok(())
 }
 
-pub fn add_commands(docker_command: String, additional_commands: Vec<&str>) -> Vec<&str> {
-    let mut commands = vec![];
-    if !docker_command.contains('-') {
-        commands.push("compose");
-    }
+enum ContainerCall<'a> {
+    Exec {
+        container: &'a str,
+        user: Option<&'a str>,
+        cmd: &'a [&'a str],
+    },
+    Stop(&'a str),
+    Start(&'a str),
+}
 
-    commands.extend(additional_commands);
-    commands
+// picks out the per container calls (`-f <compose> exec|stop|start ...`) the backend can handle,
+// anything else (up, restart, ...) stays with the compose command
+fn parse_container_call<'a>(commands: &'a [&'a str]) -> Option<ContainerCall<'a>> {
+    let args = match commands {
+        ["-f", _, args @ ..] => args,
+        args => args,
+    };
+    match args {
+        ["exec", "--user", user, container, cmd @ ..] if !cmd.is_empty() => {
+            Some(ContainerCall::Exec {
+                container,
+                user: Some(user),
+                cmd,
+            })
+        }
+        ["exec", container, cmd @ ..] if !container.starts_with('-') && !cmd.is_empty() => {
+            Some(ContainerCall::Exec {
+                container,
+                user: None,
+                cmd,
+            })
+        }
+        ["stop", container] => Some(ContainerCall::Stop(container)),
+        ["start", container] => Some(ContainerCall::Start(container)),
+        _ => None,
+    }
 }
 
 pub fn run_command(
@@ -60,15 +196,34 @@ pub fn run_command(
     command_name: String,
     commands: Vec<&str>,
 ) -> Result<Output, Error> {
-    let commands = add_commands(options.docker_command.clone(), commands);
+    match parse_container_call(&commands) {
+        Some(ContainerCall::Exec {
+            container,
+            user,
+            cmd,
+        }) => options
+            .backend
+            .exec(options, &command_name, container, user, cmd),
+        Some(ContainerCall::Stop(container)) => options.backend.stop(options, container),
+        Some(ContainerCall::Start(container)) => options.backend.start(options, container),
+        None => run_compose_command(options, command_name, commands),
+    }
+}
+
+pub fn run_compose_command(
+    options: &Options,
+    command_name: String,
+    commands: Vec<&str>,
+) -> Result<Output, Error> {
+    let commands = options.docker_command.args(commands);
 
     info!(
         "({}): {} {}",
         command_name,
-        options.docker_command,
+        options.docker_command.program(),
         commands.clone().join(" "),
     );
-    let output = Command::new(options.docker_command.clone())
+    let output = Command::new(options.docker_command.program())
         .args(commands)
         .output()?;
 
@@ -80,11 +235,29 @@ pub fn run_command(
     Ok(output)
 }
 
+/// Runs the docker/podman CLI directly, ie. for `run` and `inspect` which have no compose equivalent
+pub fn run_engine_command(
+    options: &Options,
+    command_name: &str,
+    commands: Vec<&str>,
+) -> Result<Output, Error> {
+    let program = options.docker_command.engine_program();
+    info!("({}): {} {}", command_name, program, commands.join(" "));
+    let output = Command::new(program).args(commands).output()?;
+    debug!(
+        "output.stdout: {}, output.stderr: {}",
+        from_utf8(&output.stdout)?,
+        from_utf8(&output.stderr)?
+    );
+    Ok(output)
+}
+
 pub fn run_cluster(options: &mut Options, compose_path: &str) -> Result<(), Error> {
     options.compose_path = Some(compose_path.to_owned());
+    options.prune_ports()?;
 
     options
-        .save_compose(options.docker_command.clone(), compose_path)
+        .save_compose(options.docker_command, compose_path)
         .map_err(|err| {
             anyhow!(
                 "Failed to save {} file @ {}: {}",
@@ -100,30 +273,88 @@ pub fn run_cluster(options: &mut Options, compose_path: &str) -> Result<(), Erro
     //simple wait for docker-compose to spin up
     thread::sleep(Duration::from_secs(6));
     pair_bitcoinds(options)?;
-    if options.network == "regtest" {
+    if options.can_mine() {
         mine_initial_blocks(options)?;
     }
     setup_l2_nodes(options)?;
-    create_ui_config_files(options, &options.network)
+    // external nodes are added after the local ones have been funded and connected
+    if let (true, Some(external_nodes_path)) = (options.hybrid, options.external_nodes_path.clone())
+    {
+        add_external_nodes_to_cluster(options, &external_nodes_path)?;
+    }
+    create_ui_config_files(options, options.network.as_str())
         .map_err(|e| anyhow!("error creating ui config: {}", e))?;
-    if options.aliases && options.external_nodes.is_none() {
+    if options.aliases && !options.is_external_only() {
         update_bash_alias(options).map_err(|e| anyhow!("error creating alias: {}", e))?;
     }
+    start_signet_miner(options)?;
+    start_miner_schedules(options);
 
     Ok(())
 }
 
 fn start_docker_compose(options: &Options) -> Result<(), Error> {
-    let commands: Vec<&str> = vec![
+    let mut commands: Vec<&str> = vec![
         "-f",
         options.compose_path.as_ref().unwrap().as_ref(),
         "up",
         "-d",
     ];
+    // rebuild images from local sources so changes on the branch are picked up
+    if options.images.iter().any(|image| image.is_build()) {
+        commands.push("--build");
+    }
     run_command(options, "start up".to_owned(), commands)?;
     Ok(())
 }
 
+/// Swaps the image of a running node's service, the container is recreated on the same data volume
+pub fn upgrade_container(
+    options: &mut Options,
+    container_name: &str,
+    image: &ImageInfo,
+) -> Result<(), Error> {
+    let compose_path = options.compose_path.clone().unwrap();
+    let service = options
+        .services
+        .get_mut(container_name)
+        .and_then(|service| service.as_mut())
+        .ok_or(anyhow!("no service found for container {}", container_name))?;
+    let previous_image = service.image.clone().unwrap_or_default();
+    service.image = Some(image.get_image());
+    service.build_ = image.get_build_step();
+    info!(
+        "upgrading {} from {} to {}",
+        container_name,
+        previous_image,
+        image.get_image()
+    );
+
+    let stop_commands = vec!["-f", &compose_path, "stop", container_name];
+    run_command(options, "stop".to_owned(), stop_commands)?;
+
+    options
+        .save_compose(options.docker_command, &compose_path)
+        .map_err(|err| anyhow!("Failed to save {} file: {}", options.docker_command, err))?;
+
+    // --no-deps keeps the rest of the cluster running while this container is recreated
+    let mut up_commands = vec!["-f", &compose_path, "up", "-d", "--no-deps"];
+    if image.is_build() {
+        up_commands.push("--build");
+    }
+    up_commands.push(container_name);
+    let output = run_command(options, "upgrade".to_owned(), up_commands)?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "failed to start {} with image {}: {}",
+            container_name,
+            image.get_image(),
+            from_utf8(&output.stderr)?
+        ));
+    }
+    Ok(())
+}
+
 fn mine_initial_blocks(options: &Options) -> Result<(), Error> {
     // mine 200+ blocks
     let miner = options
@@ -142,6 +373,12 @@ fn mine_initial_blocks(options: &Options) -> Result<(), Error> {
 fn setup_l2_nodes(options: &mut Options) -> Result<(), Error> {
     options.add_pubkeys_l2_nodes()?;
 
+    if let Some(faucet) = options.faucet.as_ref() {
+        let faucet = faucet.build(options)?;
+        connect_l2_nodes(options)?;
+        return fund_l2_nodes_from_faucet(options, faucet.as_ref(), options.faucet_confirmations);
+    }
+
     let miner = options
         .bitcoinds
         .iter()
@@ -152,8 +389,7 @@ fn setup_l2_nodes(options: &mut Options) -> Result<(), Error> {
         ));
     }
 
-    //TODO: add faucet call here instead of miner for signet
-    if options.network == "regtest" {
+    if options.can_mine() {
         connect_l2_nodes(options)?;
         options.get_l2_nodes().into_iter().for_each(|node| {
             let found_miner = miner.unwrap();
@@ -162,16 +398,17 @@ fn setup_l2_nodes(options: &mut Options) -> Result<(), Error> {
                 Err(e) => error!("failed to start/fund node: {}", e),
             }
         });
+    } else {
+        warn!(
+            "no faucet set for {}, l2 wallets start empty, use --faucet-url or --faucet-wallet to fund them",
+            options.network
+        );
     }
     Ok(())
 }
 
 fn update_bash_alias(options: &Options) -> Result<(), Error> {
-    let docker_command = if options.docker_command.contains('-') {
-        options.docker_command.to_owned()
-    } else {
-        "docker compose".to_owned()
-    };
+    let docker_command = options.docker_command;
     let mut script_content = String::new();
     script_content.push_str(&format!("{}", options.shell_type.unwrap_or_default()));
     options.lnd_nodes.iter().for_each(|lnd| {
@@ -199,7 +436,7 @@ fn update_bash_alias(options: &Options) -> Result<(), Error> {
         docker_command= docker_command,
         container_name= lnd.get_container_name(),
         name=name,
-    network=options.network));
+    network=options.network.cln_network()));
         script_content.push('\n');
     });
     options.eclair_nodes.iter().for_each(|lnd| {
@@ -230,6 +467,8 @@ fn update_bash_alias(options: &Options) -> Result<(), Error> {
         ));
         script_content.push('\n');
     });
+    // hybrid mode, the external nodes get their aliases next to the local ones
+    script_content.push_str(&get_external_aliases(options));
     let script_path = "scripts/aliases.sh";
     let full_path = get_absolute_path(script_path)?;
     if let Some(parent) = Path::new(&full_path).parent() {
@@ -254,16 +493,7 @@ fn update_bash_alias(options: &Options) -> Result<(), Error> {
 pub fn update_bash_alias_external(options: &Options) -> Result<(), Error> {
     let mut script_content = String::new();
     script_content.push_str(&format!("{}", options.shell_type.unwrap_or_default()));
-    options.external_nodes.clone().unwrap().iter().for_each(|lnd| {
-        script_content.push_str(&format!(
-            r#"
-{name}() {{
-     lncli --network={network} --macaroonpath={macaroon_path} --rpcserver={rpcserver} --tlscertpath="" "$@"
-}}
-"#,
-        name=lnd.node_alias, network=lnd.network, macaroon_path=lnd.macaroon_path, rpcserver=lnd.api_endpoint));
-        script_content.push('\n');
-    });
+    script_content.push_str(&get_external_aliases(options));
     let script_path = "scripts/aliases.sh";
     let full_path = get_absolute_path(script_path)?;
     let mut file: File = OpenOptions::new()
@@ -282,6 +512,54 @@ pub fn update_bash_alias_external(options: &Options) -> Result<(), Error> {
     Ok(())
 }
 
+fn get_external_aliases(options: &Options) -> String {
+    let mut script_content = String::new();
+    let external_nodes = options.external_nodes.clone().unwrap_or_default();
+    external_nodes.iter().for_each(|node| {
+        let alias = match node.kind {
+            LnNodeKind::Lnd => format!(
+                r#"
+{name}() {{
+     lncli --network={network} --macaroonpath={macaroon_path} --rpcserver={rpcserver} --tlscertpath="" "$@"
+}}
+"#,
+                name=node.node_alias, network=node.network, macaroon_path=node.macaroon_path, rpcserver=node.api_endpoint),
+            // there is no local cli for the remote api nodes, call the methods over http instead, ie. `<name> getinfo`
+            LnNodeKind::Coreln => format!(
+                r#"
+{name}() {{
+     curl -sk -X POST -H "Rune: {rune}" "{url}/v1/$1"
+}}
+"#,
+                name=node.node_alias, rune=node.rune, url=node.get_api_url()),
+            LnNodeKind::Eclair => format!(
+                r#"
+{name}() {{
+     curl -s -u ":{password}" -X POST "{url}/$1"
+}}
+"#,
+                name=node.node_alias, password=node.api_password, url=node.get_api_url()),
+        };
+        script_content.push_str(&alias);
+        script_content.push('\n');
+    });
+    options.external_bitcoinds.iter().for_each(|bitcoind| {
+        script_content.push_str(&format!(
+            r#"
+{name}() {{
+     curl -s --user "{user}:{password}" --data-binary "{{\"jsonrpc\":\"1.0\",\"id\":\"doppler\",\"method\":\"$1\",\"params\":[]}}" "{url}"
+}}
+"#,
+            name = bitcoind.name,
+            user = bitcoind.user,
+            password = bitcoind.password,
+            url = bitcoind.rpc_url,
+        ));
+        script_content.push('\n');
+    });
+    script_content
+}
+
 fn connect_l2_nodes(options: &Options) -> Result<(), Error> {
     let mut get_a_node = options.lnd_nodes.iter();
     options.get_l2_nodes().iter().for_each(|from_node| {
@@ -316,3 +594,181 @@ pub fn restart_service(options: &Options, service_name: String) -> Result<Output
     let output = run_command(options, "restart service".to_owned(), commands.clone())?;
     Ok(output)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ExternalNode, Network};
+
+    fn settings() -> ServiceSettings {
+        ServiceSettings {
+            environment: vec!["LOG=debug".to_owned(), "NEW=1".to_owned()],
+            volumes: vec!["./data:/data:ro".to_owned()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn apply_merges_into_a_list_environment_and_short_volumes() {
+        let mut service = Service {
+            environment: Environment::List(vec!["LOG=info".to_owned(), "KEEP=1".to_owned()]),
+            volumes: Volumes::Simple(vec!["./lnd:/home/lnd".to_owned()]),
+            ..Default::default()
+        };
+        settings().apply(&mut service);
+        assert_eq!(
+            service.environment,
+            Environment::List(vec![
+                "KEEP=1".to_owned(),
+                "LOG=debug".to_owned(),
+                "NEW=1".to_owned()
+            ])
+        );
+        assert_eq!(
+            service.volumes,
+            Volumes::Simple(vec![
+                "./lnd:/home/lnd".to_owned(),
+                "./data:/data:ro".to_owned()
+            ])
+        );
+    }
+
+    #[test]
+    fn apply_merges_into_a_map_environment_and_long_volumes() {
+        let existing = AdvancedVolumes {
+            source: Some("lnd".to_owned()),
+            target: "/home/lnd".to_owned(),
+            _type: "volume".to_owned(),
+            read_only: false,
+            bind: None,
+            volume: None,
+            tmpfs: None,
+        };
+        let mut service = Service {
+            environment: Environment::KvPair(
+                [
+                    (
+                        "LOG".to_owned(),
+                        Some(SingleValue::String("info".to_owned())),
+                    ),
+                    ("KEEP".to_owned(), Some(SingleValue::Unsigned(1))),
+                ]
+                .into_iter()
+                .collect(),
+            ),
+            volumes: Volumes::Advanced(vec![existing.clone()]),
+            ..Default::default()
+        };
+        settings().apply(&mut service);
+        let Environment::KvPair(environment) = service.environment else {
+            panic!("environment changed form");
+        };
+        assert_eq!(environment["KEEP"], Some(SingleValue::Unsigned(1)));
+        assert_eq!(
+            environment["LOG"],
+            Some(SingleValue::String("debug".to_owned()))
+        );
+        assert_eq!(
+            environment["NEW"],
+            Some(SingleValue::String("1".to_owned()))
+        );
+        assert_eq!(
+            service.volumes,
+            Volumes::Advanced(vec![
+                existing,
+                AdvancedVolumes {
+                    source: Some("./data".to_owned()),
+                    target: "/data".to_owned(),
+                    _type: "bind".to_owned(),
+                    read_only: true,
+                    bind: None,
+                    volume: None,
+                    tmpfs: None,
+                }
+            ])
+        );
+    }
+
+    #[test]
+    fn net_admin_is_only_added_for_netem_once() {
+        let mut options = Options::for_tests(Network::Regtest);
+        let mut service = Service::default();
+        add_net_admin(&options, &mut service);
+        assert!(service.cap_add.is_empty());
+
+        options.net_admin = true;
+        add_net_admin(&options, &mut service);
+        add_net_admin(&options, &mut service);
+        assert_eq!(service.cap_add, vec!["NET_ADMIN"]);
+    }
+
+    #[test]
+    fn hands_container_calls_to_the_backend() {
+        let exec = [
+            "-f",
+            "compose.yaml",
+            "exec",
+            "--user",
+            "1000:1000",
+            "bd1",
+            "bitcoin-cli",
+        ];
+        match parse_container_call(&exec) {
+            Some(ContainerCall::Exec {
+                container,
+                user,
+                cmd,
+            }) => {
+                assert_eq!(container, "bd1");
+                assert_eq!(user, Some("1000:1000"));
+                assert_eq!(cmd, ["bitcoin-cli"]);
+            }
+            _ => panic!("exec not picked up"),
+        }
+        assert!(matches!(
+            parse_container_call(&["exec", "lnd1", "lncli", "getinfo"]),
+            Some(ContainerCall::Exec { user: None, .. })
+        ));
+        assert!(matches!(
+            parse_container_call(&["-f", "compose.yaml", "stop", "lnd1"]),
+            Some(ContainerCall::Stop("lnd1"))
+        ));
+        // flags the backend doesn't know and cluster wide commands stay with compose
+        for commands in [
+            vec!["-f", "compose.yaml", "exec", "-T", "lnd1", "lncli"],
+            vec!["-f", "compose.yaml", "exec", "lnd1"],
+            vec!["-f", "compose.yaml", "up", "-d"],
+            vec!["-f", "compose.yaml", "stop"],
+        ] {
+            assert!(parse_container_call(&commands).is_none(), "{:?}", commands);
+        }
+    }
+
+    #[test]
+    fn writes_aliases_for_every_external_node() {
+        let mut options = Options::for_tests(Network::Regtest);
+        let node = |alias: &str, kind: LnNodeKind| ExternalNode {
+            node_alias: alias.to_owned(),
+            kind,
+            macaroon_path: "admin.macaroon".to_owned(),
+            api_endpoint: format!("{}.example.com", alias),
+            tls_cert_path: String::new(),
+            network: "signet".to_owned(),
+            p2p_address: String::new(),
+            rune: "abc".to_owned(),
+            api_password: "secret".to_owned(),
+        };
+        options.external_nodes = Some(vec![
+            node("exchange", LnNodeKind::Lnd),
+            node("merchant", LnNodeKind::Coreln),
+            node("customer", LnNodeKind::Eclair),
+        ]);
+        let aliases = get_external_aliases(&options);
+        assert!(aliases.contains("exchange() {\n     lncli --network=signet --macaroonpath=admin.macaroon --rpcserver=exchange.example.com"));
+        assert!(aliases.contains(
+            r#"curl -sk -X POST -H "Rune: abc" "https://merchant.example.com:3010/v1/$1""#
+        ));
+        assert!(aliases
+            .contains(r#"curl -s -u ":secret" -X POST "http://customer.example.com:8080/$1""#));
+    }
+}
@@ -12,10 +12,14 @@ use std::{
 };
 
 use crate::{
-    copy_file, create_folder, get_absolute_path, restart_service, run_command, ImageInfo, L1Node,
-    L2Node, NodeCommand, NodePair, Options, NETWORK,
+    add_net_admin, copy_file, create_folder, get_absolute_path, restart_service, run_command,
+    set_conf_overrides, BuildDetails, EclairRest, ExternalNode, ImageInfo, L1Node, L2Node,
+    LnNodeKind, NodeCommand, NodePair, Options, NETWORK,
 };
 
+// eclair negotiates a mutual close with the peer before it publishes the tx
+const CLOSING_TXID_RETRIES: u32 = 10;
+
 #[derive(Default, Debug, Clone)]
 pub struct Eclair {
     pub wallet_starting_balance: i64,
@@ -31,6 +35,8 @@ pub struct Eclair {
     pub api_password: String,
     pub path_vol: String,
     pub bitcoind_node_container_name: String,
+    // set when running with --rest, replaces the eclair-cli calls
+    pub eclair_rest: Option<EclairRest>,
 }
 
 impl Eclair {
@@ -39,8 +45,17 @@ impl Eclair {
         options: &Options,
         node_command: &NodeCommand,
     ) -> Result<String, Error> {
+        if let Some(rest) = self.eclair_rest.as_ref() {
+            let to_node = options.get_l2_by_name(node_command.to.as_str())?;
+            return rest.get_peers_channel_id(to_node.get_cached_pubkey());
+        }
         get_peers_channel_id(self, options, node_command)
     }
+
+    pub fn add_rest_client(&mut self) -> Result<(), Error> {
+        self.eclair_rest = Some(EclairRest::new(&self.server_url, &self.api_password)?);
+        Ok(())
+    }
 }
 
 impl L2Node for Eclair {
@@ -81,30 +96,64 @@ impl L2Node for Eclair {
         add_pubkey(self, option)
     }
     fn get_node_pubkey(&self, options: &Options) -> Result<String, Error> {
-        get_node_pubkey(self, options)
+        if let Some(rest) = self.eclair_rest.as_ref() {
+            rest.get_node_pubkey(options)
+        } else {
+            get_node_pubkey(self, options)
+        }
+    }
+    fn get_network(&self, _options: &Options) -> Result<String, Error> {
+        match self.eclair_rest.as_ref() {
+            Some(rest) => rest.get_network(),
+            None => Err(anyhow!("{} has no api to ask for its network", self.name)),
+        }
     }
     fn open_channel(&self, options: &Options, node_command: &NodeCommand) -> Result<(), Error> {
-        open_channel(self, options, node_command)
+        if let Some(rest) = self.eclair_rest.as_ref() {
+            rest.open_channel(self, options, node_command)
+        } else {
+            open_channel(self, options, node_command)
+        }
     }
     fn connect(&self, options: &Options, node_command: &NodeCommand) -> Result<(), Error> {
-        connect(self, options, node_command)
+        if let Some(rest) = self.eclair_rest.as_ref() {
+            rest.connect(self, options, node_command)
+        } else {
+            connect(self, options, node_command)
+        }
     }
-    fn close_channel(&self, options: &Options, node_command: &NodeCommand) -> Result<(), Error> {
-        close_channel(self, options, node_command)
+    fn close_channel(
+        &self,
+        options: &Options,
+        node_command: &NodeCommand,
+    ) -> Result<String, Error> {
+        if let Some(rest) = self.eclair_rest.as_ref() {
+            rest.close_channel(self, options, node_command, false)
+        } else {
+            close_channel(self, options, node_command)
+        }
     }
     fn force_close_channel(
         &self,
         options: &Options,
         node_command: &NodeCommand,
-    ) -> std::result::Result<(), Error> {
-        force_close_channel(self, options, node_command)
+    ) -> std::result::Result<String, Error> {
+        if let Some(rest) = self.eclair_rest.as_ref() {
+            rest.close_channel(self, options, node_command, true)
+        } else {
+            force_close_channel(self, options, node_command)
+        }
     }
     fn create_invoice(
         &self,
         options: &Options,
         node_command: &NodeCommand,
     ) -> Result<String, Error> {
-        create_invoice(self, options, node_command)
+        if let Some(rest) = self.eclair_rest.as_ref() {
+            rest.create_invoice(self, options, node_command)
+        } else {
+            create_invoice(self, options, node_command)
+        }
     }
     fn pay_invoice(
         &self,
@@ -112,10 +161,18 @@ impl L2Node for Eclair {
         node_command: &NodeCommand,
         payment_request: String,
     ) -> Result<(), Error> {
-        pay_invoice(self, options, node_command, payment_request)
+        if let Some(rest) = self.eclair_rest.as_ref() {
+            rest.pay_invoice(options, node_command, payment_request)
+        } else {
+            pay_invoice(self, options, node_command, payment_request)
+        }
     }
     fn create_on_chain_address(&self, options: &Options) -> Result<String, Error> {
-        create_eclair_address(self, options)
+        if let Some(rest) = self.eclair_rest.as_ref() {
+            rest.create_address(options)
+        } else {
+            create_eclair_address(self, options)
+        }
     }
     fn pay_address(
         &self,
@@ -123,13 +180,21 @@ impl L2Node for Eclair {
         node_command: &NodeCommand,
         address: &str,
     ) -> Result<String, Error> {
-        pay_address(self, options, node_command, address)
+        if let Some(rest) = self.eclair_rest.as_ref() {
+            rest.pay_address(options, node_command, address)
+        } else {
+            pay_address(self, options, node_command, address)
+        }
     }
     fn get_rhash(&self, option: &Options) -> Result<String, Error> {
-        get_rhash(self, option)
+        if let Some(rest) = self.eclair_rest.as_ref() {
+            rest.get_rhash(self, option)
+        } else {
+            get_rhash(self, option)
+        }
     }
     fn get_preimage(&self, _option: &Options, _rhash: String) -> Result<String, Error> {
-        unimplemented!("only implemented for LND nodes at the moment");
+        Err(hold_invoices_unsupported(self))
     }
     fn create_hold_invoice(
         &self,
@@ -137,42 +202,61 @@ impl L2Node for Eclair {
         _node_command: &NodeCommand,
         _rhash: String,
     ) -> Result<String, Error> {
-        // Not implemented yet, needs some more research into their api
-        unimplemented!("only implemented for LND nodes at the moment");
+        Err(hold_invoices_unsupported(self))
     }
     fn settle_hold_invoice(&self, _options: &Options, _preimage: String) -> Result<(), Error> {
-        // Not implemented yet, needs some more research into their api
-        unimplemented!("only implemented for LND nodes at the moment");
+        Err(hold_invoices_unsupported(self))
     }
-    fn wait_for_block(&self, _options: &Options, _num_of_blocks: i64) -> Result<(), Error> {
-        unimplemented!("only implemented for LND nodes at the moment");
+    fn wait_for_block(&self, options: &Options, num_of_blocks: i64) -> Result<(), Error> {
+        if let Some(rest) = self.eclair_rest.as_ref() {
+            return rest.wait_for_block(options, num_of_blocks);
+        }
+        unimplemented!(
+            "only implemented for LND nodes and nodes talked to over rest at the moment"
+        );
     }
     fn send_keysend(
         &self,
-        _options: &Options,
-        _node_command: &NodeCommand,
-        _to_pubkey: String,
+        options: &Options,
+        node_command: &NodeCommand,
+        to_pubkey: String,
     ) -> Result<(), Error> {
-        unimplemented!("only implemented for LND nodes at the moment");
+        if let Some(rest) = self.eclair_rest.as_ref() {
+            return rest.send_keysend(options, node_command, to_pubkey);
+        }
+        unimplemented!(
+            "only implemented for LND nodes and nodes talked to over rest at the moment"
+        );
     }
 }
 
+// neither eclair-cli nor the http api can hold an invoice, there is no plugin for it like on CoreLN
+fn hold_invoices_unsupported(node: &Eclair) -> Error {
+    anyhow!(
+        "{} is an Eclair node, Eclair doesn't support hold invoices",
+        node.name
+    )
+}
+
 pub fn build_eclair(
     options: &mut Options,
     name: &str,
     image: &ImageInfo,
-    pair: &NodePair,
+    details: &BuildDetails,
 ) -> Result<()> {
-    let mut eclair_conf = build_and_save_config(options, name, pair).unwrap();
+    let pair = details.get_pair()?;
+    let mut eclair_conf =
+        build_and_save_config(options, name, pair, &details.conf_overrides).unwrap();
     debug!("{} volume: {}", name, eclair_conf.path_vol);
 
-    let rest_port = options.new_port();
-    let grpc_port = options.new_port();
-    let p2p_port = options.new_port();
+    let rest_port = options.new_port(&eclair_conf.container_name, &eclair_conf.rest_port)?;
+    let grpc_port = options.new_port(&eclair_conf.container_name, &eclair_conf.grpc_port)?;
+    let p2p_port = options.new_port(&eclair_conf.container_name, &eclair_conf.p2p_port)?;
     let bitcoind = vec![eclair_conf.bitcoind_node_container_name.clone()];
-    let eclair = Service {
+    let mut eclair = Service {
         depends_on: DependsOnOptions::Simple(bitcoind),
         image: Some(image.get_image()),
+        build_: image.get_build_step(),
         container_name: Some(eclair_conf.container_name.clone()),
         env_file: Some(EnvFile::Simple(".env".to_owned())),
         ports: Ports::Short(vec![
@@ -184,6 +268,8 @@ pub fn build_eclair(
         networks: Networks::Simple(vec![NETWORK.to_owned()]),
         ..Default::default()
     };
+    details.service_settings.apply(&mut eclair);
+    add_net_admin(options, &mut eclair);
     options
         .services
         .insert(eclair_conf.container_name.clone(), Some(eclair));
@@ -194,11 +280,19 @@ pub fn build_eclair(
     );
     eclair_conf.grpc_port = grpc_port.to_string();
     eclair_conf.rest_port = rest_port.to_string();
+    if options.rest {
+        eclair_conf.add_rest_client()?;
+    }
     options.eclair_nodes.push(eclair_conf);
     Ok(())
 }
 
-fn build_and_save_config(options: &Options, name: &str, pair: &NodePair) -> Result<Eclair, Error> {
+fn build_and_save_config(
+    options: &Options,
+    name: &str,
+    pair: &NodePair,
+    conf_overrides: &[String],
+) -> Result<Eclair, Error> {
     if options.bitcoinds.is_empty() {
         return Err(anyhow!(
             "bitcoind nodes need to be defined before eclair nodes can be setup"
@@ -228,6 +322,7 @@ fn build_and_save_config(options: &Options, name: &str, pair: &NodePair) -> Resu
         api_password.to_owned(),
         bitcoind_node,
     )?;
+    set_conf_overrides(&mut conf, conf_overrides, "")?;
 
     let _ = copy_file(&conf, &destination_dir.clone(), "eclair.conf")?;
 
@@ -253,6 +348,7 @@ fn build_and_save_config(options: &Options, name: &str, pair: &NodePair) -> Resu
         p2p_port: "9735".to_owned(),
         grpc_port: "10000".to_owned(),
         bitcoind_node_container_name: bitcoind_node.get_container_name(),
+        eclair_rest: None,
     })
 }
 
@@ -325,6 +421,16 @@ pub fn add_eclair_nodes(options: &mut Options) -> Result<(), Error> {
     let nodes: Vec<_> = node_l2
         .iter_mut()
         .map(|node| {
+            if let Some(rest_port) =
+                options.get_assigned_port(&node.container_name, &node.rest_port)
+            {
+                node.server_url = format!("http://localhost:{}", rest_port);
+            }
+            if options.rest {
+                if let Err(e) = node.add_rest_client() {
+                    error!("failed to add rest client for {}: {}", node.get_name(), e);
+                }
+            }
             node.add_pubkey(options);
             node.clone()
         })
@@ -335,6 +441,62 @@ pub fn add_eclair_nodes(options: &mut Options) -> Result<(), Error> {
     Ok(())
 }
 
+pub fn add_external_eclair_nodes(options: &mut Options) -> Result<(), Error> {
+    let mut node_l2: Vec<_> = options
+        .external_nodes
+        .clone()
+        .unwrap()
+        .iter()
+        .filter(|external_node| external_node.kind == LnNodeKind::Eclair)
+        .map(load_external_eclair_config)
+        .filter_map(|res| {
+            res.map_err(|e| error!("failed to load external eclair node: {}", e))
+                .ok()
+        })
+        .collect();
+
+    let nodes: Vec<_> = node_l2
+        .iter_mut()
+        .map(|node| {
+            node.add_pubkey(options);
+            node.clone()
+        })
+        .collect();
+
+    options.eclair_nodes.extend(nodes);
+    Ok(())
+}
+
+pub fn load_external_eclair_config(external_node: &ExternalNode) -> Result<Eclair, Error> {
+    if external_node.api_password.is_empty() {
+        return Err(anyhow!(
+            "an API_PASSWORD is needed to talk to {}",
+            external_node.node_alias
+        ));
+    }
+    let server_url = external_node.get_api_url();
+    let eclair_rest = EclairRest::new(&server_url, &external_node.api_password)?;
+
+    // other nodes reach it through its container name, which for external nodes is its p2p host
+    let (p2p_host, p2p_port) = external_node.get_p2p_address();
+    Ok(Eclair {
+        wallet_starting_balance: 0,
+        name: external_node.node_alias.to_owned(),
+        alias: external_node.node_alias.to_owned(),
+        container_name: p2p_host,
+        pubkey: None,
+        rpc_server: format!("{}:10000", external_node.api_endpoint),
+        server_url,
+        path_vol: String::from(""),
+        api_password: external_node.api_password.clone(),
+        grpc_port: "10000".to_owned(),
+        rest_port: "8080".to_owned(),
+        p2p_port,
+        bitcoind_node_container_name: String::from(""),
+        eclair_rest: Some(eclair_rest),
+    })
+}
+
 fn load_config(
     name: &str,
     container_name: String,
@@ -356,6 +518,7 @@ fn load_config(
         //TODO: pull this value from the config file
         api_password: "test1234".to_owned(),
         bitcoind_node_container_name: bitcoind_service,
+        eclair_rest: None,
     })
 }
 
@@ -478,7 +641,7 @@ fn close_channel(
     node: &Eclair,
     options: &Options,
     node_command: &NodeCommand,
-) -> Result<(), Error> {
+) -> Result<String, Error> {
     let compose_path = options.compose_path.as_ref().unwrap();
     //TODO: find a way to specify which channel to close, right now we just grab a random one for this peer
     let peer_channel_id = format!(
@@ -513,15 +676,16 @@ fn close_channel(
             node.get_name(),
             to_node.get_name()
         );
+        return Ok("".to_owned());
     }
-    Ok(())
+    wait_for_closing_txid(|| get_channel(node, options, &peer_channel_id))
 }
 
 fn force_close_channel(
     node: &Eclair,
     options: &Options,
     node_command: &NodeCommand,
-) -> Result<(), Error> {
+) -> Result<String, Error> {
     let compose_path = options.compose_path.as_ref().unwrap();
     //TODO: find a way to specify which channel to close, right now we just grab a random one for this peer
     let peer_channel_id = format!(
@@ -556,8 +720,54 @@ fn force_close_channel(
             node.get_name(),
             to_node.get_name()
         );
+        return Ok("".to_owned());
     }
-    Ok(())
+    wait_for_closing_txid(|| get_channel(node, options, &peer_channel_id))
+}
+
+fn get_channel(node: &Eclair, options: &Options, peer_channel_id: &str) -> Result<Value, Error> {
+    let compose_path = options.compose_path.as_ref().unwrap();
+    let commands = vec![
+        "-f",
+        compose_path,
+        "exec",
+        "--user",
+        "1000:1000",
+        &node.container_name,
+        "eclair-cli",
+        "-p",
+        &node.api_password,
+        "channel",
+        peer_channel_id,
+    ];
+    let output = run_command(options, "channel".to_owned(), commands)?;
+    if !output.status.success() {
+        return Err(anyhow!("failed to get channel {}", peer_channel_id));
+    }
+    Ok(from_slice(&output.stdout)?)
+}
+
+/// Eclair only acks a close, the closing tx shows up on the channel once it's published
+pub fn wait_for_closing_txid(
+    get_channel: impl Fn() -> Result<Value, Error>,
+) -> Result<String, Error> {
+    for _ in 0..CLOSING_TXID_RETRIES {
+        let channel = get_channel()?;
+        let data = &channel["data"];
+        let closing_tx = match data["localCommitPublished"]["commitTx"].as_object() {
+            Some(commit_tx) => commit_tx.get("txid").and_then(Value::as_str),
+            None => data["mutualClosePublished"]
+                .as_array()
+                .and_then(|published| published.last())
+                .and_then(|closing_tx| closing_tx["txid"].as_str()),
+        };
+        if let Some(txid) = closing_tx {
+            return Ok(txid.to_owned());
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+    debug!("no closing tx published yet, it can't be tagged");
+    Ok("".to_owned())
 }
 
 fn get_peers_channel_id(
@@ -718,6 +928,11 @@ fn pay_address(
     let amt = node_command.amt.unwrap_or(1000).to_string();
     let address_command = &format!("--address={}", address);
     let amount_command = &format!("--amountSatoshis={}", amt);
+    let fee_command = &match node_command.fee_rate {
+        Some(fee_rate) => format!("--feeRatePerByte={}", fee_rate),
+        //TODO: make it configurable to set number of confirmations
+        None => "--confirmationTarget=1".to_owned(),
+    };
     let compose_path = options.compose_path.as_ref().unwrap();
 
     let commands = vec![
@@ -733,7 +948,7 @@ fn pay_address(
         "sendonchain",
         address_command,
         amount_command,
-        "--confirmationTarget=1", //TODO: make it configurable to set number of confirmations
+        fee_command,
     ];
     let output = run_command(options, "sendonchain".to_owned(), commands)?;
     if !output.status.success() {
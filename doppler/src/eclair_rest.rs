@@ -0,0 +1,319 @@
+use crate::{wait_for_closing_txid, wait_for_height, Eclair, L2Node, NodeCommand, Options};
+use anyhow::{anyhow, Error, Result};
+use log::{debug, error, info};
+use reqwest::blocking::Client;
+use serde_json::Value;
+use std::{thread, time::Duration};
+
+/// Talks to an Eclair node through its http api, authenticated with the api password
+#[derive(Debug, Clone)]
+pub struct EclairRest {
+    base_url: String,
+    api_password: String,
+    client: Client,
+}
+
+impl EclairRest {
+    pub fn new(base_url: &str, api_password: &str) -> Result<Self, Error> {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(120))
+            .build()?;
+        Ok(Self {
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            // the password is quoted when written to eclair.conf
+            api_password: api_password.trim_matches('"').to_owned(),
+            client,
+        })
+    }
+
+    // eclair takes form encoded params and uses basic auth with an empty user
+    fn call(&self, method: &str, params: &[(&str, String)]) -> Result<Value, Error> {
+        let url = format!("{}/{}", self.base_url, method);
+        info!("({}): {} {:?}", method, url, params);
+        let response = self
+            .client
+            .post(&url)
+            .basic_auth("", Some(&self.api_password))
+            .form(params)
+            .send()?;
+        let status = response.status();
+        let text = response.text()?;
+        debug!("response ({}): {}", status, text);
+        if !status.is_success() {
+            return Err(anyhow!("{} failed ({}): {}", method, status, text));
+        }
+        // a few endpoints (ie. getnewaddress) answer with a bare string
+        Ok(serde_json::from_str(&text).unwrap_or(Value::String(text)))
+    }
+
+    pub fn get_node_pubkey(&self, _options: &Options) -> Result<String, Error> {
+        let mut retries = 8;
+        while retries > 0 {
+            match self.call("getinfo", &[]) {
+                Ok(info) => {
+                    if let Some(pubkey) = info.get("nodeId").and_then(Value::as_str) {
+                        return Ok(pubkey.to_owned());
+                    }
+                    error!("no pubkey found");
+                    return Ok("".to_owned());
+                }
+                Err(e) => debug!("trying to get pubkey again: {}", e),
+            }
+            thread::sleep(Duration::from_secs(4));
+            retries -= 1;
+        }
+        Ok("".to_owned())
+    }
+
+    pub fn get_network(&self) -> Result<String, Error> {
+        let info = self.call("getinfo", &[])?;
+        info["network"]
+            .as_str()
+            .map(str::to_owned)
+            .ok_or(anyhow!("getinfo has no network: {}", info))
+    }
+
+    pub fn create_address(&self, _options: &Options) -> Result<String, Error> {
+        let result = self.call("getnewaddress", &[])?;
+        match result.as_str() {
+            Some(address) => Ok(address.trim().to_owned()),
+            None => {
+                error!("no addess found");
+                Ok("".to_owned())
+            }
+        }
+    }
+
+    pub fn connect(
+        &self,
+        node: &Eclair,
+        options: &Options,
+        node_command: &NodeCommand,
+    ) -> Result<(), Error> {
+        let to_node = options.get_l2_by_name(node_command.to.as_str())?;
+        let result = self.call("connect", &[("uri", to_node.get_connection_url())]);
+        match result {
+            Ok(_) => info!(
+                "successfully connected from {} to {}",
+                node.get_name(),
+                to_node.get_name()
+            ),
+            Err(e) if e.to_string().contains("already connected") => info!(
+                "successfully connected from {} to {}",
+                node.get_name(),
+                to_node.get_name()
+            ),
+            Err(e) => error!(
+                "failed to connect from {} to {}: {}",
+                node.get_name(),
+                to_node.get_name(),
+                e
+            ),
+        }
+        Ok(())
+    }
+
+    pub fn open_channel(
+        &self,
+        node: &Eclair,
+        options: &Options,
+        node_command: &NodeCommand,
+    ) -> Result<(), Error> {
+        let _ = self.connect(node, options, node_command);
+        let to_node = options.get_l2_by_name(node_command.to.as_str())?;
+        let amt = node_command.amt.unwrap_or(100000);
+        let result = self.call(
+            "open",
+            &[
+                ("nodeId", to_node.get_cached_pubkey()),
+                ("fundingSatoshis", amt.to_string()),
+                ("fundingFeeBudgetSatoshis", (amt / 10).to_string()),
+            ],
+        );
+        match result {
+            Ok(_) => info!(
+                "successfully opened channel from {} to {}",
+                node.get_name(),
+                to_node.get_name()
+            ),
+            Err(e) => error!(
+                "failed to open channel from {} to {}: {}",
+                node.get_name(),
+                to_node.get_name(),
+                e
+            ),
+        }
+        Ok(())
+    }
+
+    pub fn create_invoice(
+        &self,
+        node: &Eclair,
+        _options: &Options,
+        node_command: &NodeCommand,
+    ) -> Result<String, Error> {
+        let result = self.call(
+            "createinvoice",
+            &[
+                ("description", node.generate_memo()),
+                (
+                    "amountMsat",
+                    (node_command.amt.unwrap_or(1000) * 1000).to_string(),
+                ),
+            ],
+        )?;
+        result
+            .get("serialized")
+            .and_then(Value::as_str)
+            .map(str::to_owned)
+            .ok_or(anyhow!("no payment requests found"))
+    }
+
+    pub fn pay_invoice(
+        &self,
+        _options: &Options,
+        node_command: &NodeCommand,
+        payment_request: String,
+    ) -> Result<(), Error> {
+        let result = self.call(
+            "payinvoice",
+            &[
+                ("invoice", payment_request),
+                ("blocking", "true".to_owned()),
+            ],
+        );
+        if let Err(e) = result {
+            error!(
+                "failed to make payment from {} to {}: {}",
+                node_command.from, node_command.to, e
+            )
+        }
+        Ok(())
+    }
+
+    pub fn send_keysend(
+        &self,
+        _options: &Options,
+        node_command: &NodeCommand,
+        to_pubkey: String,
+    ) -> Result<(), Error> {
+        let result = self.call(
+            "sendtonode",
+            &[
+                ("nodeId", to_pubkey),
+                (
+                    "amountMsat",
+                    (node_command.amt.unwrap_or(1000) * 1000).to_string(),
+                ),
+            ],
+        );
+        if let Err(e) = result {
+            error!(
+                "failed to keysend from {} to {}: {}",
+                node_command.from, node_command.to, e
+            )
+        }
+        Ok(())
+    }
+
+    pub fn pay_address(
+        &self,
+        _options: &Options,
+        node_command: &NodeCommand,
+        address: &str,
+    ) -> Result<String, Error> {
+        let fee_param = match node_command.fee_rate {
+            Some(fee_rate) => ("feeRatePerByte", fee_rate.to_string()),
+            //TODO: make it configurable to set number of confirmations
+            None => ("confirmationTarget", "1".to_owned()),
+        };
+        let result = self.call(
+            "sendonchain",
+            &[
+                ("address", address.to_owned()),
+                (
+                    "amountSatoshis",
+                    node_command.amt.unwrap_or(1000).to_string(),
+                ),
+                fee_param,
+            ],
+        );
+        match result {
+            Ok(txid) => Ok(txid.as_str().unwrap_or_default().trim().to_owned()),
+            Err(e) => {
+                error!("failed to pay on chain tx: {}", e);
+                Ok("".to_owned())
+            }
+        }
+    }
+
+    pub fn close_channel(
+        &self,
+        node: &Eclair,
+        options: &Options,
+        node_command: &NodeCommand,
+        force: bool,
+    ) -> Result<String, Error> {
+        //TODO: find a way to specify which channel to close, right now we just grab a random one for this peer
+        let to_node = options.get_l2_by_name(node_command.to.as_str())?;
+        let channel_id = self.get_peers_channel_id(to_node.get_cached_pubkey())?;
+        if channel_id.is_empty() {
+            info!(
+                "no channels to closed from {} to {}",
+                node.get_name(),
+                to_node.get_name()
+            );
+            return Ok("".to_owned());
+        }
+        let method = if force { "forceclose" } else { "close" };
+        if let Err(e) = self.call(method, &[("channelId", channel_id.clone())]) {
+            error!(
+                "failed to close channel from {} to {}: {}",
+                node.get_name(),
+                to_node.get_name(),
+                e
+            );
+            return Ok("".to_owned());
+        }
+        info!(
+            "successfully closed channel from {} to {}",
+            node.get_name(),
+            to_node.get_name()
+        );
+        wait_for_closing_txid(|| self.call("channel", &[("channelId", channel_id.clone())]))
+    }
+
+    pub fn get_peers_channel_id(&self, peer_pubkey: String) -> Result<String, Error> {
+        let result = self.call("channels", &[("nodeId", peer_pubkey)])?;
+        Ok(result
+            .as_array()
+            .and_then(|channels| channels.first())
+            .and_then(|channel| channel.get("channelId"))
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_owned())
+    }
+
+    pub fn get_rhash(&self, node: &Eclair, _options: &Options) -> Result<String, Error> {
+        let result = self.call("createinvoice", &[("description", node.generate_memo())])?;
+        result
+            .get("paymentHash")
+            .and_then(Value::as_str)
+            .map(str::to_owned)
+            .ok_or(anyhow!("no r_hash found"))
+    }
+
+    fn get_block_height(&self) -> Result<i64, Error> {
+        let info = self.call("getinfo", &[])?;
+        info.get("blockHeight")
+            .and_then(Value::as_i64)
+            .ok_or(anyhow!("no blockHeight found"))
+    }
+
+    pub fn wait_for_block(&self, options: &Options, num_of_blocks: i64) -> Result<(), Error> {
+        let target = self.get_block_height()? + num_of_blocks;
+        info!("waiting for block {}", target);
+        // eclair has no blocking call for this, poll until it has caught up
+        wait_for_height(options, target, || self.get_block_height())
+    }
+}
@@ -0,0 +1,410 @@
+use crate::{BitcoindRpc, L1Node, Options, SpendKind};
+use anyhow::{anyhow, Error, Result};
+use log::{debug, error, info, warn};
+use reqwest::blocking::Client;
+use serde_json::{json, Value};
+use std::{
+    fmt,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+// signet blocks come every ~10 minutes, give a few of them time to show up
+const CONFIRMATION_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+const CONFIRMATION_POLL: Duration = Duration::from_secs(10);
+
+/// Funds L2 wallets on networks where doppler can't mine the coins itself
+pub trait Faucet: Send + Sync {
+    /// Sends amt sats to the address, returns the txid
+    fn send(&self, address: &str, amt: i64) -> Result<String, Error>;
+    /// None when the faucet has no way to look the tx up, the funding is then not waited on
+    fn get_confirmations(&self, txid: &str) -> Result<Option<i64>, Error>;
+    fn describe(&self) -> String;
+}
+
+/// Where the L2 nodes get their starting balance from, set with --faucet-url or --faucet-wallet
+#[derive(Clone)]
+pub enum FaucetConfig {
+    Http {
+        url: String,
+        esplora_url: Option<String>,
+    },
+    // name of a bitcoind in the cluster or the external nodes file with a pre-funded wallet
+    Wallet(String),
+    // any other faucet, ie. a stand-in used in tests
+    Custom(Arc<dyn Faucet>),
+}
+
+impl fmt::Debug for FaucetConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FaucetConfig::Http { url, esplora_url } => f
+                .debug_struct("Http")
+                .field("url", url)
+                .field("esplora_url", esplora_url)
+                .finish(),
+            FaucetConfig::Wallet(name) => f.debug_tuple("Wallet").field(name).finish(),
+            FaucetConfig::Custom(faucet) => {
+                f.debug_tuple("Custom").field(&faucet.describe()).finish()
+            }
+        }
+    }
+}
+
+impl FaucetConfig {
+    pub fn build(&self, options: &Options) -> Result<Arc<dyn Faucet>, Error> {
+        match self {
+            FaucetConfig::Http { url, esplora_url } => {
+                Ok(Arc::new(HttpFaucet::new(url, esplora_url.clone())?))
+            }
+            FaucetConfig::Wallet(name) => Ok(Arc::new(WalletFaucet::from_options(options, name)?)),
+            FaucetConfig::Custom(faucet) => Ok(faucet.clone()),
+        }
+    }
+}
+
+/// Faucet with an http api, POST {"address": .., "sats": ..} answered with {"txid": ..} or the bare txid
+/// Confirmations are looked up in an esplora api when one is set
+#[derive(Debug, Clone)]
+pub struct HttpFaucet {
+    url: String,
+    esplora_url: Option<String>,
+    client: Client,
+}
+
+impl HttpFaucet {
+    pub fn new(url: &str, esplora_url: Option<String>) -> Result<Self, Error> {
+        let client = Client::builder().timeout(Duration::from_secs(60)).build()?;
+        Ok(Self {
+            url: url.trim_end_matches('/').to_owned(),
+            esplora_url: esplora_url.map(|url| url.trim_end_matches('/').to_owned()),
+            client,
+        })
+    }
+
+    fn get_esplora(&self, esplora_url: &str, path: &str) -> Result<String, Error> {
+        let response = self
+            .client
+            .get(format!("{}/{}", esplora_url, path))
+            .send()?;
+        let status = response.status();
+        let text = response.text()?;
+        if !status.is_success() {
+            return Err(anyhow!("esplora {} failed ({}): {}", path, status, text));
+        }
+        Ok(text)
+    }
+}
+
+impl Faucet for HttpFaucet {
+    fn send(&self, address: &str, amt: i64) -> Result<String, Error> {
+        let body = json!({ "address": address, "sats": amt });
+        info!("(faucet): {} {}", self.url, body);
+        let response = self.client.post(&self.url).json(&body).send()?;
+        let status = response.status();
+        let text = response.text()?;
+        debug!("faucet response ({}): {}", status, text);
+        if !status.is_success() {
+            return Err(anyhow!("faucet failed ({}): {}", status, text));
+        }
+        let txid = match serde_json::from_str::<Value>(&text) {
+            Ok(Value::Object(result)) => result
+                .get("txid")
+                .and_then(Value::as_str)
+                .map(str::to_owned)
+                .ok_or(anyhow!("no txid in faucet response: {}", text))?,
+            Ok(Value::String(txid)) => txid,
+            _ => text.trim().to_owned(),
+        };
+        Ok(txid)
+    }
+
+    fn get_confirmations(&self, txid: &str) -> Result<Option<i64>, Error> {
+        let Some(esplora_url) = self.esplora_url.as_ref() else {
+            return Ok(None);
+        };
+        let status: Value =
+            serde_json::from_str(&self.get_esplora(esplora_url, &format!("tx/{}/status", txid))?)?;
+        let Some(block_height) = status.get("block_height").and_then(Value::as_i64) else {
+            return Ok(Some(0));
+        };
+        let tip: i64 = self
+            .get_esplora(esplora_url, "blocks/tip/height")?
+            .trim()
+            .parse()?;
+        Ok(Some(tip - block_height + 1))
+    }
+
+    fn describe(&self) -> String {
+        self.url.clone()
+    }
+}
+
+/// Pays out of a pre-funded bitcoind wallet over json-rpc
+#[derive(Debug, Clone)]
+pub struct WalletFaucet {
+    name: String,
+    rpc: BitcoindRpc,
+}
+
+impl WalletFaucet {
+    pub fn new(name: &str, rpc: BitcoindRpc) -> Self {
+        Self {
+            name: name.to_owned(),
+            rpc,
+        }
+    }
+
+    pub fn from_options(options: &Options, name: &str) -> Result<Self, Error> {
+        if let Some(external) = options
+            .external_bitcoinds
+            .iter()
+            .find(|bitcoind| bitcoind.name == name)
+        {
+            let rpc = BitcoindRpc::new(
+                &external.rpc_url,
+                &external.user,
+                &external.password,
+                external.wallet.clone(),
+            )?;
+            return Ok(Self::new(name, rpc));
+        }
+        let mut bitcoind = options
+            .bitcoinds
+            .iter()
+            .find(|bitcoind| bitcoind.get_name() == name)
+            .cloned()
+            .ok_or(anyhow!("no bitcoind named {} to use as a faucet", name))?;
+        if bitcoind.get_rpc_client().is_none() {
+            bitcoind.add_rpc_client()?;
+        }
+        let rpc = bitcoind.get_rpc_client().unwrap().clone();
+        Ok(Self::new(name, rpc))
+    }
+}
+
+impl Faucet for WalletFaucet {
+    fn send(&self, address: &str, amt: i64) -> Result<String, Error> {
+        self.rpc.send_to_address(address, amt)
+    }
+
+    fn get_confirmations(&self, txid: &str) -> Result<Option<i64>, Error> {
+        self.rpc.get_transaction_confirmations(txid).map(Some)
+    }
+
+    fn describe(&self) -> String {
+        format!("wallet of {}", self.name)
+    }
+}
+
+/// Sends every L2 node its wallet_starting_balance from the faucet and waits for the funding to confirm
+pub fn fund_l2_nodes_from_faucet(
+    options: &Options,
+    faucet: &dyn Faucet,
+    confirmations: i64,
+) -> Result<(), Error> {
+    info!("funding l2 nodes from faucet {}", faucet.describe());
+    let mut pending = vec![];
+    for node in options.get_l2_nodes() {
+        let amt = node.get_starting_wallet_balance();
+        if amt <= 0 {
+            continue;
+        }
+        // the faucet's coins count against the run budget like any other spend
+        if let Err(e) = options
+            .budget
+            .reserve(&faucet.describe(), SpendKind::OnChain, amt)
+        {
+            error!("skipped funding {} from faucet: {}", node.get_name(), e);
+            continue;
+        }
+        let funded = node
+            .create_on_chain_address(options)
+            .and_then(|address| faucet.send(&address, amt))
+            .inspect_err(|_| {
+                options
+                    .budget
+                    .release(&faucet.describe(), SpendKind::OnChain, amt)
+            });
+        match funded {
+            Ok(txid) => {
+                info!(
+                    "container: {} funded with {} sats in {}",
+                    node.get_container_name(),
+                    amt,
+                    txid
+                );
+                pending.push((node.get_name().to_owned(), txid));
+            }
+            Err(e) => error!("failed to fund {} from faucet: {}", node.get_name(), e),
+        }
+    }
+
+    // on regtest or a custom signet nothing else mines, confirm the funding with the cluster's miner
+    if options.can_mine() {
+        if let Some(miner) = options
+            .bitcoinds
+            .iter()
+            .find(|bitcoind| bitcoind.get_container_name().contains("miner"))
+        {
+            miner.mine_bitcoin(options, confirmations)?;
+        }
+    }
+    wait_for_confirmations(faucet, pending, confirmations)
+}
+
+fn wait_for_confirmations(
+    faucet: &dyn Faucet,
+    mut pending: Vec<(String, String)>,
+    confirmations: i64,
+) -> Result<(), Error> {
+    let started = Instant::now();
+    while !pending.is_empty() {
+        let mut still_pending = vec![];
+        for (node, txid) in pending {
+            match faucet.get_confirmations(&txid) {
+                Ok(Some(confirmed)) if confirmed >= confirmations => {
+                    info!("funding of {} confirmed ({})", node, txid)
+                }
+                Ok(Some(confirmed)) => {
+                    debug!("funding of {} at {} confirmations", node, confirmed);
+                    still_pending.push((node, txid));
+                }
+                Ok(None) => warn!(
+                    "faucet {} can't look up {}, not waiting for the funding of {} to confirm",
+                    faucet.describe(),
+                    txid,
+                    node
+                ),
+                Err(e) => {
+                    debug!("failed to look up funding of {}: {}", node, e);
+                    still_pending.push((node, txid));
+                }
+            }
+        }
+        pending = still_pending;
+        if pending.is_empty() {
+            break;
+        }
+        if started.elapsed() > CONFIRMATION_TIMEOUT {
+            let nodes: Vec<String> = pending.into_iter().map(|(node, _)| node).collect();
+            return Err(anyhow!(
+                "funding of {} not confirmed after {} minutes",
+                nodes.join(", "),
+                CONFIRMATION_TIMEOUT.as_secs() / 60
+            ));
+        }
+        info!(
+            "waiting for {} faucet funding tx(s) to reach {} confirmations",
+            pending.len(),
+            confirmations
+        );
+        thread::sleep(CONFIRMATION_POLL);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ContainerBackend, Lnd, Network, SpendBudget};
+    use std::{
+        os::unix::process::ExitStatusExt,
+        process::{ExitStatus, Output},
+        sync::Mutex,
+    };
+
+    // answers lncli newaddress with an address named after the container
+    struct AddressBackend;
+
+    impl ContainerBackend for AddressBackend {
+        fn exec(
+            &self,
+            _options: &Options,
+            _command_name: &str,
+            container: &str,
+            _user: Option<&str>,
+            cmd: &[&str],
+        ) -> Result<Output, Error> {
+            assert!(cmd.contains(&"newaddress"));
+            Ok(Output {
+                status: ExitStatus::from_raw(0),
+                stdout: json!({ "address": format!("addr-{}", container) })
+                    .to_string()
+                    .into_bytes(),
+                stderr: vec![],
+            })
+        }
+        fn stop(&self, _options: &Options, _container: &str) -> Result<Output, Error> {
+            unreachable!()
+        }
+        fn start(&self, _options: &Options, _container: &str) -> Result<Output, Error> {
+            unreachable!()
+        }
+        fn kill(&self, _options: &Options, _container: &str) -> Result<Output, Error> {
+            unreachable!()
+        }
+    }
+
+    #[derive(Default)]
+    struct RecordingFaucet {
+        sent: Mutex<Vec<(String, i64)>>,
+    }
+
+    impl Faucet for RecordingFaucet {
+        fn send(&self, address: &str, amt: i64) -> Result<String, Error> {
+            let mut sent = self.sent.lock().unwrap();
+            sent.push((address.to_owned(), amt));
+            Ok(format!("txid{}", sent.len()))
+        }
+        fn get_confirmations(&self, _txid: &str) -> Result<Option<i64>, Error> {
+            Ok(Some(3))
+        }
+        fn describe(&self) -> String {
+            "recording".to_owned()
+        }
+    }
+
+    fn lnd(name: &str, wallet_starting_balance: i64) -> Lnd {
+        Lnd {
+            name: name.to_owned(),
+            container_name: format!("doppler-lnd-{}", name),
+            wallet_starting_balance,
+            ..Default::default()
+        }
+    }
+
+    fn fund(budget: SpendBudget) -> Vec<(String, i64)> {
+        let mut options = Options::for_tests(Network::Signet);
+        options.backend = Arc::new(AddressBackend);
+        options.budget = budget;
+        options.lnd_nodes = vec![lnd("lnd1", 100_000), lnd("lnd2", 0), lnd("lnd3", 250_000)];
+        let recording = Arc::new(RecordingFaucet::default());
+        options.faucet = Some(FaucetConfig::Custom(recording.clone()));
+
+        let faucet = options.faucet.clone().unwrap().build(&options).unwrap();
+        fund_l2_nodes_from_faucet(&options, faucet.as_ref(), 3).unwrap();
+        let sent = recording.sent.lock().unwrap().clone();
+        sent
+    }
+
+    #[test]
+    fn funds_every_l2_node_with_a_starting_balance() {
+        assert_eq!(
+            fund(SpendBudget::default()),
+            vec![
+                ("addr-doppler-lnd-lnd1".to_owned(), 100_000),
+                ("addr-doppler-lnd-lnd3".to_owned(), 250_000),
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_funding_over_the_run_budget() {
+        assert_eq!(
+            fund(SpendBudget::new(Some(200_000), None)),
+            vec![("addr-doppler-lnd-lnd1".to_owned(), 100_000)]
+        );
+    }
+}
@@ -0,0 +1,277 @@
+use crate::{Bitcoind, L1Node, NodeCommand, Options, SpendKind, Tag};
+use anyhow::{anyhow, Error, Result};
+use log::{debug, error, info};
+use serde_json::{json, Value};
+
+// every stuffing transaction pays the node back this much, the fee is what fills the mempool
+const FILL_AMOUNT_BTC: f64 = 0.0001;
+// a one input one output child, rounded up so the package ends up at or above the target
+const CPFP_CHILD_VSIZE: i64 = 200;
+// a filler spends one or two wallet inputs, the budget books its fee at this size
+const FILL_TX_VSIZE: i64 = 250;
+const DUST_LIMIT: i64 = 546;
+
+/// bd1 FILL_MEMPOOL n FEERATE x: sends n replaceable transactions from bd1's wallet back to itself at x sat/vB
+/// with a TAG the last one is saved, it has no descendants so it can be bumped with RBF
+pub fn fill_mempool(options: &Options, node_command: &NodeCommand) -> Result<(), Error> {
+    let node = options.get_bitcoind_by_name(&node_command.from)?;
+    let count = node_command.amt.unwrap_or(1);
+    let fee_rate = get_fee_rate(node_command)?;
+    let address = node.create_address(options)?;
+    let mut last_tx_id = None;
+    let mut sent = 0;
+    let fee = fee_rate as i64 * FILL_TX_VSIZE;
+    for _ in 0..count {
+        if let Err(e) = options
+            .budget
+            .reserve(&node_command.from, SpendKind::Fees, fee)
+        {
+            error!("({}): stopped filling the mempool: {}", node.get_name(), e);
+            break;
+        }
+        // address, amount, comment, comment_to, subtractfeefromamount, replaceable, conf_target, estimate_mode, avoid_reuse, fee_rate
+        let result = node.call_wallet(
+            options,
+            "sendtoaddress",
+            &[
+                json!(address),
+                json!(FILL_AMOUNT_BTC),
+                json!(""),
+                json!(""),
+                json!(false),
+                json!(true),
+                Value::Null,
+                json!("unset"),
+                Value::Null,
+                json!(fee_rate),
+            ],
+        );
+        match result {
+            Ok(tx_id) => {
+                debug!("({}): mempool filler {}", node.get_name(), tx_id);
+                last_tx_id = tx_id.as_str().map(str::to_owned);
+            }
+            // usually the wallet running out of coins or hitting the mempool chain limits
+            Err(e) => {
+                options
+                    .budget
+                    .release(&node_command.from, SpendKind::Fees, fee);
+                error!("({}): stopped filling the mempool: {}", node.get_name(), e);
+                break;
+            }
+        }
+        sent += 1;
+    }
+    info!(
+        "({}): filled the mempool with {}/{} transaction(s) at {} sat/vB",
+        node.get_name(),
+        sent,
+        count,
+        fee_rate
+    );
+    if let (Some(name), Some(val)) = (node_command.tag.clone(), last_tx_id) {
+        options.save_tag(&Tag { name, val })?;
+    }
+    Ok(())
+}
+
+/// bd1 RBF TAG tx FEERATE x: replaces the tagged wallet transaction with one paying x sat/vB, the tag follows the replacement
+pub fn replace_by_fee(options: &Options, node_command: &NodeCommand) -> Result<(), Error> {
+    let node = options.get_bitcoind_by_name(&node_command.from)?;
+    let fee_rate = get_fee_rate(node_command)?;
+    let tag = get_tagged_tx(options, node_command)?;
+    // the replacement pays about the new rate for the same size, the budget books the difference
+    let entry = node.call(options, "getmempoolentry", &[json!(tag.val)])?;
+    let vsize = entry["vsize"]
+        .as_i64()
+        .ok_or(anyhow!("{} has no vsize", tag.val))?;
+    let extra_fee = (fee_rate as i64 * vsize - to_sats(&entry["fees"]["base"])?).max(0);
+    options
+        .budget
+        .spend(&node_command.from, SpendKind::Fees, extra_fee, || {
+            let result = node.call_wallet(
+                options,
+                "bumpfee",
+                &[json!(tag.val), json!({ "fee_rate": fee_rate })],
+            )?;
+            let replacement = result["txid"]
+                .as_str()
+                .ok_or(anyhow!("bumpfee returned no txid: {}", result))?
+                .to_owned();
+            info!(
+                "({}): replaced {} with {} at {} sat/vB",
+                node.get_name(),
+                tag.val,
+                replacement,
+                fee_rate
+            );
+            options.save_tag(&Tag {
+                name: tag.name.clone(),
+                val: replacement,
+            })
+        })
+}
+
+/// bd1 CPFP TAG tx FEERATE x: spends bd1's output of the tagged transaction so the pair pays x sat/vB
+pub fn child_pays_for_parent(options: &Options, node_command: &NodeCommand) -> Result<(), Error> {
+    let node = options.get_bitcoind_by_name(&node_command.from)?;
+    let fee_rate = get_fee_rate(node_command)?;
+    let tag = get_tagged_tx(options, node_command)?;
+    let (vout, value) = find_own_output(options, node, &tag.val)?;
+
+    // the child has to cover whatever the unconfirmed ancestors are missing
+    let entry = node.call(options, "getmempoolentry", &[json!(tag.val)])?;
+    let ancestor_vsize = entry["ancestorsize"]
+        .as_i64()
+        .ok_or(anyhow!("{} has no ancestor size", tag.val))?;
+    let ancestor_fee = to_sats(&entry["fees"]["ancestor"])?;
+    let child_fee = fee_rate as i64 * (ancestor_vsize + CPFP_CHILD_VSIZE) - ancestor_fee;
+    if child_fee <= 0 {
+        return Err(anyhow!(
+            "{} already pays at least {} sat/vB with its ancestors",
+            tag.val,
+            fee_rate
+        ));
+    }
+    if value - child_fee < DUST_LIMIT {
+        return Err(anyhow!(
+            "output {}:{} of {} sats can't pay a {} sats child fee",
+            tag.val,
+            vout,
+            value,
+            child_fee
+        ));
+    }
+
+    options
+        .budget
+        .spend(&node_command.from, SpendKind::Fees, child_fee, || {
+            let address = node.create_address(options)?;
+            let amount = format!("{:.8}", (value - child_fee) as f64 / 100_000_000.0);
+            let raw_tx = node.call(
+                options,
+                "createrawtransaction",
+                &[
+                    json!([{ "txid": tag.val, "vout": vout }]),
+                    json!({ address: amount }),
+                ],
+            )?;
+            let signed = node.call_wallet(options, "signrawtransactionwithwallet", &[raw_tx])?;
+            if !signed["complete"].as_bool().unwrap_or(false) {
+                return Err(anyhow!("failed to sign the child of {}", tag.val));
+            }
+            let child = node.call(options, "sendrawtransaction", &[signed["hex"].clone()])?;
+            info!(
+                "({}): child {} pays {} sats for {} at {} sat/vB",
+                node.get_name(),
+                child.as_str().unwrap_or_default(),
+                child_fee,
+                tag.val,
+                fee_rate
+            );
+            Ok(())
+        })
+}
+
+// first unspent output of the transaction that belongs to the node's wallet, with its value in sats
+fn find_own_output(options: &Options, node: &Bitcoind, tx_id: &str) -> Result<(u64, i64), Error> {
+    let tx = node.call(options, "getrawtransaction", &[json!(tx_id), json!(true)])?;
+    let outputs = tx["vout"]
+        .as_array()
+        .ok_or(anyhow!("{} has no outputs", tx_id))?;
+    for output in outputs {
+        let Some(address) = output["scriptPubKey"]["address"].as_str() else {
+            continue;
+        };
+        let info = node.call_wallet(options, "getaddressinfo", &[json!(address)])?;
+        if !info["ismine"].as_bool().unwrap_or(false) {
+            continue;
+        }
+        let vout = output["n"]
+            .as_u64()
+            .ok_or(anyhow!("invalid output of {}", tx_id))?;
+        // gettxout has no result for spent outputs
+        let unspent = node.call(
+            options,
+            "gettxout",
+            &[json!(tx_id), json!(vout), json!(true)],
+        )?;
+        if !unspent.is_object() {
+            continue;
+        }
+        return Ok((vout, to_sats(&output["value"])?));
+    }
+    Err(anyhow!(
+        "{} has no unspent output in {}'s wallet to bump it with",
+        tx_id,
+        node.get_name()
+    ))
+}
+
+fn get_fee_rate(node_command: &NodeCommand) -> Result<u64, Error> {
+    match node_command.fee_rate {
+        Some(fee_rate) if fee_rate > 0 => Ok(fee_rate),
+        _ => Err(anyhow!("{} needs a FEERATE above 0", node_command.name)),
+    }
+}
+
+fn get_tagged_tx(options: &Options, node_command: &NodeCommand) -> Result<Tag, Error> {
+    let name = node_command
+        .tag
+        .clone()
+        .ok_or(anyhow!("{} needs a TAG", node_command.name))?;
+    let tag = options.get_tag_by_name(name.clone());
+    if tag.val.is_empty() {
+        return Err(anyhow!("no transaction tagged {}", name));
+    }
+    Ok(tag)
+}
+
+fn to_sats(btc: &Value) -> Result<i64, Error> {
+    btc.as_f64()
+        .map(|btc| (btc * 100_000_000.0).round() as i64)
+        .ok_or(anyhow!("invalid amount {}", btc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Network;
+
+    #[test]
+    fn needs_a_fee_rate_above_zero() {
+        let mut command = NodeCommand {
+            name: "RBF".to_owned(),
+            fee_rate: Some(20),
+            ..Default::default()
+        };
+        assert_eq!(get_fee_rate(&command).unwrap(), 20);
+        command.fee_rate = Some(0);
+        assert!(get_fee_rate(&command).is_err());
+        command.fee_rate = None;
+        assert!(get_fee_rate(&command).is_err());
+    }
+
+    #[test]
+    fn converts_btc_amounts_to_sats() {
+        assert_eq!(to_sats(&json!(0.0001)).unwrap(), 10_000);
+        // floats like 0.29 aren't exact, the amount is rounded
+        assert_eq!(to_sats(&json!(0.29)).unwrap(), 29_000_000);
+        assert_eq!(to_sats(&json!(0.00000546)).unwrap(), DUST_LIMIT);
+        assert!(to_sats(&json!("0.1")).is_err());
+    }
+
+    #[test]
+    fn bumps_need_a_known_tag() {
+        let options = Options::for_tests(Network::Regtest);
+        let mut command = NodeCommand {
+            name: "CPFP".to_owned(),
+            ..Default::default()
+        };
+        let error = get_tagged_tx(&options, &command).unwrap_err();
+        assert!(error.to_string().contains("needs a TAG"));
+        command.tag = Some("filler".to_owned());
+        let error = get_tagged_tx(&options, &command).unwrap_err();
+        assert!(error.to_string().contains("no transaction tagged filler"));
+    }
+}
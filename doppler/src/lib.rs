@@ -1,30 +1,62 @@
 mod bitcoind;
+mod bitcoind_rpc;
+mod budget;
+mod chaos;
 mod cln;
+mod cln_rest;
+mod compose_command;
 mod conf_handler;
+mod container_backend;
 mod docker;
 mod eclair;
+mod eclair_rest;
+mod faucet;
+mod fees;
 mod hash_map_wrapper;
 mod lnd_actions;
+mod mining;
+mod netem;
+mod network;
 mod node;
 mod node_kind;
 mod parser;
+mod partition;
 mod polar_default_images;
+mod port_allocator;
+mod reorg;
+mod signet;
 mod simple_storage;
 mod tools;
 mod visualizer;
 mod workflow;
 
 pub use bitcoind::*;
+pub use bitcoind_rpc::*;
+pub use budget::*;
+pub use chaos::*;
 pub use cln::*;
+pub use cln_rest::*;
+pub use compose_command::*;
 pub use conf_handler::*;
+pub use container_backend::*;
 pub use docker::*;
 pub use eclair::*;
+pub use eclair_rest::*;
+pub use faucet::*;
+pub use fees::*;
 pub use hash_map_wrapper::*;
 pub use lnd_actions::*;
+pub use mining::*;
+pub use netem::*;
+pub use network::*;
 pub use node::*;
 pub use node_kind::*;
 pub use parser::*;
+pub use partition::*;
 pub use polar_default_images::*;
+pub use port_allocator::*;
+pub use reorg::*;
+pub use signet::*;
 pub use simple_storage::*;
 pub use tools::*;
 pub use visualizer::*;
@@ -1,6 +1,7 @@
 use crate::{
-    add_rest_client, copy_file, get_absolute_path, ExternalNode, ImageInfo, L1Node, L2Node, LndCli,
-    LndRest, NodeCommand, NodePair, Options, NETWORK,
+    add_net_admin, add_rest_client, copy_file, get_absolute_path, set_conf_overrides, BuildDetails,
+    ExternalNode, ImageInfo, L1Node, L2Node, LnNodeKind, LndCli, LndRest, NodeCommand, NodePair,
+    Options, NETWORK,
 };
 use anyhow::{anyhow, Error, Result};
 use conf_parser::processer::{read_to_file_conf, FileConf, Section};
@@ -127,6 +128,12 @@ impl L2Node for Lnd {
             self.lnd_cli.get_node_pubkey(self, options)
         }
     }
+    fn get_network(&self, _options: &Options) -> Result<String, Error> {
+        match self.lnd_rest.as_ref() {
+            Some(rest) => rest.get_network(),
+            None => Err(anyhow!("{} has no api to ask for its network", self.name)),
+        }
+    }
     fn open_channel(&self, options: &Options, node_command: &NodeCommand) -> Result<(), Error> {
         if let Some(rest) = self.lnd_rest.clone() {
             rest.open_channel(self, options, node_command)
@@ -141,7 +148,11 @@ impl L2Node for Lnd {
             self.lnd_cli.connect(self, options, node_command)
         }
     }
-    fn close_channel(&self, options: &Options, node_command: &NodeCommand) -> Result<(), Error> {
+    fn close_channel(
+        &self,
+        options: &Options,
+        node_command: &NodeCommand,
+    ) -> Result<String, Error> {
         if let Some(rest) = self.lnd_rest.clone() {
             rest.close_channel(self, options, node_command)
         } else {
@@ -152,7 +163,7 @@ impl L2Node for Lnd {
         &self,
         options: &Options,
         node_command: &NodeCommand,
-    ) -> std::result::Result<(), Error> {
+    ) -> std::result::Result<String, Error> {
         if let Some(rest) = self.lnd_rest.clone() {
             rest.force_close_channel(self, options, node_command)
         } else {
@@ -305,18 +316,21 @@ pub fn build_lnd(
     options: &mut Options,
     name: &str,
     image: &ImageInfo,
-    pair: &NodePair,
+    details: &BuildDetails,
 ) -> Result<()> {
-    let mut lnd_conf = build_and_save_config(options, name, image, pair).unwrap();
+    let pair = details.get_pair()?;
+    let mut lnd_conf =
+        build_and_save_config(options, name, image, pair, &details.conf_overrides).unwrap();
     debug!("{} volume: {}", name, lnd_conf.path_vol);
 
-    let rest_port = options.new_port();
-    let grpc_port = options.new_port();
-    let p2p_port = options.new_port();
+    let rest_port = options.new_port(&lnd_conf.container_name, &lnd_conf.rest_port)?;
+    let grpc_port = options.new_port(&lnd_conf.container_name, &lnd_conf.grpc_port)?;
+    let p2p_port = options.new_port(&lnd_conf.container_name, &lnd_conf.p2p_port)?;
     let bitcoind = vec![lnd_conf.bitcoind_node_container_name.clone()];
-    let lnd = Service {
+    let mut lnd = Service {
         depends_on: DependsOnOptions::Simple(bitcoind),
         image: Some(image.get_image()),
+        build_: image.get_build_step(),
         container_name: Some(lnd_conf.container_name.clone()),
         ports: Ports::Short(vec![
             format!("{}:{}", p2p_port, lnd_conf.p2p_port),
@@ -328,6 +342,8 @@ pub fn build_lnd(
         networks: Networks::Simple(vec![NETWORK.to_owned()]),
         ..Default::default()
     };
+    details.service_settings.apply(&mut lnd);
+    add_net_admin(options, &mut lnd);
 
     options
         .services
@@ -361,6 +377,7 @@ fn build_and_save_config(
     name: &str,
     _image: &ImageInfo,
     pair: &NodePair,
+    conf_overrides: &[String],
 ) -> Result<Lnd, Error> {
     if options.bitcoinds.is_empty() {
         return Err(anyhow!(
@@ -385,10 +402,17 @@ fn build_and_save_config(
         bitcoind_node = node;
     }
 
-    set_l1_values(&mut conf, &options.network, bitcoind_node)?;
+    set_l1_values(&mut conf, options.network.as_str(), bitcoind_node)?;
+    if let Some(signet) = options.custom_signet.as_ref() {
+        conf.sections
+            .get_mut("Bitcoin")
+            .unwrap()
+            .set_property("bitcoin.signetchallenge", &signet.get_challenge());
+    }
     let container_name = format!("doppler-lnd-{}", name);
 
     set_application_options_values(&mut conf, name, &container_name)?;
+    set_conf_overrides(&mut conf, conf_overrides, "Application Options")?;
 
     let _ = copy_file(&conf, &destination_dir.clone(), "lnd.conf")?;
     let full_path = get_absolute_path(destination_dir)?
@@ -397,8 +421,7 @@ fn build_and_save_config(
         .to_string();
     let macaroon_path = format!(
         "{}/data/chain/bitcoin/{}/admin.macaroon",
-        full_path,
-        options.network.clone()
+        full_path, options.network
     );
     let server_url = format!("http://{}:10000", container_name);
     Ok(Lnd {
@@ -418,7 +441,7 @@ fn build_and_save_config(
         bitcoind_node_container_name: bitcoind_node.container_name.clone(),
         lnd_cli: LndCli,
         lnd_rest: None, //we set this later in the process when the forwarding ports are determined
-        network: options.network.clone(),
+        network: options.network.to_string(),
     })
 }
 
@@ -478,17 +501,20 @@ pub fn load_config(
 
 pub fn load_external_node_config(external_node: &ExternalNode) -> Result<Lnd, Error> {
     let server_url = format!("https://{}:8080", external_node.api_endpoint);
-    let lnd_rest = Some(LndRest::new(
+    // external nodes are only reachable over rest, so the client is always authenticated
+    let lnd_rest = Some(add_rest_client(LndRest::new(
         &server_url,
         external_node.macaroon_path.clone(),
         external_node.tls_cert_path.clone(),
-    )?);
+    )?)?);
 
+    // other nodes reach it through its container name, which for external nodes is its p2p host
+    let (p2p_host, p2p_port) = external_node.get_p2p_address();
     Ok(Lnd {
         wallet_starting_balance: 0,
         name: external_node.node_alias.to_owned(),
         alias: external_node.node_alias.to_owned(),
-        container_name: external_node.node_alias.clone(),
+        container_name: p2p_host,
         pubkey: None,
         rpc_server: format!("{}:10000", external_node.api_endpoint),
         server_url: server_url.clone(),
@@ -497,7 +523,7 @@ pub fn load_external_node_config(external_node: &ExternalNode) -> Result<Lnd, Er
         path_vol: String::from(""),
         grpc_port: "10000".to_owned(),
         rest_port: "8080".to_owned(),
-        p2p_port: "9735".to_owned(),
+        p2p_port,
         bitcoind_node_container_name: String::from(""),
         lnd_cli: LndCli,
         lnd_rest: lnd_rest,
@@ -604,7 +630,7 @@ pub fn add_lnd_nodes(options: &mut Options) -> Result<(), Error> {
                     bitcoind_service,
                     rest_port,
                     options.rest,
-                    &options.network,
+                    options.network.as_str(),
                 );
             }
             load_config(
@@ -613,7 +639,7 @@ pub fn add_lnd_nodes(options: &mut Options) -> Result<(), Error> {
                 bitcoind_service,
                 None,
                 options.rest,
-                &options.network,
+                options.network.as_str(),
             )
         })
         .filter_map(|res| res.ok())
@@ -638,6 +664,7 @@ pub fn add_external_lnd_nodes(options: &mut Options) -> Result<(), Error> {
         .clone()
         .unwrap()
         .iter()
+        .filter(|external_node| external_node.kind == LnNodeKind::Lnd)
         .map(|external_node| load_external_node_config(external_node))
         .filter_map(|res| res.ok())
         .collect();
@@ -650,6 +677,6 @@ pub fn add_external_lnd_nodes(options: &mut Options) -> Result<(), Error> {
         })
         .collect();
 
-    options.lnd_nodes = nodes;
+    options.lnd_nodes.extend(nodes);
     Ok(())
 }
@@ -11,7 +11,7 @@ impl LndCli {
         let rpc_command = lnd.get_rpc_server_command();
         let macaroon_path = lnd.get_macaroon_path();
         let compose_path = options.compose_path.as_ref().unwrap();
-        let network = format!("--network={}", options.network.clone());
+        let network = format!("--network={}", options.network);
         let commands = vec![
             "-f",
             compose_path,
@@ -58,7 +58,7 @@ impl LndCli {
         let rpc_command = lnd.get_rpc_server_command();
         let macaroon_path = lnd.get_macaroon_path();
         let compose_path = options.compose_path.as_ref().unwrap();
-        let network = format!("--network={}", options.network.clone());
+        let network = format!("--network={}", options.network);
 
         let commands = vec![
             "-f",
@@ -99,7 +99,7 @@ impl LndCli {
         let macaroon_path = node.get_macaroon_path();
         let compose_path = options.compose_path.as_ref().unwrap();
         let to_pubkey = to_node.get_cached_pubkey();
-        let network = format!("--network={}", options.network.clone());
+        let network = format!("--network={}", options.network);
 
         let commands = vec![
             "-f",
@@ -151,7 +151,7 @@ impl LndCli {
         let rpc_command = node.get_rpc_server_command();
         let macaroon_path = node.get_macaroon_path();
         let compose_path = options.compose_path.as_ref().unwrap();
-        let network = format!("--network={}", options.network.clone());
+        let network = format!("--network={}", options.network);
 
         let commands = vec![
             "-f",
@@ -212,14 +212,14 @@ impl LndCli {
         node: &Lnd,
         options: &Options,
         node_command: &NodeCommand,
-    ) -> Result<(), Error> {
+    ) -> Result<String, Error> {
         //TODO: add a user defined tag to channels to specify which channel to close, right now we just grab a random one for this peer
         let peer_channel_point = node.get_peers_channel_point(options, node_command)?;
         let to_node = options.get_l2_by_name(node_command.to.as_str())?;
         let rpc_command = node.get_rpc_server_command();
         let macaroon_path = node.get_macaroon_path();
         let compose_path = options.compose_path.as_ref().unwrap();
-        let network = format!("--network={}", options.network.clone());
+        let network = format!("--network={}", options.network);
 
         let commands = vec![
             "-f",
@@ -251,8 +251,11 @@ impl LndCli {
                 node.get_name(),
                 to_node.get_name()
             );
+            return Ok("".to_owned());
         }
-        Ok(())
+        Ok(node
+            .get_property("closing_txid", output)
+            .unwrap_or_default())
     }
 
     pub fn force_close_channel(
@@ -260,14 +263,14 @@ impl LndCli {
         node: &Lnd,
         options: &Options,
         node_command: &NodeCommand,
-    ) -> Result<(), Error> {
+    ) -> Result<String, Error> {
         //TODO: add a user defined tag to channels to specify which channel to close, right now we just grab a random one for this peer
         let peer_channel_point = node.get_peers_channel_point(options, node_command)?;
         let to_node = options.get_l2_by_name(node_command.to.as_str())?;
         let rpc_command = node.get_rpc_server_command();
         let macaroon_path = node.get_macaroon_path();
         let compose_path = options.compose_path.as_ref().unwrap();
-        let network = format!("--network={}", options.network.clone());
+        let network = format!("--network={}", options.network);
 
         let commands = vec![
             "-f",
@@ -300,8 +303,11 @@ impl LndCli {
                 node.get_name(),
                 to_node.get_name()
             );
+            return Ok("".to_owned());
         }
-        Ok(())
+        Ok(node
+            .get_property("closing_txid", output)
+            .unwrap_or_default())
     }
 
     pub fn get_peers_channel_point(
@@ -315,7 +321,7 @@ impl LndCli {
         let macaroon_path = node.get_macaroon_path();
         let compose_path = options.compose_path.as_ref().unwrap();
         let to_pubkey = to_node.get_cached_pubkey();
-        let network = format!("--network={}", options.network.clone());
+        let network = format!("--network={}", options.network);
 
         let commands = vec![
             "-f",
@@ -352,7 +358,7 @@ impl LndCli {
         let rpc_command = node.get_rpc_server_command();
         let macaroon_path = node.get_macaroon_path();
         let compose_path = options.compose_path.as_ref().unwrap();
-        let network = format!("--network={}", options.network.clone());
+        let network = format!("--network={}", options.network);
 
         let commands = vec![
             "-f",
@@ -390,7 +396,7 @@ impl LndCli {
         let rpc_command = node.get_rpc_server_command();
         let macaroon_path = node.get_macaroon_path();
         let compose_path = options.compose_path.as_ref().unwrap();
-        let network = format!("--network={}", options.network.clone());
+        let network = format!("--network={}", options.network);
 
         let mut commands = vec![
             "-f",
@@ -453,7 +459,7 @@ impl LndCli {
         let rpc_command = node.get_rpc_server_command();
         let macaroon_path = node.get_macaroon_path();
         let compose_path = options.compose_path.as_ref().unwrap();
-        let network = format!("--network={}", options.network.clone());
+        let network = format!("--network={}", options.network);
 
         let commands = vec![
             "-f",
@@ -500,9 +506,12 @@ impl LndCli {
         let rpc_command = node.get_rpc_server_command();
         let macaroon_path = node.get_macaroon_path();
         let compose_path = options.compose_path.as_ref().unwrap();
-        let network = format!("--network={}", options.network.clone());
+        let network = format!("--network={}", options.network);
+        let fee_rate = node_command
+            .fee_rate
+            .map(|fee_rate| format!("--sat_per_vbyte={}", fee_rate));
 
-        let commands = vec![
+        let mut commands = vec![
             "-f",
             compose_path,
             "exec",
@@ -521,6 +530,9 @@ impl LndCli {
             "--amt",
             &amt,
         ];
+        if let Some(fee_rate) = fee_rate.as_ref() {
+            commands.push(fee_rate);
+        }
         let output = run_command(options, "sendcoins".to_owned(), commands)?;
         let found_tx_id: Option<String> = node.get_property("txid", output);
         if found_tx_id.is_none() {
@@ -544,7 +556,7 @@ impl LndCli {
         let rpc_command = node.get_rpc_server_command();
         let macaroon_path = node.get_macaroon_path();
         let compose_path = options.compose_path.as_ref().unwrap();
-        let network = format!("--network={}", options.network.clone());
+        let network = format!("--network={}", options.network);
 
         let commands = vec![
             "-f",
@@ -578,7 +590,7 @@ impl LndCli {
         let rpc_command = node.get_rpc_server_command();
         let macaroon_path = node.get_macaroon_path();
         let compose_path = options.compose_path.as_ref().unwrap();
-        let network = format!("--network={}", options.network.clone());
+        let network = format!("--network={}", options.network);
 
         let commands = vec![
             "-f",
@@ -615,7 +627,7 @@ impl LndCli {
         let rpc_command = node.get_rpc_server_command();
         let macaroon_path = node.get_macaroon_path();
         let compose_path = options.compose_path.as_ref().unwrap();
-        let network = format!("--network={}", options.network.clone());
+        let network = format!("--network={}", options.network);
 
         let commands = vec![
             "-f",
@@ -651,7 +663,7 @@ impl LndCli {
         let rpc_command = node.get_rpc_server_command();
         let macaroon_path = node.get_macaroon_path();
         let compose_path = options.compose_path.as_ref().unwrap();
-        let network = format!("--network={}", options.network.clone());
+        let network = format!("--network={}", options.network);
 
         let commands = vec![
             "-f",
@@ -681,7 +693,7 @@ impl LndCli {
         let rpc_command = node.get_rpc_server_command();
         let macaroon_path = node.get_macaroon_path();
         let compose_path = options.compose_path.as_ref().unwrap();
-        let network = format!("--network={}", options.network.clone());
+        let network = format!("--network={}", options.network);
 
         let commands = vec![
             "-f",
@@ -9,7 +9,12 @@ use reqwest::{
     Certificate, Method,
 };
 use serde_json::{json, Value};
-use std::{fs::OpenOptions, io::Read, thread, time::Duration};
+use std::{
+    fs::OpenOptions,
+    io::{BufRead, BufReader, Read},
+    thread,
+    time::Duration,
+};
 
 #[derive(Debug, Clone)]
 pub struct LndRest {
@@ -110,6 +115,17 @@ impl LndRest {
         Ok("".to_owned())
     }
 
+    pub fn get_network(&self) -> Result<String, Error> {
+        let url = self.build_url("/v1/getinfo");
+        let info: Value = self
+            .send_request("getinfo".to_owned(), Method::GET, url, None, None)?
+            .json()?;
+        info["chains"][0]["network"]
+            .as_str()
+            .map(str::to_owned)
+            .ok_or(anyhow!("getinfo has no network: {}", info))
+    }
+
     pub fn create_lnd_address(&self, _options: &Options) -> Result<String, Error> {
         let url = self.build_url("/v1/newaddress?type=UNUSED_TAPROOT_PUBKEY");
         let result: Value = self
@@ -247,7 +263,7 @@ impl LndRest {
         node: &Lnd,
         options: &Options,
         node_command: &NodeCommand,
-    ) -> Result<(), Error> {
+    ) -> Result<String, Error> {
         //TODO: add a user defined tag to channels to specify which channel to close, right now we just grab a random one for this peer
         let peer_channel_point = node.get_peers_channel_point(options, node_command)?;
         let parts: Vec<&str> = peer_channel_point.split(':').collect();
@@ -269,8 +285,9 @@ impl LndRest {
                 to_node.get_name(),
                 result.text()?
             );
+            return Ok("".to_owned());
         }
-        Ok(())
+        get_closing_txid(result)
     }
 
     pub fn force_close_channel(
@@ -278,7 +295,7 @@ impl LndRest {
         node: &Lnd,
         options: &Options,
         node_command: &NodeCommand,
-    ) -> Result<(), Error> {
+    ) -> Result<String, Error> {
         //TODO: add a user defined tag to channels to specify which channel to close, right now we just grab a random one for this peer
         let peer_channel_point = node.get_peers_channel_point(options, node_command)?;
         let parts: Vec<&str> = peer_channel_point.split(':').collect();
@@ -304,8 +321,9 @@ impl LndRest {
                 node.get_name(),
                 to_node.get_name()
             );
+            return Ok("".to_owned());
         }
-        Ok(())
+        get_closing_txid(result)
     }
 
     pub fn get_peers_channel_point(
@@ -482,10 +500,13 @@ impl LndRest {
     ) -> Result<String, Error> {
         let amt = node_command.amt.unwrap_or(1000).to_string();
         let url: String = self.build_url("/v1/transactions");
-        let body = json!({
+        let mut body = json!({
                 "addr":address,
                 "amount": amt
         });
+        if let Some(fee_rate) = node_command.fee_rate {
+            body["sat_per_vbyte"] = json!(fee_rate.to_string());
+        }
         let result = self.send_request(
             "sendcoins".to_owned(),
             Method::POST,
@@ -655,6 +676,21 @@ pub fn add_rest_client(rest_client: LndRest) -> Result<LndRest, Error> {
     Ok(rest_client_cpy)
 }
 
+// closing a channel streams updates until it confirms, the first one has the broadcast txid
+fn get_closing_txid(response: Response) -> Result<String, Error> {
+    let mut update = String::new();
+    BufReader::new(response).read_line(&mut update)?;
+    let update: Value = serde_json::from_str(&update)?;
+    let Some(txid) = update["result"]["close_pending"]["txid"].as_str() else {
+        debug!("no pending close in the first update: {}", update);
+        return Ok("".to_owned());
+    };
+    // lnd sends the txid bytes in their internal order
+    let mut bytes = BASE64_STANDARD.decode(txid)?;
+    bytes.reverse();
+    Ok(hex::encode(bytes))
+}
+
 pub fn base64_url_safe(base64_str: &str) -> String {
     base64_str.replace("+", "-").replace("/", "_")
 }
@@ -1,8 +1,11 @@
 use clap::{arg, command, Parser};
-use doppler::{create_db, get_absolute_path, run_workflow_until_stop, AppSubCommands, Options};
+use doppler::{
+    create_db, get_absolute_path, run_workflow_until_stop, AppSubCommands, ComposeCommand,
+    CustomSignet, EngineBackend, FaucetConfig, Network, Options, PortRange, SpendBudget,
+};
 use fern::colors::{Color, ColoredLevelConfig};
 use log::{debug, info, LevelFilter};
-use std::{env, fs, io::Error, path::PathBuf};
+use std::{env, fs, io::Error, path::PathBuf, sync::Arc};
 use time::{format_description::well_known::Iso8601, OffsetDateTime};
 
 #[derive(Parser)]
@@ -15,27 +18,79 @@ pub struct Cli {
     /// Set the log level
     #[arg(short, long)]
     level: Option<String>,
-    /// Set docker compose command with/without '-'
+    /// Use the standalone `docker-compose` binary instead of detecting the compose implementation
     #[arg(short, long)]
     docker_dash: bool,
 
+    /// Run exec/stop/start on the containers through the Docker Engine API (DOCKER_HOST or /var/run/docker.sock)
+    /// instead of spawning a compose process per call
+    #[arg(long)]
+    docker_api: bool,
+
     /// Path to doppler.db, stores tags
     #[arg(short, long, default_value = "./doppler.db")]
     storage_path: String,
 
-    /// Set communication with LND to be REST instead of CLI
+    /// Set communication with LND to be REST and with bitcoind to be JSON-RPC instead of CLI
     #[arg(short, long)]
     rest: bool,
 
     /// Set network lightning nodes and bitcoind are running on, default to regtest
-    #[arg(short, long, default_value = "regtest")]
-    network: String,
+    #[arg(short, long, value_enum, default_value_t = Network::Regtest)]
+    network: Network,
+
+    /// Create a signet only this cluster can mine: generates a challenge key, configures every bitcoind with it
+    /// and signs a block every --signet-block-interval seconds on the miners. Implies --network signet
+    #[arg(long, conflicts_with = "network")]
+    custom_signet: bool,
+
+    /// Seconds between the blocks mined on a --custom-signet
+    #[arg(long, default_value = "30", requires = "custom_signet")]
+    signet_block_interval: u64,
 
-    /// Path to override file for external LND nodes
-    /// Doppler scripts can only use these nodes matching aliases when set
+    /// Path to override file for external LND, CoreLN and Eclair nodes
+    /// Doppler scripts can only use these nodes matching aliases when set, unless --hybrid is set
     #[arg(short, long)]
     external_nodes: Option<String>,
 
+    /// Run the local docker cluster and the nodes from --external-nodes side by side,
+    /// actions are routed to the local or remote node by name
+    #[arg(long, requires = "external_nodes")]
+    hybrid: bool,
+
+    /// Allow running against mainnet nodes, refused otherwise
+    #[arg(long)]
+    i_know_this_is_mainnet: bool,
+
+    /// Max sats all nodes together may spend on payments, channel opens and on chain sends during the run
+    #[arg(long)]
+    budget_sats: Option<i64>,
+
+    /// Max sats a single node may spend on payments, channel opens and on chain sends during the run
+    #[arg(long)]
+    node_budget_sats: Option<i64>,
+
+    /// Http faucet the l2 wallets are funded from on signet/testnet, POST {"address", "sats"} returning the txid
+    #[arg(long, conflicts_with = "faucet_wallet")]
+    faucet_url: Option<String>,
+
+    /// Esplora api used to wait for the --faucet-url funding to confirm, ie. https://mutinynet.com/api
+    #[arg(long, requires = "faucet_url")]
+    faucet_esplora: Option<String>,
+
+    /// Name of a bitcoind (in the cluster or the external nodes file) with a pre-funded wallet to fund the l2 wallets from
+    #[arg(long)]
+    faucet_wallet: Option<String>,
+
+    /// Confirmations the faucet funding needs before the doppler file continues
+    #[arg(long, default_value = "1")]
+    faucet_confirmations: i64,
+
+    /// Range of host ports the cluster's published ports are picked from, ie. 9090-9999
+    /// Ports already in use on the host are skipped
+    #[arg(long, default_value = "9090-9999")]
+    port_range: PortRange,
+
     #[command(subcommand)]
     app_sub_commands: Option<AppSubCommands>,
 
@@ -54,15 +109,65 @@ fn main() -> Result<(), Error> {
     debug!("doppler.db location: {}", cli.storage_path);
     let conn = create_db(cli.storage_path).expect("failed to create doppler.db file");
     info!("rest {}", cli.rest);
+    if cli.network == Network::Mainnet {
+        if !cli.i_know_this_is_mainnet {
+            return Err(Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "refusing to run against mainnet without --i-know-this-is-mainnet",
+            ));
+        }
+        if cli.external_nodes.is_none() || cli.hybrid {
+            return Err(Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "doppler can't start a local cluster on mainnet, only --external-nodes can be used",
+            ));
+        }
+    }
+    let network = if cli.custom_signet {
+        Network::Signet
+    } else {
+        cli.network
+    };
+    // a run against external nodes only never builds a cluster, compose isn't needed on the host
+    let docker_command = if cli.external_nodes.is_some() && !cli.hybrid {
+        ComposeCommand::default()
+    } else {
+        ComposeCommand::detect(cli.docker_dash)
+            .map_err(|e| Error::new(std::io::ErrorKind::NotFound, e.to_string()))?
+    };
     let mut options = Options::new(
-        cli.docker_dash,
+        docker_command,
         cli.ui_config_path,
         cli.app_sub_commands,
         conn,
         cli.rest,
         cli.external_nodes,
-        cli.network,
+        cli.hybrid,
+        network,
+        cli.port_range,
     );
+    options.allow_mainnet = cli.i_know_this_is_mainnet;
+    options.budget = SpendBudget::new(cli.budget_sats, cli.node_budget_sats);
+    options.faucet = match (cli.faucet_url, cli.faucet_wallet) {
+        (Some(url), _) => Some(FaucetConfig::Http {
+            url,
+            esplora_url: cli.faucet_esplora,
+        }),
+        (None, Some(name)) => Some(FaucetConfig::Wallet(name)),
+        (None, None) => None,
+    };
+    options.faucet_confirmations = cli.faucet_confirmations;
+    if cli.custom_signet {
+        let signet = CustomSignet::load_or_create(cli.signet_block_interval)
+            .map_err(|e| Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+        info!("custom signet challenge: {}", signet.get_challenge());
+        options.custom_signet = Some(signet);
+    }
+    if cli.docker_api {
+        let backend = EngineBackend::from_env()
+            .map_err(|e| Error::new(std::io::ErrorKind::NotFound, e.to_string()))?;
+        options.backend = Arc::new(backend);
+    }
     run_workflow_until_stop(&mut options, contents)?;
     info!("successfully cleaned up processes, shutting down");
     Ok(())
@@ -0,0 +1,349 @@
+use crate::{Bitcoind, L1Node, MineFilter, Network, Options};
+use anyhow::{anyhow, Error, Result};
+use log::info;
+use serde_json::json;
+
+// more than any transaction can pay, the block templates skip it and everything spending it
+const EXCLUDE_FEE_DELTA: i64 = -2_100_000_000_000_000;
+// confirmations before a coinbase output can be spent
+const COINBASE_MATURITY: i64 = 100;
+
+/// MINE_BLOCKS n EXCLUDE TAG tx: mines n blocks that leave the tagged transactions in the mempool
+/// MINE_BLOCKS n ONLY TAG tx: mines a block with just the tagged transactions, the other n-1 are empty
+pub fn mine_filtered(
+    options: &Options,
+    node: &Bitcoind,
+    num_blocks: i64,
+    filter: &MineFilter,
+) -> Result<(), Error> {
+    if !options.can_mine() {
+        return Err(anyhow!(
+            "filtered mining needs doppler to mine the blocks, not possible on {}",
+            options.network
+        ));
+    }
+    let tx_ids = match filter {
+        MineFilter::Exclude(tags) | MineFilter::Only(tags) => get_tx_ids(options, tags)?,
+    };
+    match filter {
+        MineFilter::Exclude(_) => mine_excluding(options, node, num_blocks, &tx_ids),
+        MineFilter::Only(_) => mine_only(options, node, num_blocks, &tx_ids),
+    }
+}
+
+/// MINE_BLOCKS n TO lnd1: pays the coinbase of the n blocks to an address of the L2 node's wallet
+pub fn mine_to_l2(
+    options: &Options,
+    node: &Bitcoind,
+    num_blocks: i64,
+    to: &str,
+) -> Result<(), Error> {
+    if !options.can_mine() {
+        return Err(anyhow!(
+            "mining to a node needs doppler to mine the blocks, not possible on {}",
+            options.network
+        ));
+    }
+    let address = options
+        .get_l2_by_name(to)?
+        .create_on_chain_address(options)?;
+    node.clone()
+        .mine_to_address(options, num_blocks, address.clone())?;
+    info!(
+        "({}): mined {} block(s) to {} ({}), the coinbases mature after {} blocks",
+        node.get_name(),
+        num_blocks,
+        to,
+        address,
+        COINBASE_MATURITY
+    );
+    Ok(())
+}
+
+fn mine_excluding(
+    options: &Options,
+    node: &Bitcoind,
+    num_blocks: i64,
+    tx_ids: &[String],
+) -> Result<(), Error> {
+    let mut excluded = vec![];
+    let mut mined = Ok(());
+    for tx_id in tx_ids {
+        if let Err(e) = prioritise(options, node, tx_id, EXCLUDE_FEE_DELTA) {
+            mined = Err(e);
+            break;
+        }
+        excluded.push(tx_id.as_str());
+    }
+    if mined.is_ok() {
+        mined = node.mine_bitcoin(options, num_blocks).map(|_| ());
+    }
+    // the deltas stay on the node until it restarts, later blocks should pick the transactions up again
+    let restore_errors: Vec<String> = excluded
+        .iter()
+        .filter_map(|tx_id| {
+            prioritise(options, node, tx_id, -EXCLUDE_FEE_DELTA)
+                .err()
+                .map(|e| format!("{}: {}", tx_id, e))
+        })
+        .collect();
+    match (mined, restore_errors.is_empty()) {
+        (Ok(()), true) => {}
+        (Ok(()), false) => {
+            return Err(anyhow!(
+                "mined {} block(s) but failed to restore the fee of {}",
+                num_blocks,
+                restore_errors.join(", ")
+            ))
+        }
+        (Err(e), true) => return Err(e),
+        (Err(e), false) => {
+            return Err(anyhow!(
+                "{}, also failed to restore the fee of {}",
+                e,
+                restore_errors.join(", ")
+            ))
+        }
+    }
+    info!(
+        "({}): mined {} block(s) without {}",
+        node.get_name(),
+        num_blocks,
+        tx_ids.join(", ")
+    );
+    Ok(())
+}
+
+fn mine_only(
+    options: &Options,
+    node: &Bitcoind,
+    num_blocks: i64,
+    tx_ids: &[String],
+) -> Result<(), Error> {
+    // generateblock builds an unsigned block, custom signet blocks need the signet miner
+    if options.network != Network::Regtest {
+        return Err(anyhow!("MINE_BLOCKS ONLY only works on regtest"));
+    }
+    let address = node.create_address(options)?;
+    for block in 0..num_blocks {
+        let transactions = if block == 0 { tx_ids.to_vec() } else { vec![] };
+        let result = node.call(
+            options,
+            "generateblock",
+            &[json!(address), json!(transactions)],
+        )?;
+        info!(
+            "({}): mined block {} with {} transaction(s)",
+            node.get_name(),
+            result["hash"].as_str().unwrap_or_default(),
+            transactions.len()
+        );
+    }
+    Ok(())
+}
+
+fn prioritise(
+    options: &Options,
+    node: &Bitcoind,
+    tx_id: &str,
+    fee_delta: i64,
+) -> Result<(), Error> {
+    // the second argument is a dummy kept for compatibility, it has to be 0
+    node.call(
+        options,
+        "prioritisetransaction",
+        &[json!(tx_id), json!(0), json!(fee_delta)],
+    )?;
+    Ok(())
+}
+
+fn get_tx_ids(options: &Options, tags: &[String]) -> Result<Vec<String>, Error> {
+    tags.iter()
+        .map(|name| {
+            let tag = options.get_tag_by_name(name.to_owned());
+            if tag.val.is_empty() {
+                return Err(anyhow!("no transaction tagged {}", name));
+            }
+            Ok(tag.val)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ContainerBackend, Lnd, Tag};
+    use std::{
+        os::unix::process::ExitStatusExt,
+        process::{ExitStatus, Output},
+        sync::{Arc, Mutex},
+    };
+
+    // records each bitcoin-cli rpc with its arguments, prioritising `fail_tx` fails
+    #[derive(Default)]
+    struct MiningBackend {
+        calls: Mutex<Vec<String>>,
+        fail_tx: Option<&'static str>,
+    }
+
+    impl ContainerBackend for MiningBackend {
+        fn exec(
+            &self,
+            _options: &Options,
+            _command_name: &str,
+            _container: &str,
+            _user: Option<&str>,
+            cmd: &[&str],
+        ) -> Result<Output, Error> {
+            // flags start with a letter after the dash, the fee deltas are negative numbers
+            let rpc: Vec<&str> = cmd
+                .iter()
+                .skip(1)
+                .filter(|arg| {
+                    !(arg.starts_with('-')
+                        && arg.trim_start_matches('-').starts_with(char::is_alphabetic))
+                })
+                .copied()
+                .collect();
+            self.calls.lock().unwrap().push(rpc.join(" "));
+            let failed = rpc[0] == "prioritisetransaction" && self.fail_tx == Some(rpc[1]);
+            let stdout = match rpc[0] {
+                "getnewaddress" => "bcrt1qtest\n",
+                "generateblock" => r#"{"hash": "00ef"}"#,
+                "newaddress" => r#"{"address": "bcrt1plnd"}"#,
+                _ => "",
+            };
+            Ok(Output {
+                status: ExitStatus::from_raw(if failed { 1 << 8 } else { 0 }),
+                stdout: stdout.as_bytes().to_vec(),
+                stderr: vec![],
+            })
+        }
+        fn stop(&self, _options: &Options, _container: &str) -> Result<Output, Error> {
+            unreachable!()
+        }
+        fn start(&self, _options: &Options, _container: &str) -> Result<Output, Error> {
+            unreachable!()
+        }
+        fn kill(&self, _options: &Options, _container: &str) -> Result<Output, Error> {
+            unreachable!()
+        }
+    }
+
+    fn mining_options(network: Network, backend: MiningBackend) -> (Options, Arc<MiningBackend>) {
+        let mut options = Options::for_tests(network);
+        let backend = Arc::new(backend);
+        options.backend = backend.clone();
+        options.bitcoinds = vec![Bitcoind {
+            name: "bd1".to_owned(),
+            container_name: "doppler-bitcoind-bd1".to_owned(),
+            ..Default::default()
+        }];
+        options.lnd_nodes = vec![Lnd {
+            name: "lnd1".to_owned(),
+            container_name: "doppler-lnd-lnd1".to_owned(),
+            ..Default::default()
+        }];
+        for (name, val) in [("stuck", "aa01"), ("filler", "aa02")] {
+            options
+                .save_tag(&Tag {
+                    name: name.to_owned(),
+                    val: val.to_owned(),
+                })
+                .unwrap();
+        }
+        (options, backend)
+    }
+
+    fn tags(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn excludes_tagged_transactions_and_restores_their_fee() {
+        let (options, backend) = mining_options(Network::Regtest, MiningBackend::default());
+        let node = &options.bitcoinds[0];
+        let filter = MineFilter::Exclude(tags(&["stuck", "filler"]));
+        mine_filtered(&options, node, 2, &filter).unwrap();
+        assert_eq!(
+            *backend.calls.lock().unwrap(),
+            vec![
+                "prioritisetransaction aa01 0 -2100000000000000",
+                "prioritisetransaction aa02 0 -2100000000000000",
+                "getnewaddress",
+                "generatetoaddress 2 bcrt1qtest",
+                "prioritisetransaction aa01 0 2100000000000000",
+                "prioritisetransaction aa02 0 2100000000000000",
+            ]
+        );
+    }
+
+    #[test]
+    fn restores_the_fee_when_excluding_fails() {
+        let backend = MiningBackend {
+            fail_tx: Some("aa02"),
+            ..Default::default()
+        };
+        let (options, backend) = mining_options(Network::Regtest, backend);
+        let filter = MineFilter::Exclude(tags(&["stuck", "filler"]));
+        assert!(mine_filtered(&options, &options.bitcoinds[0], 1, &filter).is_err());
+        // nothing is mined and only the transaction that got the delta is restored
+        assert_eq!(
+            *backend.calls.lock().unwrap(),
+            vec![
+                "prioritisetransaction aa01 0 -2100000000000000",
+                "prioritisetransaction aa02 0 -2100000000000000",
+                "prioritisetransaction aa01 0 2100000000000000",
+            ]
+        );
+    }
+
+    #[test]
+    fn mines_only_the_tagged_transactions_in_the_first_block() {
+        let (options, backend) = mining_options(Network::Regtest, MiningBackend::default());
+        let filter = MineFilter::Only(tags(&["filler"]));
+        mine_filtered(&options, &options.bitcoinds[0], 2, &filter).unwrap();
+        assert_eq!(
+            *backend.calls.lock().unwrap(),
+            vec![
+                "getnewaddress",
+                r#"generateblock bcrt1qtest ["aa02"]"#,
+                "generateblock bcrt1qtest []",
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_tags_and_networks_it_cant_mine() {
+        let (options, backend) = mining_options(Network::Regtest, MiningBackend::default());
+        let filter = MineFilter::Exclude(tags(&["stuck", "missing"]));
+        let error = mine_filtered(&options, &options.bitcoinds[0], 1, &filter).unwrap_err();
+        assert!(error.to_string().contains("no transaction tagged missing"));
+        assert!(backend.calls.lock().unwrap().is_empty());
+
+        let (options, backend) = mining_options(Network::Signet, MiningBackend::default());
+        let filter = MineFilter::Only(tags(&["stuck"]));
+        assert!(mine_filtered(&options, &options.bitcoinds[0], 1, &filter).is_err());
+        assert!(backend.calls.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn mines_coinbases_to_an_l2_wallet() {
+        let (options, backend) = mining_options(Network::Regtest, MiningBackend::default());
+        mine_to_l2(&options, &options.bitcoinds[0], 101, "lnd1").unwrap();
+        assert_eq!(
+            *backend.calls.lock().unwrap(),
+            vec!["newaddress p2tr", "generatetoaddress 101 bcrt1plnd"]
+        );
+    }
+
+    #[test]
+    fn mines_to_known_l2_nodes_on_networks_it_can_mine() {
+        let (options, backend) = mining_options(Network::Regtest, MiningBackend::default());
+        assert!(mine_to_l2(&options, &options.bitcoinds[0], 1, "cln1").is_err());
+        let (options, _) = mining_options(Network::Signet, MiningBackend::default());
+        let error = mine_to_l2(&options, &options.bitcoinds[0], 1, "lnd1").unwrap_err();
+        assert!(error.to_string().contains("not possible on signet"));
+        assert!(backend.calls.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,127 @@
+use crate::{get_local_container_name, run_in_network_namespace, NodeCommand, Options};
+use anyhow::{Error, Result};
+use log::{debug, info};
+use std::str::from_utf8;
+
+// every node service is on the doppler network only, which docker attaches as the first interface
+const INTERFACE: &str = "eth0";
+
+/// NETEM lnd2 DELAY 200ms JITTER 50ms LOSS 2%: shapes all traffic leaving the container,
+/// NETEM lnd2 CLEAR removes it again. The netem arguments come in the command's subcommand.
+pub fn apply_netem(options: &Options, node_command: &NodeCommand) -> Result<(), Error> {
+    let container = get_local_container_name(options, &node_command.from)?;
+    let script = match node_command.subcommand.as_ref() {
+        Some(netem) => format!("tc qdisc replace dev {} root netem {}", INTERFACE, netem),
+        None => format!("tc qdisc del dev {} root 2>/dev/null; true", INTERFACE),
+    };
+    run_tc(options, &container, &script)?;
+    match node_command.subcommand.as_ref() {
+        Some(netem) => info!("({}): netem {}", node_command.from, netem),
+        None => info!("({}): netem cleared", node_command.from),
+    }
+    Ok(())
+}
+
+// tc runs in the node's container, which has NET_ADMIN when the script uses NETEM, images without tc get it from the net tools container
+fn run_tc(options: &Options, container: &str, script: &str) -> Result<(), Error> {
+    let output = options.backend.exec(
+        options,
+        "netem",
+        container,
+        Some("0:0"),
+        &["sh", "-c", script],
+    )?;
+    if output.status.success() {
+        return Ok(());
+    }
+    debug!(
+        "tc failed in {}, retrying from the net tools container: {}",
+        container,
+        from_utf8(&output.stderr)?.trim()
+    );
+    run_in_network_namespace(options, container, script)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ContainerBackend, Lnd, Network};
+    use std::{
+        os::unix::process::ExitStatusExt,
+        process::{ExitStatus, Output},
+        sync::{Arc, Mutex},
+    };
+
+    // tc succeeds in the node's container, the net tools fallback is never needed
+    #[derive(Default)]
+    struct TcBackend {
+        scripts: Mutex<Vec<(String, String)>>,
+    }
+
+    impl ContainerBackend for TcBackend {
+        fn exec(
+            &self,
+            _options: &Options,
+            _command_name: &str,
+            container: &str,
+            user: Option<&str>,
+            cmd: &[&str],
+        ) -> Result<Output, Error> {
+            assert_eq!(user, Some("0:0"));
+            self.scripts
+                .lock()
+                .unwrap()
+                .push((container.to_owned(), cmd.join(" ")));
+            Ok(Output {
+                status: ExitStatus::from_raw(0),
+                stdout: vec![],
+                stderr: vec![],
+            })
+        }
+        fn stop(&self, _options: &Options, _container: &str) -> Result<Output, Error> {
+            unreachable!()
+        }
+        fn start(&self, _options: &Options, _container: &str) -> Result<Output, Error> {
+            unreachable!()
+        }
+        fn kill(&self, _options: &Options, _container: &str) -> Result<Output, Error> {
+            unreachable!()
+        }
+    }
+
+    #[test]
+    fn runs_tc_in_the_node_container() {
+        let mut options = Options::for_tests(Network::Regtest);
+        let backend = Arc::new(TcBackend::default());
+        options.backend = backend.clone();
+        options.lnd_nodes = vec![Lnd {
+            name: "lnd2".to_owned(),
+            container_name: "doppler-lnd-lnd2".to_owned(),
+            ..Default::default()
+        }];
+        let mut command = NodeCommand {
+            name: "NETEM".to_owned(),
+            from: "lnd2".to_owned(),
+            subcommand: Some("delay 200ms 50ms loss 2%".to_owned()),
+            ..Default::default()
+        };
+        apply_netem(&options, &command).unwrap();
+        command.subcommand = None;
+        apply_netem(&options, &command).unwrap();
+
+        assert_eq!(
+            *backend.scripts.lock().unwrap(),
+            vec![
+                (
+                    "doppler-lnd-lnd2".to_owned(),
+                    "sh -c tc qdisc replace dev eth0 root netem delay 200ms 50ms loss 2%"
+                        .to_owned()
+                ),
+                (
+                    "doppler-lnd-lnd2".to_owned(),
+                    "sh -c tc qdisc del dev eth0 root 2>/dev/null; true".to_owned()
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,118 @@
+use clap::ValueEnum;
+use std::fmt;
+
+/// Bitcoin network the cluster runs on, each node implementation has its own name for some of them
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum Network {
+    #[default]
+    Regtest,
+    Signet,
+    Testnet4,
+    // only reachable through --external-nodes, there are no local templates for it
+    #[value(alias = "main", alias = "bitcoin")]
+    Mainnet,
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl Network {
+    /// Name used by doppler (config templates, ui config) and by LND and Eclair
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Network::Regtest => "regtest",
+            Network::Signet => "signet",
+            Network::Testnet4 => "testnet4",
+            Network::Mainnet => "mainnet",
+        }
+    }
+
+    /// Section bitcoin.conf reads the network specific settings from
+    pub fn bitcoind_section(&self) -> &'static str {
+        match self {
+            Network::Mainnet => "main",
+            _ => self.as_str(),
+        }
+    }
+
+    pub fn bitcoind_p2p_port(&self) -> &'static str {
+        match self {
+            Network::Regtest => "18444",
+            Network::Signet => "38333",
+            Network::Testnet4 => "48333",
+            Network::Mainnet => "8333",
+        }
+    }
+
+    pub fn bitcoind_rpc_port(&self) -> &'static str {
+        match self {
+            Network::Regtest => "18443",
+            Network::Signet => "38332",
+            Network::Testnet4 => "48332",
+            Network::Mainnet => "8332",
+        }
+    }
+
+    /// CoreLN calls mainnet `bitcoin`, it's also the name of its data folder for the network
+    pub fn cln_network(&self) -> &'static str {
+        match self {
+            Network::Mainnet => "bitcoin",
+            _ => self.as_str(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // the networks doppler can build a local cluster for
+    const LOCAL_NETWORKS: [Network; 3] = [Network::Regtest, Network::Signet, Network::Testnet4];
+
+    #[test]
+    fn parses_network_names() {
+        assert_eq!(
+            Network::from_str("testnet4", true).unwrap(),
+            Network::Testnet4
+        );
+        assert_eq!(Network::from_str("main", true).unwrap(), Network::Mainnet);
+        assert_eq!(
+            Network::from_str("bitcoin", true).unwrap(),
+            Network::Mainnet
+        );
+        // testnet3 is gone, it has to be picked explicitly as testnet4
+        assert!(Network::from_str("testnet", true).is_err());
+    }
+
+    #[test]
+    fn maps_names_per_implementation() {
+        assert_eq!(Network::Testnet4.as_str(), "testnet4");
+        assert_eq!(Network::Testnet4.bitcoind_section(), "testnet4");
+        assert_eq!(Network::Testnet4.cln_network(), "testnet4");
+        assert_eq!(Network::Mainnet.bitcoind_section(), "main");
+        assert_eq!(Network::Mainnet.cln_network(), "bitcoin");
+        assert_eq!(Network::Testnet4.bitcoind_p2p_port(), "48333");
+        assert_eq!(Network::Testnet4.bitcoind_rpc_port(), "48332");
+    }
+
+    #[test]
+    fn has_config_templates_for_every_local_network() {
+        for network in LOCAL_NETWORKS {
+            let read = |file: &str| {
+                let path = format!("{}/config/{}/{}", env!("CARGO_MANIFEST_DIR"), network, file);
+                fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path, e))
+            };
+            let bitcoin = read("bitcoin.conf");
+            assert!(bitcoin.contains(&format!("[{}]", network.bitcoind_section())));
+            let cln = read("cln.conf");
+            assert!(cln.contains(&format!("\nnetwork={}\n", network.cln_network())));
+            let eclair = read("eclair.conf");
+            assert!(eclair.contains(&format!("eclair.chain = \"{}\"", network)));
+            read("lnd.conf");
+        }
+    }
+}
@@ -1,9 +1,10 @@
-use crate::{run_command, Bitcoind, NodeKind, Options};
-use anyhow::Error;
-use log::info;
+use crate::{run_command, Bitcoind, BitcoindRpc, NodeKind, Options, Tag};
+use anyhow::{anyhow, Error};
+use docker_compose_types::{AdvancedBuildStep, BuildStep};
+use log::{debug, info};
 use rand::Rng;
 use serde_yaml::{from_slice, Value};
-use std::{any::Any, process::Output};
+use std::{any::Any, process::Output, thread, time::Duration};
 
 pub trait L2Node: Any {
     fn stop(&self, options: &Options) -> Result<(), Error> {
@@ -29,14 +30,16 @@ pub trait L2Node: Any {
     fn get_node_pubkey(&self, options: &Options) -> Result<String, Error>;
     fn open_channel(&self, options: &Options, node_command: &NodeCommand) -> Result<(), Error>;
     fn connect(&self, options: &Options, node_command: &NodeCommand) -> Result<(), Error>;
-    fn close_channel(&self, options: &Options, node_command: &NodeCommand) -> Result<(), Error>;
+    // the close functions return the closing txid, empty when it isn't known
+    fn close_channel(&self, options: &Options, node_command: &NodeCommand)
+        -> Result<String, Error>;
     fn get_rhash(&self, option: &Options) -> Result<String, Error>;
     fn get_preimage(&self, option: &Options, rhash: String) -> Result<String, Error>;
     fn force_close_channel(
         &self,
         options: &Options,
         node_command: &NodeCommand,
-    ) -> Result<(), Error>;
+    ) -> Result<String, Error>;
     fn get_starting_wallet_balance(&self) -> i64;
     fn create_invoice(
         &self,
@@ -81,10 +84,24 @@ pub trait L2Node: Any {
     ) -> Result<String, Error>;
     fn settle_hold_invoice(&self, options: &Options, preimage: String) -> Result<(), Error>;
     fn send_on_chain(&self, options: &Options, node_command: &NodeCommand) -> Result<(), Error> {
-        let to_node = options.get_l2_by_name(&node_command.to)?;
-        let on_chain_address_from = to_node.create_on_chain_address(options)?;
+        // a bitcoind can receive too, ie. to CPFP the transaction from its wallet
+        let on_chain_address_from = match options.get_l2_by_name(&node_command.to) {
+            Ok(to_node) => to_node.create_on_chain_address(options)?,
+            Err(_) => options
+                .bitcoinds
+                .iter()
+                .find(|bitcoind| bitcoind.get_name() == node_command.to)
+                .ok_or(anyhow!("no node named {} to send to", node_command.to))?
+                .create_address(options)?,
+        };
         let tx_id = self.pay_address(options, node_command, on_chain_address_from.as_str())?;
         info!("on chain transaction created: {}", tx_id);
+        if let (Some(tag), false) = (node_command.tag.as_ref(), tx_id.is_empty()) {
+            options.save_tag(&Tag {
+                name: tag.to_owned(),
+                val: tx_id,
+            })?;
+        }
         Ok(())
     }
     fn fund_node(&self, options: &Options, miner: &Bitcoind) -> Result<(), Error> {
@@ -111,6 +128,8 @@ pub trait L2Node: Any {
         generate_memo()
     }
     fn wait_for_block(&self, options: &Options, num_of_blocks: i64) -> Result<(), Error>;
+    /// The network the node says it is on, asked over its api
+    fn get_network(&self, options: &Options) -> Result<String, Error>;
 }
 
 pub trait L1Node: Any {
@@ -138,6 +157,7 @@ pub trait L1Node: Any {
     fn get_rpc_username(&self) -> String;
     fn get_rpc_password(&self) -> String;
     fn get_rpc_port(&self) -> String;
+    fn get_rpc_client(&self) -> Option<&BitcoindRpc>;
     fn get_p2p_port(&self) -> String;
     fn create_address(&self, options: &Options) -> Result<String, Error>;
     fn mine_to_address(
@@ -162,6 +182,14 @@ pub struct ImageInfo {
     name: String,
     is_custom: bool,
     node_kind: NodeKind,
+    build: Option<ImageBuild>,
+}
+
+/// Local source for an image, built by docker compose instead of being pulled
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct ImageBuild {
+    pub context: String,
+    pub dockerfile: Option<String>,
 }
 
 impl ImageInfo {
@@ -171,6 +199,16 @@ impl ImageInfo {
             name,
             is_custom,
             node_kind,
+            build: None,
+        }
+    }
+    pub fn new_build(name: String, build: ImageBuild, node_kind: NodeKind) -> ImageInfo {
+        ImageInfo {
+            tag: format!("doppler/{}", name.to_lowercase()),
+            name,
+            is_custom: true,
+            node_kind,
+            build: Some(build),
         }
     }
     pub fn get_image(&self) -> String {
@@ -203,6 +241,21 @@ impl ImageInfo {
     pub fn get_tag(&self) -> String {
         self.tag.clone()
     }
+    pub fn get_node_kind(&self) -> NodeKind {
+        self.node_kind.clone()
+    }
+    pub fn is_build(&self) -> bool {
+        self.build.is_some()
+    }
+    pub fn get_build_step(&self) -> Option<BuildStep> {
+        self.build.as_ref().map(|build| {
+            BuildStep::Advanced(AdvancedBuildStep {
+                context: build.context.clone(),
+                dockerfile: build.dockerfile.clone(),
+                ..Default::default()
+            })
+        })
+    }
 }
 
 #[derive(Default, Debug, Clone)]
@@ -216,21 +269,94 @@ pub struct NodeCommand {
     pub tag: Option<String>,
     // timeout in seconds
     pub timeout: Option<u64>,
+    // sat/vB, set with FEERATE
+    pub fee_rate: Option<u64>,
+    // tagged transactions MINE_BLOCKS leaves out or is limited to
+    pub mine_filter: Option<MineFilter>,
+    // L2 node that gets the coinbase of MINE_BLOCKS ... TO
+    pub coinbase_to: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MineFilter {
+    Exclude(Vec<String>),
+    Only(Vec<String>),
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct MinerTime {
     pub miner_interval_amt: u64,
     pub miner_interval_type: char,
+    // the interval is the mean of exponentially distributed waits, like real block production
+    pub poisson: bool,
 }
 
 impl MinerTime {
-    pub fn new(amt: u64, time_type: char) -> MinerTime {
+    pub fn new(amt: u64, time_type: char, poisson: bool) -> MinerTime {
         MinerTime {
             miner_interval_amt: amt,
             miner_interval_type: time_type,
+            poisson,
+        }
+    }
+
+    pub fn get_interval(&self) -> Duration {
+        match self.miner_interval_type {
+            'm' => Duration::from_secs(self.miner_interval_amt * 60),
+            'h' => Duration::from_secs(self.miner_interval_amt * 60 * 60),
+            _ => Duration::from_secs(self.miner_interval_amt),
+        }
+    }
+
+    /// Time until the next block, drawn from an exponential distribution when poisson
+    pub fn next_wait(&self) -> Duration {
+        let interval = self.get_interval();
+        if !self.poisson {
+            return interval;
+        }
+        // 1 - gen() is in (0, 1], keeps ln away from infinity
+        let uniform: f64 = 1.0 - rand::thread_rng().gen::<f64>();
+        interval.mul_f64(-uniform.ln())
+    }
+}
+
+// about a minute of a node not answering before a WAIT gives up
+const WAIT_MAX_FAILURES: u32 = 30;
+
+/// Polls the height every 2 seconds until it reaches the target. Gives up when doppler
+/// is stopping or the node keeps failing, a node that answers can take as long as it needs
+pub fn wait_for_height(
+    options: &Options,
+    target: i64,
+    get_height: impl Fn() -> Result<i64, Error>,
+) -> Result<(), Error> {
+    let mut failures = 0;
+    while options.main_thread_active.val() {
+        match get_height() {
+            Ok(height) if height >= target => return Ok(()),
+            Ok(height) => {
+                failures = 0;
+                debug!("at block {}, waiting for block {}", height, target)
+            }
+            Err(e) => {
+                failures += 1;
+                if failures >= WAIT_MAX_FAILURES {
+                    return Err(anyhow!(
+                        "gave up waiting for block {} after {} failed tries: {}",
+                        target,
+                        failures,
+                        e
+                    ));
+                }
+                debug!("still waiting for block {}: {}", target, e)
+            }
         }
+        thread::sleep(Duration::from_secs(2));
     }
+    Err(anyhow!(
+        "stopped waiting for block {}, doppler is shutting down",
+        target
+    ))
 }
 
 pub fn generate_memo() -> String {
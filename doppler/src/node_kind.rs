@@ -88,3 +88,17 @@ impl<'a> TryFrom<Pair<'a, Rule>> for LnNodeKind {
         }
     }
 }
+
+// used for the TYPE key in the external nodes file
+impl TryFrom<&str> for LnNodeKind {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value.trim().to_lowercase().as_str() {
+            "lnd" => Ok(LnNodeKind::Lnd),
+            "coreln" | "cln" => Ok(LnNodeKind::Coreln),
+            "eclair" => Ok(LnNodeKind::Eclair),
+            _ => bail!("unknown ln node type: {}", value),
+        }
+    }
+}
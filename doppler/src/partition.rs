@@ -0,0 +1,237 @@
+use crate::{run_engine_command, L1Node, NodeCommand, Options};
+use anyhow::{anyhow, Error, Result};
+use log::info;
+use std::str::from_utf8;
+
+// runs iptables and tc in the network namespace of a node, the node images don't ship them
+pub const NET_TOOLS_IMAGE: &str = "nicolaka/netshoot:v0.13";
+// separate chains for both directions, `-i` can't be used on rules reached from OUTPUT
+const SETUP_CHAINS: &str =
+    "iptables -N DOPPLER_IN 2>/dev/null; iptables -N DOPPLER_OUT 2>/dev/null; \
+    iptables -C INPUT -j DOPPLER_IN 2>/dev/null || iptables -I INPUT -j DOPPLER_IN; \
+    iptables -C OUTPUT -j DOPPLER_OUT 2>/dev/null || iptables -I OUTPUT -j DOPPLER_OUT";
+
+/// A PARTITION between two containers, the rules live in the first one's network namespace
+#[derive(Debug, Clone)]
+pub struct Partition {
+    pub container: String,
+    pub blocked_container: String,
+    pub blocked_ip: String,
+}
+
+/// PARTITION lnd1 FROM lnd2: drops all traffic between the two containers, both keep running
+pub fn partition_nodes(options: &Options, node_command: &NodeCommand) -> Result<(), Error> {
+    let container = get_local_container_name(options, &node_command.from)?;
+    let blocked_container = get_local_container_name(options, &node_command.to)?;
+    if container == blocked_container {
+        return Err(anyhow!("can't partition {} from itself", node_command.from));
+    }
+    let blocked_ip = get_container_ip(options, &blocked_container)?;
+    run_in_network_namespace(
+        options,
+        &container,
+        &format!(
+            "{}; iptables -A DOPPLER_IN -s {ip} -j DROP && iptables -A DOPPLER_OUT -d {ip} -j DROP",
+            SETUP_CHAINS,
+            ip = blocked_ip
+        ),
+    )?;
+    info!(
+        "partitioned {} from {} ({})",
+        node_command.from, node_command.to, blocked_ip
+    );
+    options.partitions.lock().unwrap().push(Partition {
+        container,
+        blocked_container,
+        blocked_ip,
+    });
+    Ok(())
+}
+
+/// ISOLATE bd2: drops all of the container's traffic except loopback
+pub fn isolate_node(options: &Options, node_command: &NodeCommand) -> Result<(), Error> {
+    let container = get_local_container_name(options, &node_command.from)?;
+    run_in_network_namespace(
+        options,
+        &container,
+        &format!(
+            "{}; iptables -A DOPPLER_IN ! -i lo -j DROP && iptables -A DOPPLER_OUT ! -o lo -j DROP",
+            SETUP_CHAINS
+        ),
+    )?;
+    info!("isolated {} from the network", node_command.from);
+    Ok(())
+}
+
+/// HEAL lnd1: removes the isolation of the node and every partition it's part of
+pub fn heal_node(options: &Options, node_command: &NodeCommand) -> Result<(), Error> {
+    let container = get_local_container_name(options, &node_command.from)?;
+    run_in_network_namespace(
+        options,
+        &container,
+        "iptables -F DOPPLER_IN 2>/dev/null; iptables -F DOPPLER_OUT 2>/dev/null; true",
+    )?;
+    let mut partitions = options.partitions.lock().unwrap();
+    for partition in partitions
+        .iter()
+        .filter(|partition| partition.blocked_container == container)
+    {
+        // the rules against this node live on the other side of the partition
+        run_in_network_namespace(
+            options,
+            &partition.container,
+            &format!(
+                "iptables -D DOPPLER_IN -s {ip} -j DROP; iptables -D DOPPLER_OUT -d {ip} -j DROP; true",
+                ip = partition.blocked_ip
+            ),
+        )?;
+    }
+    partitions.retain(|partition| {
+        partition.container != container && partition.blocked_container != container
+    });
+    info!("healed {}", node_command.from);
+    Ok(())
+}
+
+/// Container of a local L2 node or bitcoind, external nodes have no container to fault
+pub fn get_local_container_name(options: &Options, name: &str) -> Result<String, Error> {
+    if options.is_external_node(name) {
+        return Err(anyhow!(
+            "{} is an external node, network faults only work on local containers",
+            name
+        ));
+    }
+    if let Ok(node) = options.get_l2_by_name(name) {
+        return Ok(node.get_container_name().to_owned());
+    }
+    options
+        .bitcoinds
+        .iter()
+        .find(|bitcoind| bitcoind.get_name() == name && !bitcoind.external)
+        .map(|bitcoind| bitcoind.get_container_name())
+        .ok_or(anyhow!("no local node named {}", name))
+}
+
+fn get_container_ip(options: &Options, container: &str) -> Result<String, Error> {
+    let output = run_engine_command(
+        options,
+        "inspect",
+        vec![
+            "inspect",
+            "-f",
+            "{{range .NetworkSettings.Networks}}{{.IPAddress}} {{end}}",
+            container,
+        ],
+    )?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "failed to inspect {}: {}",
+            container,
+            from_utf8(&output.stderr)?.trim()
+        ));
+    }
+    from_utf8(&output.stdout)?
+        .split_whitespace()
+        .next()
+        .map(str::to_owned)
+        .ok_or(anyhow!("{} has no ip address", container))
+}
+
+/// Runs a shell script from the net tools image inside the network namespace of the container
+pub fn run_in_network_namespace(
+    options: &Options,
+    container: &str,
+    script: &str,
+) -> Result<(), Error> {
+    let network = format!("container:{}", container);
+    let output = run_engine_command(
+        options,
+        "net tools",
+        vec![
+            "run",
+            "--rm",
+            "--network",
+            &network,
+            "--cap-add",
+            "NET_ADMIN",
+            NET_TOOLS_IMAGE,
+            "sh",
+            "-c",
+            script,
+        ],
+    )?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "net tools failed in {}: {}",
+            container,
+            from_utf8(&output.stderr)?.trim()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Bitcoind, ExternalBitcoind, Lnd, Network};
+
+    fn cluster() -> Options {
+        let mut options = Options::for_tests(Network::Regtest);
+        options.lnd_nodes = vec![Lnd {
+            name: "lnd1".to_owned(),
+            container_name: "doppler-lnd-lnd1".to_owned(),
+            ..Default::default()
+        }];
+        options.bitcoinds = vec![
+            Bitcoind {
+                name: "bd1".to_owned(),
+                container_name: "doppler-bitcoind-bd1".to_owned(),
+                ..Default::default()
+            },
+            Bitcoind {
+                name: "bd9".to_owned(),
+                external: true,
+                ..Default::default()
+            },
+        ];
+        options.external_bitcoinds = vec![ExternalBitcoind {
+            name: "bd9".to_owned(),
+            rpc_url: String::new(),
+            user: String::new(),
+            password: String::new(),
+            wallet: None,
+            network: "regtest".to_owned(),
+        }];
+        options
+    }
+
+    #[test]
+    fn finds_the_containers_of_local_nodes() {
+        let options = cluster();
+        assert_eq!(
+            get_local_container_name(&options, "lnd1").unwrap(),
+            "doppler-lnd-lnd1"
+        );
+        assert_eq!(
+            get_local_container_name(&options, "bd1").unwrap(),
+            "doppler-bitcoind-bd1"
+        );
+        let error = get_local_container_name(&options, "bd9").unwrap_err();
+        assert!(error.to_string().contains("bd9 is an external node"));
+        assert!(get_local_container_name(&options, "lnd9").is_err());
+    }
+
+    #[test]
+    fn refuses_to_partition_a_node_from_itself() {
+        let options = cluster();
+        let command = NodeCommand {
+            name: "PARTITION".to_owned(),
+            from: "lnd1".to_owned(),
+            to: "lnd1".to_owned(),
+            ..Default::default()
+        };
+        let error = partition_nodes(&options, &command).unwrap_err();
+        assert_eq!(error.to_string(), "can't partition lnd1 from itself");
+        assert!(options.partitions.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,342 @@
+use anyhow::{anyhow, Error};
+use log::{debug, info};
+use rusqlite::{params, Connection};
+use std::{
+    net::TcpListener,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortRange {
+    pub start: i64,
+    pub end: i64,
+}
+
+impl Default for PortRange {
+    fn default() -> Self {
+        PortRange {
+            start: 9090,
+            end: 9999,
+        }
+    }
+}
+
+impl FromStr for PortRange {
+    type Err = String;
+
+    // expects the format `<start>-<end>`, ie. `9090-9999`
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (start, end) = value.split_once('-').ok_or(format!(
+            "invalid port range {}, expected <start>-<end>",
+            value
+        ))?;
+        let start = start
+            .trim()
+            .parse::<u16>()
+            .map_err(|e| format!("invalid start port {}: {}", start, e))?;
+        let end = end
+            .trim()
+            .parse::<u16>()
+            .map_err(|e| format!("invalid end port {}: {}", end, e))?;
+        if start == 0 || start > end {
+            return Err(format!("invalid port range {}", value));
+        }
+        Ok(PortRange {
+            start: start as i64,
+            end: end as i64,
+        })
+    }
+}
+
+impl std::fmt::Display for PortRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+#[derive(Default, Debug, Clone)]
+pub struct PortAssignment {
+    pub service: String,
+    pub container_port: String,
+    pub host_port: i64,
+}
+
+/// Hands out host ports for the generated compose file, skipping any port already bound on the host.
+/// Every assignment is saved to doppler.db so a SKIP_CONF run can recover the same mapping.
+#[derive(Clone)]
+pub struct PortAllocator {
+    range: PortRange,
+    // assignments handed out or loaded during this run
+    assigned: Vec<PortAssignment>,
+    // assignments saved by a previous run, reused when the port is still free
+    previous: Vec<PortAssignment>,
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl PortAllocator {
+    pub fn new(range: PortRange, connection: Arc<Mutex<Connection>>) -> Self {
+        let previous = get_port_assignments(connection.clone()).unwrap_or_default();
+        PortAllocator {
+            range,
+            assigned: vec![],
+            previous,
+            connection,
+        }
+    }
+
+    pub fn allocate(&mut self, service: &str, container_port: &str) -> Result<i64, Error> {
+        if let Some(assignment) = self.get_assignment(service, container_port) {
+            return Ok(assignment.host_port);
+        }
+        let previous_port = self
+            .previous
+            .iter()
+            .find(|assignment| {
+                assignment.service == service && assignment.container_port == container_port
+            })
+            .map(|assignment| assignment.host_port);
+        let host_port = match previous_port {
+            Some(port) if self.is_available(port) => {
+                debug!("reusing port {} for {}:{}", port, service, container_port);
+                port
+            }
+            _ => self.find_free_port()?,
+        };
+        let assignment = PortAssignment {
+            service: service.to_owned(),
+            container_port: container_port.to_owned(),
+            host_port,
+        };
+        self.save(&assignment)?;
+        self.assigned.push(assignment);
+        Ok(host_port)
+    }
+
+    /// Loads the saved mapping as-is, the ports are expected to be held by the running cluster
+    pub fn load_assignments(&mut self) -> Result<(), Error> {
+        self.assigned = get_port_assignments(self.connection.clone())?;
+        info!("loaded {} port assignments", self.assigned.len());
+        Ok(())
+    }
+
+    /// Drops the saved ports of services that are no longer part of the cluster
+    pub fn prune(&mut self, services: &[&str]) -> Result<(), Error> {
+        let stale: Vec<PortAssignment> = get_port_assignments(self.connection.clone())?
+            .into_iter()
+            .filter(|assignment| !services.contains(&assignment.service.as_str()))
+            .collect();
+        let connection = self.connection.lock().unwrap();
+        for assignment in stale.iter() {
+            debug!(
+                "dropping port {} of removed service {}",
+                assignment.host_port, assignment.service
+            );
+            connection.execute(
+                "DELETE FROM ports WHERE service = ?1",
+                params![assignment.service],
+            )?;
+        }
+        self.previous
+            .retain(|assignment| services.contains(&assignment.service.as_str()));
+        self.assigned
+            .retain(|assignment| services.contains(&assignment.service.as_str()));
+        Ok(())
+    }
+
+    pub fn get_assignment(&self, service: &str, container_port: &str) -> Option<PortAssignment> {
+        self.assigned
+            .iter()
+            .find(|assignment| {
+                assignment.service == service && assignment.container_port == container_port
+            })
+            .cloned()
+    }
+
+    fn find_free_port(&self) -> Result<i64, Error> {
+        (self.range.start..=self.range.end)
+            .find(|port| self.is_available(*port))
+            .ok_or(anyhow!(
+                "no free host ports left in range {}, set a wider range with --port-range",
+                self.range
+            ))
+    }
+
+    fn is_available(&self, port: i64) -> bool {
+        if port < self.range.start || port > self.range.end {
+            return false;
+        }
+        if self
+            .assigned
+            .iter()
+            .any(|assignment| assignment.host_port == port)
+        {
+            return false;
+        }
+        TcpListener::bind(("0.0.0.0", port as u16)).is_ok()
+    }
+
+    fn save(&self, assignment: &PortAssignment) -> Result<(), Error> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            "INSERT INTO ports (service, container_port, host_port) VALUES (?1, ?2, ?3)
+             ON CONFLICT(service, container_port) DO UPDATE SET host_port = excluded.host_port",
+            params![
+                assignment.service,
+                assignment.container_port,
+                assignment.host_port
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+fn get_port_assignments(protected: Arc<Mutex<Connection>>) -> Result<Vec<PortAssignment>, Error> {
+    let connection = protected.lock().unwrap();
+    let mut stmt = connection.prepare("SELECT service, container_port, host_port FROM ports")?;
+
+    let mut rows = stmt.query(())?;
+    let mut assignments: Vec<PortAssignment> = Vec::new();
+
+    while let Some(row) = rows.next()? {
+        assignments.push(PortAssignment {
+            service: row.get(0)?,
+            container_port: row.get(1)?,
+            host_port: row.get(2)?,
+        });
+    }
+    Ok(assignments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::create_db;
+
+    fn test_connection() -> Arc<Mutex<Connection>> {
+        Arc::new(Mutex::new(create_db(":memory:".to_owned()).unwrap()))
+    }
+
+    // a port the OS just handed out, free once the listener is dropped
+    fn free_port() -> i64 {
+        let listener = TcpListener::bind(("0.0.0.0", 0)).unwrap();
+        listener.local_addr().unwrap().port() as i64
+    }
+
+    fn single_port_range(port: i64) -> PortRange {
+        PortRange {
+            start: port,
+            end: port,
+        }
+    }
+
+    #[test]
+    fn allocates_and_saves_ports() {
+        let connection = test_connection();
+        let port = free_port();
+        let mut allocator = PortAllocator::new(single_port_range(port), connection.clone());
+
+        assert_eq!(allocator.allocate("bd1", "18443").unwrap(), port);
+        // the same service and container port keep their host port within a run
+        assert_eq!(allocator.allocate("bd1", "18443").unwrap(), port);
+        let saved = get_port_assignments(connection).unwrap();
+        assert_eq!(saved.len(), 1);
+        assert_eq!(saved[0].service, "bd1");
+        assert_eq!(saved[0].host_port, port);
+    }
+
+    #[test]
+    fn reuses_ports_from_a_previous_run() {
+        let connection = test_connection();
+        let port = free_port();
+        let range = PortRange {
+            start: port.saturating_sub(50).max(1),
+            end: port,
+        };
+        PortAllocator::new(range, connection.clone())
+            .save(&PortAssignment {
+                service: "lnd1".to_owned(),
+                container_port: "8080".to_owned(),
+                host_port: port,
+            })
+            .unwrap();
+
+        let mut allocator = PortAllocator::new(range, connection);
+        assert_eq!(allocator.allocate("lnd1", "8080").unwrap(), port);
+    }
+
+    #[test]
+    fn skips_ports_that_are_taken() {
+        let connection = test_connection();
+        let listener = TcpListener::bind(("0.0.0.0", 0)).unwrap();
+        let taken = listener.local_addr().unwrap().port() as i64;
+        let mut allocator = PortAllocator::new(single_port_range(taken), connection.clone());
+        assert!(allocator.allocate("lnd1", "8080").is_err());
+
+        // two services can't share a host port even when the host has it free
+        let port = free_port();
+        let mut allocator = PortAllocator::new(single_port_range(port), connection);
+        assert_eq!(allocator.allocate("lnd1", "8080").unwrap(), port);
+        assert!(allocator.allocate("lnd2", "8080").is_err());
+    }
+
+    #[test]
+    fn prunes_removed_services() {
+        let connection = test_connection();
+        let allocator = PortAllocator::new(PortRange::default(), connection.clone());
+        for (service, host_port) in [("bd1", 9090), ("lnd1", 9091), ("lnd2", 9092)] {
+            allocator
+                .save(&PortAssignment {
+                    service: service.to_owned(),
+                    container_port: "8080".to_owned(),
+                    host_port,
+                })
+                .unwrap();
+        }
+
+        let mut allocator = PortAllocator::new(PortRange::default(), connection.clone());
+        allocator.prune(&["bd1", "lnd2"]).unwrap();
+        let services: Vec<String> = get_port_assignments(connection)
+            .unwrap()
+            .into_iter()
+            .map(|assignment| assignment.service)
+            .collect();
+        assert_eq!(services, vec!["bd1", "lnd2"]);
+        assert!(allocator
+            .previous
+            .iter()
+            .all(|assignment| assignment.service != "lnd1"));
+    }
+
+    #[test]
+    fn parses_port_ranges() {
+        assert_eq!(
+            PortRange::from_str("9090-9999"),
+            Ok(PortRange {
+                start: 9090,
+                end: 9999
+            })
+        );
+        assert_eq!(
+            PortRange::from_str(" 10000 - 10000 "),
+            Ok(PortRange {
+                start: 10000,
+                end: 10000
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_port_ranges() {
+        for range in [
+            "9090",
+            "a-9999",
+            "9090-",
+            "0-100",
+            "9999-9090",
+            "9090-70000",
+        ] {
+            assert!(PortRange::from_str(range).is_err(), "{} accepted", range);
+        }
+    }
+}
@@ -0,0 +1,282 @@
+use crate::{Bitcoind, L1Node, NodeCommand, Options};
+use anyhow::{anyhow, Error, Result};
+use log::{debug, info};
+use serde_json::{json, Value};
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+// a reconnected node needs to download and validate the other side's blocks
+const SYNC_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// REORG bd1 DEPTH n: replaces the last n blocks of bd1's chain with n+1 new ones, its peers follow the longer chain
+pub fn reorg_chain(options: &Options, node_command: &NodeCommand) -> Result<(), Error> {
+    check_can_reorg(options)?;
+    let node = options.get_bitcoind_by_name(&node_command.from)?;
+    let depth = get_depth(node_command)?;
+    let height = get_block_count(options, node)?;
+    if depth > height {
+        return Err(anyhow!(
+            "can't reorg {} blocks, {} is at height {}",
+            depth,
+            node.get_name(),
+            height
+        ));
+    }
+    let fork_point = node
+        .call(options, "getblockhash", &[json!(height - depth + 1)])?
+        .as_str()
+        .ok_or(anyhow!("no block hash at height {}", height - depth + 1))?
+        .to_owned();
+    info!(
+        "({}): reorging the last {} block(s), starting at {}",
+        node.get_name(),
+        depth,
+        fork_point
+    );
+    node.call(options, "invalidateblock", &[json!(fork_point)])?;
+    // the transactions of the replaced blocks go back to the mempool and get mined again
+    let mined = node.mine_bitcoin(options, depth + 1);
+    // the old branch has less work now, reconsidering it only makes it a known stale branch
+    node.call(options, "reconsiderblock", &[json!(fork_point)])?;
+    mined?;
+    info!(
+        "({}): reorg done, new tip {} at height {}",
+        node.get_name(),
+        get_best_block_hash(options, node)?,
+        get_block_count(options, node)?
+    );
+    Ok(())
+}
+
+/// FORK bd1 bd2 DEPTH n: cuts bd1 off the network, mines n blocks on it and n+1 on bd2, then reconnects them
+/// bd1 and the nodes following it reorg to bd2's side once they see it
+pub fn fork_chain(options: &Options, node_command: &NodeCommand) -> Result<(), Error> {
+    check_can_reorg(options)?;
+    let losing = options.get_bitcoind_by_name(&node_command.from)?;
+    let winning = options.get_bitcoind_by_name(&node_command.to)?;
+    if losing.get_name() == winning.get_name() {
+        return Err(anyhow!("FORK needs two different bitcoind nodes"));
+    }
+    let depth = get_depth(node_command)?;
+    info!(
+        "forking {} ({} block(s)) from {} ({} block(s))",
+        losing.get_name(),
+        depth,
+        winning.get_name(),
+        depth + 1
+    );
+    losing.call(options, "setnetworkactive", &[json!(false)])?;
+    let mined = losing
+        .mine_bitcoin(options, depth)
+        .and_then(|_| winning.mine_bitcoin(options, depth + 1));
+    // always reconnect, a failed fork shouldn't leave the node cut off
+    losing.call(options, "setnetworkactive", &[json!(true)])?;
+    mined?;
+    let winning_peer = format!(
+        "{}:{}",
+        winning.get_container_name(),
+        winning.get_p2p_port()
+    );
+    losing.call(options, "addnode", &[json!(winning_peer), json!("onetry")])?;
+    wait_for_same_tip(options, losing, winning)?;
+    info!(
+        "fork resolved, {} reorged to {}'s chain",
+        losing.get_name(),
+        winning.get_name()
+    );
+    Ok(())
+}
+
+fn check_can_reorg(options: &Options) -> Result<(), Error> {
+    if !options.can_mine() {
+        return Err(anyhow!(
+            "reorgs need doppler to mine the blocks, not possible on {}",
+            options.network
+        ));
+    }
+    Ok(())
+}
+
+fn get_depth(node_command: &NodeCommand) -> Result<i64, Error> {
+    let depth = node_command.amt.unwrap_or(1);
+    if depth < 1 {
+        return Err(anyhow!("DEPTH needs to be at least 1"));
+    }
+    Ok(depth)
+}
+
+fn get_block_count(options: &Options, node: &Bitcoind) -> Result<i64, Error> {
+    node.call(options, "getblockcount", &[])?
+        .as_i64()
+        .ok_or(anyhow!("invalid block count from {}", node.get_name()))
+}
+
+fn get_best_block_hash(options: &Options, node: &Bitcoind) -> Result<String, Error> {
+    match node.call(options, "getbestblockhash", &[])? {
+        Value::String(hash) => Ok(hash),
+        other => Err(anyhow!("invalid best block hash: {}", other)),
+    }
+}
+
+fn wait_for_same_tip(options: &Options, first: &Bitcoind, second: &Bitcoind) -> Result<(), Error> {
+    let started = Instant::now();
+    loop {
+        let first_tip = get_best_block_hash(options, first)?;
+        let second_tip = get_best_block_hash(options, second)?;
+        if first_tip == second_tip {
+            return Ok(());
+        }
+        if started.elapsed() > SYNC_TIMEOUT {
+            return Err(anyhow!(
+                "{} and {} didn't converge on a tip after {}s",
+                first.get_name(),
+                second.get_name(),
+                SYNC_TIMEOUT.as_secs()
+            ));
+        }
+        debug!(
+            "waiting for {} to sync with {}",
+            first.get_name(),
+            second.get_name()
+        );
+        thread::sleep(Duration::from_secs(1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ContainerBackend, Network};
+    use std::{
+        os::unix::process::ExitStatusExt,
+        process::{ExitStatus, Output},
+        sync::{Arc, Mutex},
+    };
+
+    // answers bitcoin-cli like a node at height 10 and records each rpc with its arguments
+    #[derive(Default)]
+    struct ChainBackend {
+        calls: Mutex<Vec<String>>,
+    }
+
+    impl ContainerBackend for ChainBackend {
+        fn exec(
+            &self,
+            _options: &Options,
+            _command_name: &str,
+            _container: &str,
+            _user: Option<&str>,
+            cmd: &[&str],
+        ) -> Result<Output, Error> {
+            let rpc: Vec<&str> = cmd
+                .iter()
+                .skip(1)
+                .filter(|arg| !arg.starts_with('-'))
+                .copied()
+                .collect();
+            self.calls.lock().unwrap().push(rpc.join(" "));
+            let stdout = match rpc[0] {
+                "getblockcount" => "10",
+                "getblockhash" => "00ab",
+                "getbestblockhash" => "00cd",
+                "getnewaddress" => "bcrt1qtest\n",
+                _ => "",
+            };
+            Ok(Output {
+                status: ExitStatus::from_raw(0),
+                stdout: stdout.as_bytes().to_vec(),
+                stderr: vec![],
+            })
+        }
+        fn stop(&self, _options: &Options, _container: &str) -> Result<Output, Error> {
+            unreachable!()
+        }
+        fn start(&self, _options: &Options, _container: &str) -> Result<Output, Error> {
+            unreachable!()
+        }
+        fn kill(&self, _options: &Options, _container: &str) -> Result<Output, Error> {
+            unreachable!()
+        }
+    }
+
+    fn chain_options(network: Network) -> (Options, Arc<ChainBackend>) {
+        let mut options = Options::for_tests(network);
+        let backend = Arc::new(ChainBackend::default());
+        options.backend = backend.clone();
+        options.bitcoinds = ["bd1", "bd2"]
+            .iter()
+            .map(|name| Bitcoind {
+                name: name.to_string(),
+                container_name: format!("doppler-bitcoind-{}", name),
+                p2pport: "18444".to_owned(),
+                ..Default::default()
+            })
+            .collect();
+        (options, backend)
+    }
+
+    fn command(name: &str, from: &str, to: &str, depth: Option<i64>) -> NodeCommand {
+        NodeCommand {
+            name: name.to_owned(),
+            from: from.to_owned(),
+            to: to.to_owned(),
+            amt: depth,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn reorgs_by_invalidating_the_fork_point() {
+        let (options, backend) = chain_options(Network::Regtest);
+        reorg_chain(&options, &command("REORG", "bd1", "", Some(3))).unwrap();
+        assert_eq!(
+            *backend.calls.lock().unwrap(),
+            vec![
+                "getblockcount",
+                "getblockhash 8",
+                "invalidateblock 00ab",
+                "getnewaddress",
+                "generatetoaddress 4 bcrt1qtest",
+                "reconsiderblock 00ab",
+            ]
+        );
+    }
+
+    #[test]
+    fn forks_while_the_losing_node_is_offline() {
+        let (options, backend) = chain_options(Network::Regtest);
+        fork_chain(&options, &command("FORK", "bd1", "bd2", Some(2))).unwrap();
+        assert_eq!(
+            *backend.calls.lock().unwrap(),
+            vec![
+                "setnetworkactive false",
+                "getnewaddress",
+                "generatetoaddress 2 bcrt1qtest",
+                "getnewaddress",
+                "generatetoaddress 3 bcrt1qtest",
+                "setnetworkactive true",
+                "addnode doppler-bitcoind-bd2:18444 onetry",
+                "getbestblockhash",
+                "getbestblockhash",
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_reorgs() {
+        let (options, backend) = chain_options(Network::Regtest);
+        assert!(reorg_chain(&options, &command("REORG", "bd1", "", Some(0))).is_err());
+        assert!(reorg_chain(&options, &command("REORG", "bd1", "", Some(11))).is_err());
+        assert!(reorg_chain(&options, &command("REORG", "bd9", "", None)).is_err());
+        assert!(fork_chain(&options, &command("FORK", "bd1", "bd1", None)).is_err());
+        // nothing past the height check is sent to the node
+        assert_eq!(*backend.calls.lock().unwrap(), vec!["getblockcount"]);
+
+        let (options, backend) = chain_options(Network::Signet);
+        assert!(reorg_chain(&options, &command("REORG", "bd1", "", None)).is_err());
+        assert!(fork_chain(&options, &command("FORK", "bd1", "bd2", None)).is_err());
+        assert!(backend.calls.lock().unwrap().is_empty());
+    }
+}
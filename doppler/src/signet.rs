@@ -0,0 +1,295 @@
+use crate::{create_folder, get_absolute_path, BitcoindRpc, BlockTemplate, L1Node, Options};
+use anyhow::{anyhow, Error, Result};
+use bitcoin::{
+    absolute::LockTime,
+    block::{Header, Version},
+    consensus::encode::{deserialize_hex, serialize, serialize_hex},
+    hashes::Hash,
+    opcodes::{
+        all::{OP_CHECKMULTISIG, OP_PUSHNUM_1, OP_RETURN},
+        OP_0,
+    },
+    script::{Builder, PushBytesBuf},
+    secp256k1::{rand, Message, Secp256k1, SecretKey},
+    sighash::{EcdsaSighashType, SighashCache},
+    transaction, Amount, Block, BlockHash, CompactTarget, OutPoint, PublicKey, ScriptBuf, Sequence,
+    Transaction, TxIn, TxMerkleNode, TxOut, Txid, Witness,
+};
+use log::{debug, error, info};
+use serde_json::{json, Value};
+use std::{
+    fs,
+    path::Path,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc,
+    },
+    thread::{self, spawn},
+    time::Duration,
+};
+
+const SIGNER_KEY_DIR: &str = "data/custom_signet";
+// BIP325 marks the signet solution in the coinbase's witness commitment output with these bytes
+const SIGNET_HEADER: [u8; 4] = [0xec, 0xc7, 0xda, 0xa2];
+
+/// A signet only doppler's signer can produce blocks for, the challenge is a 1-of-1 multisig of its key
+#[derive(Debug, Clone)]
+pub struct CustomSignet {
+    secret_key: SecretKey,
+    challenge: ScriptBuf,
+    // seconds between the blocks mined on the schedule
+    pub block_interval: u64,
+}
+
+impl CustomSignet {
+    /// Reuses the key of the current cluster, a new one is generated when the data folder has been reset
+    pub fn load_or_create(block_interval: u64) -> Result<Self, Error> {
+        let key_path = format!("{}/signer.key", SIGNER_KEY_DIR);
+        let secret_key = if Path::new(&key_path).exists() {
+            SecretKey::from_str(fs::read_to_string(&key_path)?.trim())?
+        } else {
+            create_folder(SIGNER_KEY_DIR)?;
+            let secret_key = SecretKey::new(&mut rand::thread_rng());
+            fs::write(&key_path, secret_key.display_secret().to_string())?;
+            info!(
+                "generated custom signet key @ {}",
+                get_absolute_path(&key_path)?.display()
+            );
+            secret_key
+        };
+        let public_key = PublicKey::new(secret_key.public_key(&Secp256k1::new()));
+        let challenge = Builder::new()
+            .push_opcode(OP_PUSHNUM_1)
+            .push_key(&public_key)
+            .push_opcode(OP_PUSHNUM_1)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+        Ok(Self {
+            secret_key,
+            challenge,
+            block_interval,
+        })
+    }
+
+    pub fn get_challenge(&self) -> String {
+        self.challenge.to_hex_string()
+    }
+
+    /// Builds a block from the node's template, signs it and grinds the proof of work, returns the block hash
+    pub fn mine_block(&self, rpc: &BitcoindRpc, address: &str) -> Result<String, Error> {
+        let template = rpc.get_block_template(json!({ "rules": ["segwit", "signet"] }))?;
+        let payout_script = ScriptBuf::from_hex(&rpc.get_address_info(address)?.script_pub_key)?;
+        let mut block = self.build_block(&template, payout_script)?;
+        self.sign_block(&mut block)?;
+        grind(&mut block.header)?;
+
+        let block_hash = block.block_hash();
+        let result: Value = rpc.call("submitblock", json!([serialize_hex(&block)]))?;
+        if !result.is_null() {
+            return Err(anyhow!("block {} rejected: {}", block_hash, result));
+        }
+        debug!("mined custom signet block {}", block_hash);
+        Ok(block_hash.to_string())
+    }
+
+    fn build_block(
+        &self,
+        template: &BlockTemplate,
+        payout_script: ScriptBuf,
+    ) -> Result<Block, Error> {
+        let height = template.height;
+        let coinbase = Transaction {
+            version: transaction::Version::ONE,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                // BIP34 height, the extra push keeps the script at the 2 byte minimum
+                script_sig: Builder::new()
+                    .push_int(height as i64)
+                    .push_opcode(OP_0)
+                    .into_script(),
+                sequence: Sequence::MAX,
+                witness: Witness::from_slice(&[[0_u8; 32]]),
+            }],
+            output: vec![
+                TxOut {
+                    value: Amount::from_sat(template.coinbasevalue),
+                    script_pubkey: payout_script,
+                },
+                TxOut {
+                    value: Amount::ZERO,
+                    script_pubkey: ScriptBuf::from_hex(&template.default_witness_commitment)?,
+                },
+            ],
+        };
+        let mut txdata = vec![coinbase];
+        for tx in template.transactions.iter() {
+            txdata.push(deserialize_hex::<Transaction>(&tx.data)?);
+        }
+        let time = template.curtime.max(template.mintime) as u32;
+        let header = Header {
+            version: Version::from_consensus(template.version),
+            prev_blockhash: BlockHash::from_str(&template.previousblockhash)?,
+            merkle_root: TxMerkleNode::all_zeros(),
+            time,
+            bits: CompactTarget::from_consensus(u32::from_str_radix(&template.bits, 16)?),
+            nonce: 0,
+        };
+        Ok(Block { header, txdata })
+    }
+
+    // BIP325: sign a virtual spend of the challenge that commits to the block without its solution
+    fn sign_block(&self, block: &mut Block) -> Result<(), Error> {
+        let witness_commitment = block.txdata[0].output[1].script_pubkey.clone();
+        set_signet_commitment(block, &witness_commitment, SIGNET_HEADER.to_vec())?;
+        let signet_merkle_root = block
+            .compute_merkle_root()
+            .ok_or(anyhow!("block has no transactions"))?;
+
+        let mut block_data = serialize(&block.header.version);
+        block_data.extend(block.header.prev_blockhash.to_byte_array());
+        block_data.extend(signet_merkle_root.to_byte_array());
+        block_data.extend(block.header.time.to_le_bytes());
+        let to_spend = Transaction {
+            version: transaction::Version(0),
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::all_zeros(), u32::MAX),
+                script_sig: Builder::new()
+                    .push_opcode(OP_0)
+                    .push_slice(PushBytesBuf::try_from(block_data)?)
+                    .into_script(),
+                sequence: Sequence::ZERO,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::ZERO,
+                script_pubkey: self.challenge.clone(),
+            }],
+        };
+        let to_sign = Transaction {
+            version: transaction::Version(0),
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(to_spend.compute_txid(), 0),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ZERO,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::ZERO,
+                script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+            }],
+        };
+        let sighash = SighashCache::new(&to_sign).legacy_signature_hash(
+            0,
+            &self.challenge,
+            EcdsaSighashType::All.to_u32(),
+        )?;
+        let signature = Secp256k1::new().sign_ecdsa(
+            &Message::from_digest(sighash.to_byte_array()),
+            &self.secret_key,
+        );
+        let mut signature = signature.serialize_der().to_vec();
+        signature.push(EcdsaSighashType::All as u8);
+        // OP_0 for the extra item OP_CHECKMULTISIG pops
+        let script_sig = Builder::new()
+            .push_opcode(OP_0)
+            .push_slice(PushBytesBuf::try_from(signature)?)
+            .into_script();
+
+        let mut solution = serialize(&script_sig);
+        solution.extend(serialize(&Witness::new()));
+        let mut signet_commitment = SIGNET_HEADER.to_vec();
+        signet_commitment.extend(solution);
+        set_signet_commitment(block, &witness_commitment, signet_commitment)?;
+        block.header.merkle_root = block
+            .compute_merkle_root()
+            .ok_or(anyhow!("block has no transactions"))?;
+        Ok(())
+    }
+}
+
+fn set_signet_commitment(
+    block: &mut Block,
+    witness_commitment: &ScriptBuf,
+    data: Vec<u8>,
+) -> Result<(), Error> {
+    block.txdata[0].output[1].script_pubkey = Builder::from(witness_commitment.to_bytes())
+        .push_slice(PushBytesBuf::try_from(data)?)
+        .into_script();
+    Ok(())
+}
+
+// signet's minimum difficulty takes a few million hashes, spread the nonces over all cores
+fn grind(header: &mut Header) -> Result<(), Error> {
+    let target = header.target();
+    let threads = thread::available_parallelism().map_or(1, |count| count.get()) as u32;
+    let found = Arc::new(AtomicBool::new(false));
+    let nonce = Arc::new(AtomicU32::new(0));
+    thread::scope(|scope| {
+        for start in 0..threads {
+            let mut candidate = *header;
+            let found = found.clone();
+            let nonce = nonce.clone();
+            scope.spawn(move || {
+                let mut current = start;
+                while !found.load(Ordering::Relaxed) {
+                    candidate.nonce = current;
+                    if target.is_met_by(candidate.block_hash()) {
+                        found.store(true, Ordering::Relaxed);
+                        nonce.store(current, Ordering::Relaxed);
+                        return;
+                    }
+                    current = match current.checked_add(threads) {
+                        Some(next) => next,
+                        None => return,
+                    };
+                }
+            });
+        }
+    });
+    if !found.load(Ordering::Relaxed) {
+        return Err(anyhow!("no valid nonce found, try mining the block again"));
+    }
+    header.nonce = nonce.load(Ordering::Relaxed);
+    Ok(())
+}
+
+/// Mines a block every block interval on the cluster's miners, taking turns between them
+pub fn start_signet_miner(options: &Options) -> Result<(), Error> {
+    let Some(signet) = options.custom_signet.clone() else {
+        return Ok(());
+    };
+    let miners: Vec<_> = options
+        .bitcoinds
+        .iter()
+        .filter(|bitcoind| !bitcoind.external && bitcoind.get_container_name().contains("miner"))
+        .cloned()
+        .collect();
+    if miners.is_empty() {
+        return Err(anyhow!("a custom signet needs at least one BITCOIND_MINER"));
+    }
+    info!(
+        "mining a custom signet block every {}s",
+        signet.block_interval
+    );
+    let thread_options = options.clone();
+    spawn(move || {
+        thread_options.add_thread(thread::current());
+        let mut turn = 0;
+        while thread_options.main_thread_active.val() {
+            thread::sleep(Duration::from_secs(signet.block_interval));
+            if thread_options.main_thread_paused.val() {
+                continue;
+            }
+            let miner = &miners[turn % miners.len()];
+            if let Err(e) = miner.mine_bitcoin(&thread_options, 1) {
+                error!("{} failed to mine a block: {}", miner.get_name(), e);
+            }
+            turn += 1;
+        }
+    });
+    Ok(())
+}
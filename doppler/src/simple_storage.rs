@@ -18,6 +18,17 @@ pub fn create_db(db_file: String) -> Result<Connection> {
         (),
     )?;
 
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS ports (
+             id INTEGER PRIMARY KEY,
+             service TEXT NOT NULL,
+             container_port TEXT NOT NULL,
+             host_port INTEGER NOT NULL,
+             UNIQUE(service, container_port)
+         )",
+        (),
+    )?;
+
     Ok(conn)
 }
 
@@ -99,6 +110,10 @@ impl Tags {
         Ok(())
     }
 
+    pub fn get_connection(&self) -> Arc<Mutex<Connection>> {
+        self.connection.clone()
+    }
+
     pub fn get_all(&self) -> Vec<Tag> {
         self.items.clone()
     }
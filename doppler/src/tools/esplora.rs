@@ -1,4 +1,4 @@
-use crate::{create_folder, get_absolute_path, Bitcoind, Options, NETWORK};
+use crate::{create_folder, get_absolute_path, Bitcoind, Network, Options, NETWORK};
 use anyhow::{anyhow, Result};
 use docker_compose_types::{
     DependsCondition, DependsOnOptions, Entrypoint, Environment, Networks, Ports, Service, Volumes,
@@ -26,14 +26,19 @@ pub fn build_esplora(
             "bitcoind nodes need to be defined before esplora nodes can be setup"
         ));
     }
-    let electrum_port = options.new_port();
-    let esplora_web_port = options.new_port();
-
-    let bitcoind: &Bitcoind = match options.get_bitcoind_by_name(target_node) {
-        Ok(bitcoind) => bitcoind,
+    if !matches!(options.network, Network::Regtest | Network::Signet) {
+        return Err(anyhow!(
+            "the esplora image only supports regtest and signet, not {}",
+            options.network
+        ));
+    }
+    let bitcoind: Bitcoind = match options.get_bitcoind_by_name(target_node) {
+        Ok(bitcoind) => bitcoind.clone(),
         Err(err) => return Err(err),
     };
     let esplora_container_name = format!("doppler-{}-{}", name, bitcoind.name);
+    let electrum_port = options.new_port(&esplora_container_name, "50001")?;
+    let esplora_web_port = options.new_port(&esplora_container_name, "80")?;
     let mut conditional = IndexMap::new();
     conditional.insert(
         bitcoind.container_name.to_owned(),
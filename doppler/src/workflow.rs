@@ -1,9 +1,14 @@
 use crate::{
-    build_bitcoind, build_cln, build_eclair, build_esplora, build_lnd, load_options_from_compose,
-    load_options_from_external_nodes, run_cluster, DopplerParser, ImageInfo, L1Node, LnNodeKind,
-    MinerTime, NodeCommand, NodeKind, Options, Rule, SupportedTool, Tag, ToolImageInfo,
+    add_external_nodes_to_cluster, apply_netem, build_bitcoind, build_cln, build_eclair,
+    build_esplora, build_lnd, child_pays_for_parent, create_ui_config_files, fill_mempool,
+    fork_chain, get_absolute_path, heal_node, isolate_node, load_options_from_compose,
+    load_options_from_external_nodes, mine_filtered, mine_to_l2, partition_nodes, reorg_chain,
+    replace_by_fee, run_cluster, start_chaos, start_signet_miner, upgrade_container, Bitcoind,
+    ChaosConfig, ChaosFault, DopplerParser, ImageBuild, ImageInfo, L1Node, L2Node, LnNodeKind,
+    MineFilter, MinerTime, NodeCommand, NodeKind, Options, Rule, ServiceSettings, SpendKind,
+    SupportedTool, Tag, ToolImageInfo, DEFAULT_SEND_COINS_AMT,
 };
-use anyhow::{Error, Result};
+use anyhow::{anyhow, Error, Result};
 use log::{debug, error, info};
 use pest::{
     iterators::{Pair, Pairs},
@@ -22,11 +27,15 @@ use std::{
 use uuid::Uuid;
 
 const COMPOSE_PATH: &str = "doppler-cluster.yaml";
+// checks every 2 seconds, gives a recreated container about a minute to come up
+const UPGRADE_READY_RETRIES: u32 = 30;
 
 pub fn run_workflow(options: &mut Options, parsed: Pair<'_, Rule>) -> Result<(), Error> {
+    options.net_admin = uses_netem(&parsed);
     for pair in parsed.into_inner() {
         match pair.as_rule() {
             Rule::loop_content => handle_loop(options, pair).expect("invalid loop block"),
+            Rule::chaos_content => handle_chaos(options, pair).expect("invalid chaos block"),
             Rule::conf => handle_conf(options, pair).expect("invalid conf line"),
             Rule::up => handle_up(options).expect("failed to start the cluster"),
             Rule::skip_conf => {
@@ -38,6 +47,14 @@ pub fn run_workflow(options: &mut Options, parsed: Pair<'_, Rule>) -> Result<(),
             Rule::btc_node_action => {
                 handle_btc_action(options, pair).expect("invalid node action line")
             }
+            Rule::node_upgrade => handle_upgrade(options, pair).expect("failed to upgrade node"),
+            Rule::chain_action => {
+                handle_chain_action(options, pair).expect("invalid chain action line")
+            }
+            Rule::network_action => {
+                handle_network_action(options, pair).expect("invalid network action line")
+            }
+            Rule::fee_action => handle_fee_action(options, pair).expect("invalid fee action line"),
             Rule::EOI => {
                 options
                     .clone()
@@ -69,6 +86,7 @@ pub fn run_workflow_until_stop(
         && options.loop_count.as_ref().load(Ordering::SeqCst) == 0
     {
         main_thread_active.set(false);
+        options.budget.log_summary();
         return Ok(());
     }
     let terminate = Arc::new(AtomicBool::new(false));
@@ -96,6 +114,7 @@ pub fn run_workflow_until_stop(
     let handles: Vec<_> = handles.drain(..).collect();
     // drop the collected handles to ensure they're joined
     drop(handles);
+    options.budget.log_summary();
     Ok(())
 }
 
@@ -189,6 +208,21 @@ fn handle_loop(options: &mut Options, line: Pair<'_, Rule>) -> Result<()> {
                 let node_command = process_ln_action(inner_pair);
                 command_stack.push(node_command);
             }
+            Rule::chain_action => {
+                debug!("processing chain command");
+                let node_command = process_chain_action(inner_pair);
+                command_stack.push(node_command);
+            }
+            Rule::network_action => {
+                debug!("processing network command");
+                let node_command = process_network_action(inner_pair);
+                command_stack.push(node_command);
+            }
+            Rule::fee_action => {
+                debug!("processing fee command");
+                let node_command = process_fee_action(inner_pair);
+                command_stack.push(node_command);
+            }
             Rule::end => {
                 debug!("processing end command");
                 run_loop(
@@ -204,6 +238,59 @@ fn handle_loop(options: &mut Options, line: Pair<'_, Rule>) -> Result<()> {
     Ok(())
 }
 
+fn handle_chaos(options: &mut Options, line: Pair<'_, Rule>) -> Result<()> {
+    let mut config = ChaosConfig::default();
+    for inner_pair in line.into_inner() {
+        match inner_pair.as_rule() {
+            Rule::chaos_start => {
+                let mut inner = inner_pair.into_inner();
+                config.interval = process_duration(&mut inner)?;
+                for setting in inner {
+                    match setting.as_rule() {
+                        Rule::chaos_duration => {
+                            config.duration = Some(process_duration(&mut setting.into_inner())?)
+                        }
+                        Rule::chaos_seed => {
+                            config.seed = Some(setting.into_inner().as_str().parse()?)
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            Rule::chaos_fault => {
+                let mut inner = inner_pair.into_inner();
+                let fault = ChaosFault::try_from(inner.next().expect("fault").as_str())?;
+                let chance: f64 = inner
+                    .next()
+                    .expect("chance")
+                    .as_str()
+                    .trim_end_matches('%')
+                    .parse()?;
+                config.faults.push((fault, chance));
+            }
+            Rule::chaos_nodes => config
+                .nodes
+                .extend(inner_pair.into_inner().map(|node| node.as_str().to_owned())),
+            Rule::chaos_downtime => {
+                config.downtime = process_duration(&mut inner_pair.into_inner())?
+            }
+            _ => (),
+        }
+    }
+    start_chaos(options, config)
+}
+
+// `num ~ time_digits`, ie. 30s, 5m or 1h
+fn process_duration(inner: &mut Pairs<'_, Rule>) -> Result<Duration> {
+    let amt: u64 = inner.next().expect("time amount").as_str().parse()?;
+    let seconds = match inner.next().expect("time unit").as_str() {
+        "h" => amt * 60 * 60,
+        "m" => amt * 60,
+        _ => amt,
+    };
+    Ok(Duration::from_secs(seconds))
+}
+
 fn run_loop(
     options: &mut Options,
     loop_options: LoopOptions,
@@ -246,23 +333,26 @@ fn run_loop(
                 debug!("running commands for loop: {}", loop_options.name);
 
                 let action = match command.name.as_str() {
-                    "MINE_BLOCKS" => node_mine_bitcoin(
-                        &current_options.clone(),
-                        command.to.to_owned(),
-                        command.amt.unwrap(),
-                    ),
-                    "OPEN_CHANNEL" => open_channel(&current_options.clone(), &command),
-                    "SEND_LN" => send_ln(&current_options.clone(), &command),
-                    "SEND_ON_CHAIN" => send_on_chain(&current_options.clone(), &command),
-                    "CLOSE_CHANNEL" => close_channel(&current_options.clone(), &command),
-                    "FORCE_CLOSE_CHANNEL" => {
-                        force_close_channel(&current_options.clone(), &command)
-                    }
-                    "STOP_LN" => stop_l2_node(&current_options.clone(), &command),
-                    "START_LN" => start_l2_node(&current_options.clone(), &command),
-                    "SEND_HOLD_LN" => send_hold_invoice(&current_options.clone(), &command),
-                    "SETTLE_HOLD_LN" => settle_hold_invoice(&current_options.clone(), &command),
-                    "WAIT" => wait_number_of_blocks(&current_options.clone(), &command),
+                    "MINE_BLOCKS" => node_mine_bitcoin(&current_options.clone(), &command),
+                    "OPEN_CHANNEL"
+                    | "SEND_LN"
+                    | "SEND_ON_CHAIN"
+                    | "CLOSE_CHANNEL"
+                    | "FORCE_CLOSE_CHANNEL"
+                    | "STOP_LN"
+                    | "START_LN"
+                    | "SEND_HOLD_LN"
+                    | "SETTLE_HOLD_LN"
+                    | "WAIT" => run_ln_action(&current_options, &command),
+                    "REORG" => reorg_chain(&current_options.clone(), &command),
+                    "FORK" => fork_chain(&current_options.clone(), &command),
+                    "PARTITION" => partition_nodes(&current_options.clone(), &command),
+                    "ISOLATE" => isolate_node(&current_options.clone(), &command),
+                    "HEAL" => heal_node(&current_options.clone(), &command),
+                    "NETEM" => apply_netem(&current_options.clone(), &command),
+                    "FILL_MEMPOOL" => fill_mempool(&current_options.clone(), &command),
+                    "RBF" => replace_by_fee(&current_options.clone(), &command),
+                    "CPFP" => child_pays_for_parent(&current_options.clone(), &command),
                     _ => unreachable!(),
                 };
                 match action {
@@ -313,12 +403,13 @@ fn handle_conf(options: &mut Options, line: Pair<Rule>) -> Result<()> {
                 .expect("node")
                 .try_into()
                 .expect("invalid node kind");
-            if options.external_nodes.is_some() && kind != NodeKind::Lnd {
-                unimplemented!("can only support LND nodes at the moment for remote nodes");
-            }
             let image_name = inner.next().expect("image name").as_str();
-            let tag_or_path = inner.next().expect("image version").as_str();
-            handle_image_command(options, kind, image_name, tag_or_path)?;
+            let version_or_build = inner.next().expect("image version");
+            if version_or_build.as_rule() == Rule::image_build {
+                handle_image_build_command(options, kind, image_name, version_or_build)?;
+            } else {
+                handle_image_command(options, kind, image_name, version_or_build.as_str())?;
+            }
         }
         Rule::node_def => {
             let kind: NodeKind = inner
@@ -326,15 +417,17 @@ fn handle_conf(options: &mut Options, line: Pair<Rule>) -> Result<()> {
                 .expect("node")
                 .try_into()
                 .expect("invalid node kind");
-            if options.external_nodes.is_some() && kind != NodeKind::Lnd {
-                unimplemented!("can only support LND nodes at the moment for remote nodes");
-            }
             let node_name = inner.next().expect("node name").as_str();
-            let image: ImageInfo = match inner.next() {
-                Some(image) => get_image(options, kind.clone(), image.as_str()),
+            let image: ImageInfo = match inner.peek() {
+                Some(image) if image.as_rule() == Rule::image_name => {
+                    let image_name = inner.next().expect("image name").as_str();
+                    get_image(options, kind.clone(), image_name)
+                }
                 _ => options.get_default_image(kind.clone()),
             };
-            handle_build_command(options, node_name, kind, &image, None)?;
+            let mut details = BuildDetails::default();
+            process_node_settings(&mut details, inner.next())?;
+            handle_build_command(options, node_name, kind, &image, Some(details))?;
         }
         Rule::node_pair => {
             let kind: LnNodeKind = inner
@@ -342,9 +435,6 @@ fn handle_conf(options: &mut Options, line: Pair<Rule>) -> Result<()> {
                 .expect("ln node kind")
                 .try_into()
                 .expect("invalid ln node kind");
-            if options.external_nodes.is_some() && kind != LnNodeKind::Lnd {
-                unimplemented!("can only support LND nodes at the moment for remote nodes");
-            }
             let name = inner.next().expect("ident").as_str();
             let image = match inner.peek().unwrap().as_rule() {
                 Rule::image_name => {
@@ -354,22 +444,20 @@ fn handle_conf(options: &mut Options, line: Pair<Rule>) -> Result<()> {
                 _ => options.get_default_image(kind.clone().into()),
             };
             let to_pair = inner.next().expect("invalid layer 1 node name").as_str();
-            let amount = match inner.peek().is_some() {
-                true => inner
+            let amount = match inner.peek() {
+                Some(amount) if amount.as_rule() == Rule::num => inner
                     .next()
                     .expect("invalid amount")
                     .as_str()
                     .parse()
                     .unwrap(),
-                false => 100000000,
+                _ => 100000000,
             };
-            handle_build_command(
-                options,
-                name,
-                kind.into(),
-                &image,
-                BuildDetails::new_pair(to_pair.to_owned(), amount),
-            )?;
+            let mut details = BuildDetails::new_pair(to_pair.to_owned(), amount);
+            if let Some(details) = details.as_mut() {
+                process_node_settings(details, inner.next())?;
+            }
+            handle_build_command(options, name, kind.into(), &image, details)?;
         }
         Rule::tool_def => {
             let tool_type: SupportedTool = inner
@@ -396,6 +484,9 @@ fn handle_conf(options: &mut Options, line: Pair<Rule>) -> Result<()> {
 pub struct BuildDetails {
     pub pair: Option<NodePair>,
     pub miner_time: Option<MinerTime>,
+    // `key=value` lines applied on top of the node's config template
+    pub conf_overrides: Vec<String>,
+    pub service_settings: ServiceSettings,
 }
 
 #[derive(Debug, Default)]
@@ -411,15 +502,84 @@ impl BuildDetails {
                 name: pair,
                 wallet_starting_balance: amount,
             }),
-            miner_time: None,
+            ..Default::default()
         })
     }
     pub fn new_miner_time(miner_time: MinerTime) -> Option<BuildDetails> {
         Some(BuildDetails {
-            pair: None,
             miner_time: Some(miner_time),
+            ..Default::default()
         })
     }
+    pub fn get_pair(&self) -> Result<&NodePair, Error> {
+        self.pair.as_ref().ok_or(anyhow!(
+            "layer 2 nodes need to be paired with a bitcoind node"
+        ))
+    }
+}
+
+fn process_node_settings(details: &mut BuildDetails, line: Option<Pair<Rule>>) -> Result<()> {
+    let node_settings = match line {
+        Some(pair) if pair.as_rule() == Rule::node_settings => pair,
+        _ => return Ok(()),
+    };
+    for setting in node_settings.into_inner() {
+        match setting.as_rule() {
+            Rule::conf_overrides => {
+                details
+                    .conf_overrides
+                    .extend(process_quoted_values(setting));
+            }
+            Rule::node_cpus => {
+                let cpus = setting.into_inner().as_str().to_owned();
+                details.service_settings.cpus = Some(cpus);
+            }
+            Rule::node_memory => {
+                let memory = setting.into_inner().as_str().to_lowercase();
+                details.service_settings.memory = Some(memory);
+            }
+            Rule::node_env => {
+                for env_var in process_quoted_values(setting) {
+                    if !env_var.contains('=') {
+                        return Err(anyhow!("invalid ENV value {}, expected KEY=value", env_var));
+                    }
+                    details.service_settings.environment.push(env_var);
+                }
+            }
+            Rule::miner_schedule => {
+                let mut schedule = setting.into_inner();
+                let amt: u64 = schedule.next().expect("interval").as_str().parse()?;
+                let time_type = schedule
+                    .next()
+                    .and_then(|time_type| time_type.as_str().chars().next())
+                    .expect("interval unit");
+                if amt == 0 {
+                    return Err(anyhow!("miner interval needs to be more than 0"));
+                }
+                let poisson = schedule.next().is_some();
+                details.miner_time = Some(MinerTime::new(amt, time_type, poisson));
+            }
+            Rule::node_volumes => {
+                for volume in process_quoted_values(setting) {
+                    if !volume.contains(':') {
+                        return Err(anyhow!(
+                            "invalid VOLUME value {}, expected host_path:container_path",
+                            volume
+                        ));
+                    }
+                    details.service_settings.volumes.push(volume);
+                }
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+fn process_quoted_values(line: Pair<Rule>) -> Vec<String> {
+    line.into_inner()
+        .map(|quoted| quoted.into_inner().as_str().to_owned())
+        .collect()
 }
 
 fn handle_image_command(
@@ -440,6 +600,35 @@ fn handle_image_command(
     Ok(())
 }
 
+fn handle_image_build_command(
+    option: &mut Options,
+    kind: NodeKind,
+    name: &str,
+    line: Pair<Rule>,
+) -> Result<()> {
+    let mut inner = line.into_inner();
+    let context = inner.next().expect("build path").as_str();
+    let context_path = get_absolute_path(context)?;
+    if !context_path.is_dir() {
+        return Err(anyhow!(
+            "build path {} for image {} is not a directory",
+            context_path.display(),
+            name
+        ));
+    }
+    let dockerfile = inner
+        .next()
+        .map(|dockerfile| dockerfile.as_str().to_owned());
+    let build = ImageBuild {
+        context: context_path.display().to_string(),
+        dockerfile,
+    };
+    option
+        .images
+        .push(ImageInfo::new_build(name.to_owned(), build, kind));
+    Ok(())
+}
+
 fn handle_build_command(
     options: &mut Options,
     name: &str,
@@ -447,12 +636,13 @@ fn handle_build_command(
     image: &ImageInfo,
     details: Option<BuildDetails>,
 ) -> Result<()> {
+    let details = details.unwrap_or_default();
     match kind {
-        NodeKind::Bitcoind => build_bitcoind(options, name, image, false),
-        NodeKind::BitcoindMiner => build_bitcoind(options, name, image, true),
-        NodeKind::Lnd => build_lnd(options, name, image, &details.unwrap().pair.unwrap()),
-        NodeKind::Eclair => build_eclair(options, name, image, &details.unwrap().pair.unwrap()),
-        NodeKind::Coreln => build_cln(options, name, image, &details.unwrap().pair.unwrap()),
+        NodeKind::Bitcoind => build_bitcoind(options, name, image, false, &details),
+        NodeKind::BitcoindMiner => build_bitcoind(options, name, image, true, &details),
+        NodeKind::Lnd => build_lnd(options, name, image, &details),
+        NodeKind::Eclair => build_eclair(options, name, image, &details),
+        NodeKind::Coreln => build_cln(options, name, image, &details),
     }
 }
 
@@ -488,17 +678,22 @@ fn handle_up(options: &mut Options) -> Result<(), Error> {
 
 fn handle_ln_action(options: &mut Options, line: Pair<Rule>) -> Result<()> {
     let command = process_ln_action(line);
+    run_ln_action(options, &command)
+}
+
+// shared by the top level and LOOPs, so loop traffic is held to the budget too
+fn run_ln_action(options: &Options, command: &NodeCommand) -> Result<()> {
     match command.name.as_str() {
-        "OPEN_CHANNEL" => open_channel(options, &command),
-        "SEND_LN" => send_ln(options, &command),
-        "SEND_ON_CHAIN" => send_on_chain(options, &command),
-        "CLOSE_CHANNEL" => close_channel(options, &command),
-        "FORCE_CLOSE_CHANNEL" => force_close_channel(options, &command),
-        "STOP_LN" => stop_l2_node(options, &command),
-        "START_LN" => start_l2_node(options, &command),
-        "SEND_HOLD_LN" => send_hold_invoice(options, &command),
-        "SETTLE_HOLD_LN" => settle_hold_invoice(options, &command),
-        "WAIT" => wait_number_of_blocks(options, &command),
+        "OPEN_CHANNEL" => spend_from_budget(options, command, SpendKind::Channel, open_channel),
+        "SEND_LN" => spend_from_budget(options, command, SpendKind::Ln, send_ln),
+        "SEND_ON_CHAIN" => spend_from_budget(options, command, SpendKind::OnChain, send_on_chain),
+        "CLOSE_CHANNEL" => close_channel(options, command),
+        "FORCE_CLOSE_CHANNEL" => force_close_channel(options, command),
+        "STOP_LN" => stop_l2_node(options, command),
+        "START_LN" => start_l2_node(options, command),
+        "SEND_HOLD_LN" => spend_from_budget(options, command, SpendKind::Ln, send_hold_invoice),
+        "SETTLE_HOLD_LN" => settle_hold_invoice(options, command),
+        "WAIT" => wait_number_of_blocks(options, command),
         _ => {
             error!("command not supported yet! {:?}", command.name);
             Ok(())
@@ -506,6 +701,19 @@ fn handle_ln_action(options: &mut Options, line: Pair<Rule>) -> Result<()> {
     }
 }
 
+// every action that moves sats goes through the budget first, an action over budget is skipped
+fn spend_from_budget(
+    options: &Options,
+    command: &NodeCommand,
+    kind: SpendKind,
+    action: fn(&Options, &NodeCommand) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let amt = kind.get_amount(command.amt);
+    options
+        .budget
+        .spend(&command.from, kind, amt, || action(options, command))
+}
+
 fn process_ln_action(line: Pair<Rule>) -> NodeCommand {
     let line_inner = line.into_inner();
     let mut node_command = NodeCommand {
@@ -562,6 +770,9 @@ fn process_ln_action(line: Pair<Rule>) -> NodeCommand {
             Rule::sub_command => {
                 node_command.subcommand = Some(pair.as_str().to_owned());
             }
+            Rule::fee_rate => {
+                node_command.fee_rate = Some(parse_fee_rate(pair));
+            }
             //Ignore any other rules found at this level
             _ => (),
         }
@@ -571,8 +782,8 @@ fn process_ln_action(line: Pair<Rule>) -> NodeCommand {
 
 fn handle_btc_action(options: &Options, line: Pair<Rule>) -> Result<()> {
     let command = process_btc_action(line);
-    match command.name.as_str() {
-        "MINE_BLOCKS" => node_mine_bitcoin(options, command.to.to_owned(), command.amt.unwrap()),
+    let action = match command.name.as_str() {
+        "MINE_BLOCKS" => node_mine_bitcoin(options, &command),
         "STOP_BTC" => stop_l1_node(options, &command),
         "START_BTC" => start_l1_node(options, &command),
         "SEND_COINS" => send_to_l2(options, &command),
@@ -580,9 +791,144 @@ fn handle_btc_action(options: &Options, line: Pair<Rule>) -> Result<()> {
             error!("command not supported yet! {:?}", command.name);
             Ok(())
         }
+    };
+    // a failed bitcoind action is logged, the rest of the script still runs
+    if let Err(e) = action {
+        error!("({}): {}", command.name, e);
+    }
+    Ok(())
+}
+
+fn handle_chain_action(options: &Options, line: Pair<Rule>) -> Result<()> {
+    let command = process_chain_action(line);
+    match command.name.as_str() {
+        "REORG" => reorg_chain(options, &command),
+        "FORK" => fork_chain(options, &command),
+        _ => {
+            error!("command not supported yet! {:?}", command.name);
+            Ok(())
+        }
+    }
+}
+
+fn process_chain_action(line: Pair<Rule>) -> NodeCommand {
+    let action = line.into_inner().next().expect("invalid input");
+    let name = match action.as_rule() {
+        Rule::chain_fork => "FORK",
+        _ => "REORG",
+    };
+    let mut node_command = NodeCommand {
+        name: name.to_owned(),
+        ..Default::default()
+    };
+    for pair in action.into_inner() {
+        match pair.as_rule() {
+            Rule::image_name if node_command.from.is_empty() => {
+                node_command.from = pair.as_str().to_owned()
+            }
+            Rule::image_name => node_command.to = pair.as_str().to_owned(),
+            Rule::reorg_depth => {
+                let depth = pair
+                    .into_inner()
+                    .as_str()
+                    .parse::<i64>()
+                    .expect("invalid num");
+                node_command.amt = Some(depth);
+            }
+            _ => (),
+        }
+    }
+    node_command
+}
+
+fn handle_network_action(options: &Options, line: Pair<Rule>) -> Result<()> {
+    let command = process_network_action(line);
+    match command.name.as_str() {
+        "PARTITION" => partition_nodes(options, &command),
+        "ISOLATE" => isolate_node(options, &command),
+        "HEAL" => heal_node(options, &command),
+        "NETEM" => apply_netem(options, &command),
+        _ => {
+            error!("command not supported yet! {:?}", command.name);
+            Ok(())
+        }
     }
 }
 
+fn process_network_action(line: Pair<Rule>) -> NodeCommand {
+    let action = line.into_inner().next().expect("invalid input");
+    let name = match action.as_rule() {
+        Rule::node_partition => "PARTITION",
+        Rule::node_isolate => "ISOLATE",
+        Rule::node_netem => "NETEM",
+        _ => "HEAL",
+    };
+    let mut node_command = NodeCommand {
+        name: name.to_owned(),
+        ..Default::default()
+    };
+    // tc netem arguments, none means CLEAR
+    let mut netem = vec![];
+    for pair in action.into_inner() {
+        match pair.as_rule() {
+            Rule::image_name if node_command.from.is_empty() => {
+                node_command.from = pair.as_str().to_owned()
+            }
+            Rule::image_name => node_command.to = pair.as_str().to_owned(),
+            Rule::netem_delay => netem.push(format!("delay {}", pair.into_inner().as_str())),
+            // netem takes the jitter as the second value of delay
+            Rule::netem_jitter => netem.push(pair.into_inner().as_str().to_owned()),
+            Rule::netem_loss => netem.push(format!("loss {}", pair.into_inner().as_str())),
+            _ => (),
+        }
+    }
+    if !netem.is_empty() {
+        node_command.subcommand = Some(netem.join(" "));
+    }
+    node_command
+}
+
+fn handle_fee_action(options: &Options, line: Pair<Rule>) -> Result<()> {
+    let command = process_fee_action(line);
+    match command.name.as_str() {
+        "FILL_MEMPOOL" => fill_mempool(options, &command),
+        "RBF" => replace_by_fee(options, &command),
+        "CPFP" => child_pays_for_parent(options, &command),
+        _ => {
+            error!("command not supported yet! {:?}", command.name);
+            Ok(())
+        }
+    }
+}
+
+fn process_fee_action(line: Pair<Rule>) -> NodeCommand {
+    let action = line.into_inner().next().expect("invalid input");
+    let mut node_command = NodeCommand {
+        name: "FILL_MEMPOOL".to_owned(),
+        ..Default::default()
+    };
+    for pair in action.into_inner() {
+        match pair.as_rule() {
+            Rule::image_name => node_command.from = pair.as_str().to_owned(),
+            Rule::fee_bump_kind => node_command.name = pair.as_str().to_owned(),
+            Rule::num => {
+                node_command.amt = Some(pair.as_str().parse::<i64>().expect("invalid num"))
+            }
+            Rule::tag => node_command.tag = Some(pair.into_inner().as_str().to_owned()),
+            Rule::fee_rate => node_command.fee_rate = Some(parse_fee_rate(pair)),
+            _ => (),
+        }
+    }
+    node_command
+}
+
+fn parse_fee_rate(pair: Pair<Rule>) -> u64 {
+    pair.into_inner()
+        .as_str()
+        .parse::<u64>()
+        .expect("invalid fee rate")
+}
+
 fn process_btc_action(line: Pair<Rule>) -> NodeCommand {
     let line_inner = line.into_inner();
     let mut line_inner = line_inner.clone().peekable();
@@ -616,20 +962,169 @@ fn process_btc_action(line: Pair<Rule>) -> NodeCommand {
         }
     } else {
         let number = val.as_str().parse::<i64>().expect("invalid num");
-        let subcommand = line_inner.next().map(|pair| pair.to_string());
-        NodeCommand {
+        let mut node_command = NodeCommand {
             name: command_name.to_owned(),
             from: "".to_owned(),
             to: btc_node.to_owned(),
             amt: Some(number),
-            subcommand,
             ..Default::default()
+        };
+        match line_inner.next() {
+            Some(pair) if pair.as_rule() == Rule::mine_filter => {
+                node_command.mine_filter = Some(process_mine_filter(pair))
+            }
+            Some(pair) if pair.as_rule() == Rule::mine_target => {
+                node_command.coinbase_to = Some(pair.into_inner().as_str().to_owned())
+            }
+            pair => node_command.subcommand = pair.map(|pair| pair.to_string()),
         }
+        node_command
     }
 }
 
+fn process_mine_filter(pair: Pair<Rule>) -> MineFilter {
+    let mut inner = pair.into_inner();
+    let kind = inner.next().expect("invalid mine filter").as_str();
+    let tags = inner
+        .map(|tag| tag.into_inner().as_str().to_owned())
+        .collect();
+    match kind {
+        "ONLY" => MineFilter::Only(tags),
+        _ => MineFilter::Exclude(tags),
+    }
+}
+
+fn handle_upgrade(options: &mut Options, line: Pair<Rule>) -> Result<()> {
+    let mut inner = line.into_inner();
+    let node_name = inner.next().expect("node name").as_str();
+    let image_name = inner.next().expect("image name").as_str();
+    if options.is_external_only() || options.is_external_node(node_name) {
+        return Err(anyhow!(
+            "UPGRADE can only be used in a local docker compose network"
+        ));
+    }
+    let image = options.get_image(image_name).ok_or(anyhow!(
+        "image {} not found, add it with `<NODE_KIND> IMAGE {} <tag>` before UP",
+        image_name,
+        image_name
+    ))?;
+
+    if let Ok(ln_node) = options.get_l2_by_name(node_name) {
+        let kind = if options
+            .lnd_nodes
+            .iter()
+            .any(|node| node.get_name() == node_name)
+        {
+            NodeKind::Lnd
+        } else if options
+            .cln_nodes
+            .iter()
+            .any(|node| node.get_name() == node_name)
+        {
+            NodeKind::Coreln
+        } else {
+            NodeKind::Eclair
+        };
+        if image.get_node_kind() != kind {
+            return Err(anyhow!(
+                "image {} is a {:?} image and can't be used for {}",
+                image_name,
+                image.get_node_kind(),
+                node_name
+            ));
+        }
+        let previous_pubkey = ln_node.get_cached_pubkey();
+        upgrade_container(options, ln_node.get_container_name(), &image)?;
+        let pubkey = wait_for_l2_node(options, ln_node.as_ref())?;
+        if !previous_pubkey.is_empty() && pubkey != previous_pubkey {
+            return Err(anyhow!(
+                "{} came back with a different pubkey {}, expected {}",
+                node_name,
+                pubkey,
+                previous_pubkey
+            ));
+        }
+        options.refresh_l2_node(node_name)?;
+    } else {
+        let bitcoind = options
+            .bitcoinds
+            .iter()
+            .find(|node| node.get_name() == node_name)
+            .cloned()
+            .ok_or(anyhow!("node {} not found", node_name))?;
+        if !matches!(
+            image.get_node_kind(),
+            NodeKind::Bitcoind | NodeKind::BitcoindMiner
+        ) {
+            return Err(anyhow!(
+                "image {} is a {:?} image and can't be used for {}",
+                image_name,
+                image.get_node_kind(),
+                node_name
+            ));
+        }
+        upgrade_container(options, &bitcoind.get_container_name(), &image)?;
+        wait_for_l1_node(options, &bitcoind)?;
+    }
+    info!("{} is running on image {}", node_name, image.get_image());
+    Ok(())
+}
+
+fn wait_for_l2_node(options: &Options, node: &dyn L2Node) -> Result<String> {
+    let mut retries = UPGRADE_READY_RETRIES;
+    loop {
+        match node.get_node_pubkey(options) {
+            Ok(pubkey) if !pubkey.is_empty() => return Ok(pubkey),
+            Ok(_) => debug!("{} has no pubkey yet", node.get_name()),
+            Err(e) => debug!("{} not ready yet: {}", node.get_name(), e),
+        }
+        retries -= 1;
+        if retries == 0 {
+            return Err(anyhow!(
+                "{} did not become ready after the upgrade",
+                node.get_name()
+            ));
+        }
+        thread::sleep(Duration::from_secs(2));
+    }
+}
+
+fn wait_for_l1_node(options: &Options, node: &Bitcoind) -> Result<()> {
+    let mut retries = UPGRADE_READY_RETRIES;
+    // a wallet is only loadable once the rpc server has finished warming up
+    while node.create_address(options).is_err() {
+        retries -= 1;
+        if retries == 0 {
+            return Err(anyhow!(
+                "{} did not become ready after the upgrade",
+                node.get_name()
+            ));
+        }
+        thread::sleep(Duration::from_secs(2));
+        let _ = node.load_wallet(options);
+    }
+    Ok(())
+}
+
+// the services are built before the NETEM lines are reached
+fn uses_netem(parsed: &Pair<'_, Rule>) -> bool {
+    parsed
+        .clone()
+        .into_inner()
+        .flatten()
+        .any(|pair| pair.as_rule() == Rule::node_netem)
+}
+
 fn handle_skip_conf(options: &mut Options) -> Result<(), Error> {
-    if let Some(external_nodes_path) = options.external_nodes_path.clone() {
+    if options.hybrid {
+        load_options_from_compose(options, COMPOSE_PATH)?;
+        let external_nodes_path = options.external_nodes_path.clone().unwrap();
+        add_external_nodes_to_cluster(options, &external_nodes_path)?;
+        create_ui_config_files(options, options.network.as_str())?;
+        info!(
+            "doppler cluster and external nodes have been found and loaded, continuing with script"
+        );
+    } else if let Some(external_nodes_path) = options.external_nodes_path.clone() {
         //TODO: add reading from external nodes config and build nodes from there
         load_options_from_external_nodes(options, &external_nodes_path)?;
         info!("external nodes have been found and loaded, continuing with script");
@@ -637,42 +1132,55 @@ fn handle_skip_conf(options: &mut Options) -> Result<(), Error> {
         load_options_from_compose(options, COMPOSE_PATH)?;
         info!("doppler cluster has been found and loaded, continuing with script");
     }
+    if !options.is_external_only() {
+        start_signet_miner(options)?;
+    }
     Ok(())
 }
 
-fn node_mine_bitcoin(options: &Options, miner_name: String, amt: i64) -> Result<(), Error> {
-    if options.external_nodes.is_some() {
-        unimplemented!("command can only be used in a local docker compose network");
+fn check_has_bitcoind(options: &Options) -> Result<(), Error> {
+    if options.is_external_only() && options.bitcoinds.is_empty() {
+        return Err(anyhow!(
+            "command needs a bitcoind, add one to the external nodes file"
+        ));
+    }
+    Ok(())
+}
+
+fn node_mine_bitcoin(options: &Options, node_command: &NodeCommand) -> Result<(), Error> {
+    check_has_bitcoind(options)?;
+    let bitcoind = options.get_bitcoind_by_name(&node_command.to)?;
+    let amt = node_command.amt.unwrap();
+    if let Some(filter) = node_command.mine_filter.as_ref() {
+        return mine_filtered(options, bitcoind, amt, filter);
+    }
+    if let Some(to) = node_command.coinbase_to.as_ref() {
+        return mine_to_l2(options, bitcoind, amt, to);
     }
-    let bitcoind = options.get_bitcoind_by_name(&miner_name)?;
     let _ = bitcoind.mine_bitcoin(options, amt);
     Ok(())
 }
 
 fn stop_l1_node(options: &Options, node_command: &NodeCommand) -> Result<(), Error> {
-    if options.external_nodes.is_some() {
-        unimplemented!("command can only be used in a local docker compose network");
-    }
+    check_has_bitcoind(options)?;
     let bitcoind = options.get_bitcoind_by_name(&node_command.from)?;
-    let _ = bitcoind.stop(options);
-    Ok(())
+    bitcoind.stop(options)
 }
 
 fn start_l1_node(options: &Options, node_command: &NodeCommand) -> Result<(), Error> {
-    if options.external_nodes.is_some() {
-        unimplemented!("command can only be used in a local docker compose network");
-    }
+    check_has_bitcoind(options)?;
     let bitcoind = options.get_bitcoind_by_name(&node_command.from)?;
-    let _ = bitcoind.start(options);
-    Ok(())
+    bitcoind.start(options)
 }
 fn send_to_l2(options: &Options, node_command: &NodeCommand) -> Result<(), Error> {
-    if options.external_nodes.is_some() {
-        unimplemented!("command can only be used in a local docker compose network");
-    }
+    check_has_bitcoind(options)?;
     let bitcoind = options.get_bitcoind_by_name(&node_command.from)?;
-    let _ = bitcoind.clone().send_to_l2(options, node_command);
-    Ok(())
+    let amt = node_command.amt.unwrap_or(DEFAULT_SEND_COINS_AMT);
+    options
+        .budget
+        .spend(&node_command.from, SpendKind::OnChain, amt, || {
+            bitcoind.clone().send_to_l2(options, node_command)
+        })
 }
 
 fn open_channel(option: &Options, node_command: &NodeCommand) -> Result<(), Error> {
@@ -692,25 +1200,56 @@ fn send_on_chain(option: &Options, node_command: &NodeCommand) -> Result<(), Err
 
 fn close_channel(option: &Options, node_command: &NodeCommand) -> Result<(), Error> {
     let from = option.get_l2_by_name(&node_command.from)?;
-    from.close_channel(option, node_command)
+    let tx_id = from.close_channel(option, node_command)?;
+    save_closing_tag(option, node_command, tx_id)
 }
 
 fn force_close_channel(option: &Options, node_command: &NodeCommand) -> Result<(), Error> {
     let from = option.get_l2_by_name(&node_command.from)?;
-    from.force_close_channel(option, node_command)
+    let tx_id = from.force_close_channel(option, node_command)?;
+    save_closing_tag(option, node_command, tx_id)
+}
+
+fn save_closing_tag(option: &Options, node_command: &NodeCommand, tx_id: String) -> Result<()> {
+    let Some(tag) = node_command.tag.clone() else {
+        return Ok(());
+    };
+    if tx_id.is_empty() {
+        error!(
+            "({}): no closing txid to save as {}",
+            node_command.from, tag
+        );
+        return Ok(());
+    }
+    info!(
+        "({}): closing tx {} saved as {}",
+        node_command.from, tx_id, tag
+    );
+    option.save_tag(&Tag {
+        name: tag,
+        val: tx_id,
+    })
 }
 
 fn stop_l2_node(options: &Options, node_command: &NodeCommand) -> Result<(), Error> {
-    if options.external_nodes.is_some() {
-        unimplemented!("command can only be used in a local docker compose network");
+    if options.is_external_node(&node_command.from) {
+        return Err(anyhow!(
+            "{} is an external node, {} can only be used in a local docker compose network",
+            node_command.from,
+            node_command.name
+        ));
     }
     let ln_node = options.get_l2_by_name(&node_command.from)?;
     ln_node.stop(options)
 }
 
 fn start_l2_node(options: &Options, node_command: &NodeCommand) -> Result<(), Error> {
-    if options.external_nodes.is_some() {
-        unimplemented!("command can only be used in a local docker compose network");
+    if options.is_external_node(&node_command.from) {
+        return Err(anyhow!(
+            "{} is an external node, {} can only be used in a local docker compose network",
+            node_command.from,
+            node_command.name
+        ));
     }
     let ln_node = options.get_l2_by_name(&node_command.from)?;
     ln_node.start(options)
@@ -749,3 +1288,423 @@ fn wait_number_of_blocks(options: &Options, node_command: &NodeCommand) -> Resul
     let num_of_blocks = node_command.amt.unwrap();
     ln_node.wait_for_block(options, num_of_blocks)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ContainerBackend, ExternalNode, LnNodeKind, Lnd, Network, SpendBudget};
+    use docker_compose_types::BuildStep;
+    use serde_json::json;
+    use std::{
+        fs,
+        os::unix::process::ExitStatusExt,
+        process::{ExitStatus, Output},
+        sync::Mutex,
+    };
+
+    // records the lncli subcommands and answers them all with an address and a txid
+    #[derive(Default)]
+    struct RecordingBackend {
+        calls: Mutex<Vec<String>>,
+    }
+
+    impl ContainerBackend for RecordingBackend {
+        fn exec(
+            &self,
+            _options: &Options,
+            command_name: &str,
+            _container: &str,
+            _user: Option<&str>,
+            _cmd: &[&str],
+        ) -> Result<Output, Error> {
+            self.calls.lock().unwrap().push(command_name.to_owned());
+            Ok(Output {
+                status: ExitStatus::from_raw(0),
+                stdout: json!({ "address": "bcrt1qtest", "txid": "txid" })
+                    .to_string()
+                    .into_bytes(),
+                stderr: vec![],
+            })
+        }
+        fn stop(&self, _options: &Options, _container: &str) -> Result<Output, Error> {
+            unreachable!()
+        }
+        fn start(&self, _options: &Options, _container: &str) -> Result<Output, Error> {
+            unreachable!()
+        }
+        fn kill(&self, _options: &Options, _container: &str) -> Result<Output, Error> {
+            unreachable!()
+        }
+    }
+
+    fn run_line(node_budget: i64, line: &str) -> Vec<String> {
+        let mut options = Options::for_tests(Network::Regtest);
+        let backend = Arc::new(RecordingBackend::default());
+        options.backend = backend.clone();
+        options.budget = SpendBudget::new(None, Some(node_budget));
+        options.lnd_nodes = ["lnd1", "lnd2"]
+            .iter()
+            .map(|name| Lnd {
+                name: name.to_string(),
+                container_name: format!("doppler-lnd-{}", name),
+                ..Default::default()
+            })
+            .collect();
+        let action = DopplerParser::parse(Rule::ln_node_action, line)
+            .unwrap()
+            .next()
+            .unwrap();
+        handle_ln_action(&mut options, action).unwrap();
+        let calls = backend.calls.lock().unwrap().clone();
+        calls
+    }
+
+    #[test]
+    fn spends_within_the_budget() {
+        let calls = run_line(10_000, "lnd1 SEND_ON_CHAIN lnd2 AMT 5000");
+        assert_eq!(calls, vec!["newaddress", "sendcoins"]);
+    }
+
+    #[test]
+    fn skips_a_spend_over_the_budget() {
+        let calls = run_line(1_000, "lnd1 SEND_ON_CHAIN lnd2 AMT 5000");
+        assert!(calls.is_empty(), "ran {:?}", calls);
+    }
+
+    fn run_conf(options: &mut Options, line: &str) -> Result<()> {
+        let conf = DopplerParser::parse(Rule::conf, line)
+            .unwrap()
+            .next()
+            .unwrap();
+        handle_conf(options, conf)
+    }
+
+    #[test]
+    fn builds_images_from_a_local_path() {
+        let mut options = Options::for_tests(Network::Regtest);
+        let context = std::env::temp_dir();
+        run_conf(
+            &mut options,
+            &format!(
+                "LND IMAGE mylnd BUILD {} DOCKERFILE Dockerfile.dev",
+                context.display()
+            ),
+        )
+        .unwrap();
+
+        let image = options.get_image("mylnd").unwrap();
+        assert!(image.is_build());
+        assert_eq!(image.get_image(), "doppler/mylnd");
+        match image.get_build_step() {
+            Some(BuildStep::Advanced(step)) => {
+                assert_eq!(step.context, context.display().to_string());
+                assert_eq!(step.dockerfile.as_deref(), Some("Dockerfile.dev"));
+            }
+            other => panic!("unexpected build step {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_a_build_path_that_is_not_a_directory() {
+        let mut options = Options::for_tests(Network::Regtest);
+        let missing = std::env::temp_dir().join("doppler-missing-build-context");
+        let result = run_conf(
+            &mut options,
+            &format!("LND IMAGE mylnd BUILD {}", missing.display()),
+        );
+        assert!(result.is_err());
+        assert!(options.get_image("mylnd").is_none());
+    }
+
+    #[test]
+    fn checks_the_upgrade_image_before_touching_the_node() {
+        let mut options = Options::for_tests(Network::Regtest);
+        options.lnd_nodes = vec![Lnd {
+            name: "lnd1".to_owned(),
+            container_name: "doppler-lnd-lnd1".to_owned(),
+            ..Default::default()
+        }];
+        run_conf(&mut options, "BITCOIND IMAGE newbd 27.0").unwrap();
+        let upgrade = |options: &mut Options, line: &str| {
+            let pair = DopplerParser::parse(Rule::node_upgrade, line)
+                .unwrap()
+                .next()
+                .unwrap();
+            handle_upgrade(options, pair).unwrap_err().to_string()
+        };
+
+        let error = upgrade(&mut options, "UPGRADE lnd1 IMAGE missing");
+        assert!(error.starts_with("image missing not found"), "{}", error);
+        let error = upgrade(&mut options, "UPGRADE lnd1 IMAGE newbd");
+        assert!(error.contains("can't be used for lnd1"), "{}", error);
+        let error = upgrade(&mut options, "UPGRADE bd9 IMAGE newbd");
+        assert_eq!(error, "node bd9 not found");
+    }
+
+    fn node_settings(line: &str) -> Result<BuildDetails> {
+        let settings = DopplerParser::parse(Rule::node_settings, line)
+            .unwrap()
+            .next();
+        let mut details = BuildDetails::default();
+        process_node_settings(&mut details, settings)?;
+        Ok(details)
+    }
+
+    #[test]
+    fn parses_miner_schedules() {
+        let miner_time = node_settings("EVERY 30s").unwrap().miner_time.unwrap();
+        assert_eq!(miner_time.get_interval(), Duration::from_secs(30));
+        assert!(!miner_time.poisson);
+        assert_eq!(miner_time.next_wait(), Duration::from_secs(30));
+
+        let miner_time = node_settings("EVERY 2m POISSON")
+            .unwrap()
+            .miner_time
+            .unwrap();
+        assert_eq!(miner_time.get_interval(), Duration::from_secs(120));
+        assert!(miner_time.poisson);
+
+        let miner_time = node_settings("EVERY 1h").unwrap().miner_time.unwrap();
+        assert_eq!(miner_time.get_interval(), Duration::from_secs(3600));
+
+        assert!(node_settings("EVERY 0s").is_err());
+    }
+
+    #[test]
+    fn poisson_waits_average_out_to_the_interval() {
+        let miner_time = MinerTime::new(60, 's', true);
+        let waits: Vec<Duration> = (0..2000).map(|_| miner_time.next_wait()).collect();
+        let mean = waits.iter().sum::<Duration>() / waits.len() as u32;
+        // the standard error of the mean is 60s / sqrt(2000), about 1.3s
+        assert!(
+            mean > Duration::from_secs(50) && mean < Duration::from_secs(70),
+            "{:?}",
+            mean
+        );
+        assert!(waits.iter().any(|wait| *wait != waits[0]));
+    }
+
+    #[test]
+    fn parses_chain_actions() {
+        let chain_action = |line: &str| {
+            process_chain_action(
+                DopplerParser::parse(Rule::chain_action, line)
+                    .unwrap()
+                    .next()
+                    .unwrap(),
+            )
+        };
+        let reorg = chain_action("REORG bd1 DEPTH 3");
+        assert_eq!(
+            (reorg.name.as_str(), reorg.from.as_str(), reorg.amt),
+            ("REORG", "bd1", Some(3))
+        );
+        assert_eq!(chain_action("REORG bd1").amt, None);
+        let fork = chain_action("FORK bd1 bd2 DEPTH 2");
+        assert_eq!(
+            (
+                fork.name.as_str(),
+                fork.from.as_str(),
+                fork.to.as_str(),
+                fork.amt
+            ),
+            ("FORK", "bd1", "bd2", Some(2))
+        );
+    }
+
+    fn network_action(line: &str) -> NodeCommand {
+        process_network_action(
+            DopplerParser::parse(Rule::network_action, line)
+                .unwrap()
+                .next()
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn parses_partition_actions() {
+        let partition = network_action("PARTITION lnd1 FROM bd1");
+        assert_eq!(
+            (
+                partition.name.as_str(),
+                partition.from.as_str(),
+                partition.to.as_str()
+            ),
+            ("PARTITION", "lnd1", "bd1")
+        );
+        let isolate = network_action("ISOLATE bd2");
+        assert_eq!(
+            (isolate.name.as_str(), isolate.from.as_str()),
+            ("ISOLATE", "bd2")
+        );
+        let heal = network_action("HEAL bd2");
+        assert_eq!((heal.name.as_str(), heal.from.as_str()), ("HEAL", "bd2"));
+    }
+
+    #[test]
+    fn parses_netem_actions() {
+        let netem = network_action("NETEM lnd2 DELAY 200ms JITTER 50ms LOSS 2%");
+        assert_eq!(
+            (netem.name.as_str(), netem.from.as_str()),
+            ("NETEM", "lnd2")
+        );
+        assert_eq!(
+            netem.subcommand.as_deref(),
+            Some("delay 200ms 50ms loss 2%")
+        );
+        assert_eq!(
+            network_action("NETEM lnd2 DELAY 1s").subcommand.as_deref(),
+            Some("delay 1s")
+        );
+        assert_eq!(
+            network_action("NETEM lnd2 LOSS 10%").subcommand.as_deref(),
+            Some("loss 10%")
+        );
+        assert_eq!(network_action("NETEM lnd2 CLEAR").subcommand, None);
+        // jitter only makes sense on top of a delay
+        assert!(DopplerParser::parse(Rule::network_action, "NETEM lnd2 JITTER 50ms").is_err());
+    }
+
+    #[test]
+    fn finds_netem_anywhere_in_the_script() {
+        let page = |file: &str| {
+            let path = format!(
+                "{}/../examples/doppler_files/network_faults/{}",
+                env!("CARGO_MANIFEST_DIR"),
+                file
+            );
+            fs::read_to_string(path).unwrap()
+        };
+        let uses = |contents: &str| {
+            uses_netem(
+                &DopplerParser::parse(Rule::page, contents)
+                    .unwrap()
+                    .next()
+                    .unwrap(),
+            )
+        };
+        assert!(uses(&page("latency.doppler")));
+        assert!(!uses(&page("partition.doppler")));
+        assert!(uses(
+            "BITCOIND_MINER bd1\nLND lnd1 PAIR bd1\nUP\nLOOP 2\nNETEM lnd1 LOSS 5%\nEND\n"
+        ));
+    }
+
+    fn fee_action(line: &str) -> NodeCommand {
+        process_fee_action(
+            DopplerParser::parse(Rule::fee_action, line)
+                .unwrap()
+                .next()
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn parses_fee_actions() {
+        let fill = fee_action("bd1 FILL_MEMPOOL 20 FEERATE 50 TAG filler");
+        assert_eq!(
+            (fill.name.as_str(), fill.from.as_str(), fill.amt),
+            ("FILL_MEMPOOL", "bd1", Some(20))
+        );
+        assert_eq!(fill.fee_rate, Some(50));
+        assert_eq!(fill.tag.as_deref(), Some("filler"));
+        assert_eq!(fee_action("bd1 FILL_MEMPOOL 5 FEERATE 2").tag, None);
+
+        let rbf = fee_action("bd1 RBF TAG filler FEERATE 100");
+        assert_eq!((rbf.name.as_str(), rbf.fee_rate), ("RBF", Some(100)));
+        assert_eq!(rbf.tag.as_deref(), Some("filler"));
+        let cpfp = fee_action("bd1 CPFP TAG lnd_payout FEERATE 80");
+        assert_eq!((cpfp.name.as_str(), cpfp.fee_rate), ("CPFP", Some(80)));
+        // a bump without a rate has nothing to bump to
+        assert!(DopplerParser::parse(Rule::fee_action, "bd1 RBF TAG filler").is_err());
+    }
+
+    #[test]
+    fn parses_on_chain_fee_rates() {
+        let parse = |line: &str| {
+            process_ln_action(
+                DopplerParser::parse(Rule::ln_node_action, line)
+                    .unwrap()
+                    .next()
+                    .unwrap(),
+            )
+        };
+        let send = parse("lnd1 SEND_ON_CHAIN bd1 AMT 50000 FEERATE 5 TAG lnd_payout");
+        assert_eq!((send.amt, send.fee_rate), (Some(50000), Some(5)));
+        assert_eq!(send.tag.as_deref(), Some("lnd_payout"));
+        assert_eq!(parse("cln1 SEND_ON_CHAIN lnd1 AMT 50000").fee_rate, None);
+    }
+
+    fn btc_action(line: &str) -> NodeCommand {
+        process_btc_action(
+            DopplerParser::parse(Rule::btc_node_action, line)
+                .unwrap()
+                .next()
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn parses_mine_filters() {
+        let exclude = btc_action("bd1 MINE_BLOCKS 3 EXCLUDE TAG stuck TAG filler");
+        assert_eq!((exclude.to.as_str(), exclude.amt), ("bd1", Some(3)));
+        assert_eq!(
+            exclude.mine_filter,
+            Some(MineFilter::Exclude(vec![
+                "stuck".to_owned(),
+                "filler".to_owned()
+            ]))
+        );
+        let only = btc_action("bd1 MINE_BLOCKS 1 ONLY TAG filler");
+        assert_eq!(
+            only.mine_filter,
+            Some(MineFilter::Only(vec!["filler".to_owned()]))
+        );
+        assert_eq!(btc_action("bd1 MINE_BLOCKS 6").mine_filter, None);
+    }
+
+    #[test]
+    fn parses_mine_targets() {
+        let mine_to = btc_action("bd1 MINE_BLOCKS 101 TO lnd1");
+        assert_eq!(
+            (
+                mine_to.to.as_str(),
+                mine_to.amt,
+                mine_to.coinbase_to.as_deref()
+            ),
+            ("bd1", Some(101), Some("lnd1"))
+        );
+        assert_eq!(mine_to.mine_filter, None);
+        assert_eq!(btc_action("bd1 MINE_BLOCKS 6").coinbase_to, None);
+    }
+
+    #[test]
+    fn refuses_to_stop_or_start_external_nodes() {
+        let mut options = Options::for_tests(Network::Regtest);
+        // stop and start on the backend are unreachable, the check has to come first
+        options.backend = Arc::new(RecordingBackend::default());
+        options.external_nodes = Some(vec![ExternalNode {
+            node_alias: "remote".to_owned(),
+            kind: LnNodeKind::Lnd,
+            macaroon_path: String::new(),
+            api_endpoint: String::new(),
+            tls_cert_path: String::new(),
+            network: "regtest".to_owned(),
+            p2p_address: String::new(),
+            rune: String::new(),
+            api_password: String::new(),
+        }]);
+        let stop = NodeCommand {
+            name: "STOP_LN".to_owned(),
+            from: "remote".to_owned(),
+            ..Default::default()
+        };
+        let error = stop_l2_node(&options, &stop).unwrap_err();
+        assert!(error.to_string().contains("remote is an external node"));
+        let start = NodeCommand {
+            name: "START_LN".to_owned(),
+            ..stop
+        };
+        assert!(start_l2_node(&options, &start).is_err());
+    }
+}